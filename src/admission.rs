@@ -0,0 +1,187 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// Decides whether a new entry is worth evicting another one for. Keys are identified by their
+/// hash, which is all a policy needs to keep track of their access frequency.
+///
+/// Accesses are recorded while only holding the cache's read lock, so implementations need to
+/// rely on interior mutability.
+pub trait AdmissionPolicy {
+  /// Records an access to the key with the given `hash`, whether it was a hit or not.
+  fn record(&self, hash: u64);
+  /// Whether the `candidate` should be admitted into the cache, at the cost of evicting `victim`.
+  fn admit(&self, candidate: u64, victim: u64) -> bool;
+}
+
+const DEPTH: usize = 4;
+const SEEDS: [u64; DEPTH] = [
+  0x97cb_3127_ea12_c1b5,
+  0xc3a5_c85c_97cb_3127,
+  0xb492_b66f_be98_f273,
+  0x9ae1_6a3b_2f90_404f,
+];
+const MAX_FREQUENCY: u8 = 15;
+/// The sketch stops widening past this many counters per row, so that its footprint stays bounded
+/// for huge capacities, and sizing it never overflows.
+const MAX_WIDTH: usize = 1 << 22;
+
+/// A TinyLFU admission policy, which only admits a new entry if it has been accessed more
+/// frequently than the entry it would evict. This keeps scans of one-shot keys from flushing
+/// the cache's working set.
+///
+/// Frequencies are estimated using a count-min sketch of 4-bit counters. All counters are halved
+/// every `10 * capacity` recorded accesses by default, see `aging_period`, so that keys that used
+/// to be hot eventually age out.
+///
+/// ```
+/// use cachers::{CacheThrough, TinyLfu};
+///
+/// let cache = CacheThrough::<usize, String>::with_admission_policy(100, TinyLfu::new(100));
+/// ```
+pub struct TinyLfu {
+  counters: Vec<AtomicU8>,
+  mask: usize,
+  additions: AtomicUsize,
+  sample_size: usize,
+}
+
+impl TinyLfu {
+  /// Creates a `TinyLfu` policy sized for a cache of the given `capacity`.
+  pub fn new(capacity: usize) -> TinyLfu {
+    let width = capacity.clamp(1, MAX_WIDTH).next_power_of_two();
+    TinyLfu {
+      counters: (0..width * DEPTH).map(|_| AtomicU8::new(0)).collect(),
+      mask: width - 1,
+      additions: AtomicUsize::new(0),
+      sample_size: capacity.max(1).saturating_mul(10),
+    }
+  }
+
+  /// Halves all counters every `accesses` recorded accesses, rather than every `10 * capacity`.
+  /// The shorter the period, the quicker keys that used to be hot, but no longer are accessed,
+  /// become evictable, at the cost of telling frequencies apart over shorter windows.
+  ///
+  /// ```
+  /// use cachers::{CacheThrough, TinyLfu};
+  ///
+  /// let policy = TinyLfu::new(100).aging_period(250);
+  /// let cache = CacheThrough::<usize, String>::with_admission_policy(100, policy);
+  /// ```
+  pub fn aging_period(mut self, accesses: usize) -> TinyLfu {
+    self.sample_size = accesses.max(1);
+    self
+  }
+
+  fn frequency(&self, hash: u64) -> u8 {
+    (0..DEPTH)
+      .map(|row| self.counters[self.index(row, hash)].load(Ordering::Relaxed))
+      .min()
+      .unwrap_or(0)
+  }
+
+  fn index(&self, row: usize, hash: u64) -> usize {
+    let spread = (hash ^ SEEDS[row]).wrapping_mul(SEEDS[row]);
+    row * (self.mask + 1) + ((spread >> 32) as usize & self.mask)
+  }
+
+  fn age(&self) {
+    for counter in self.counters.iter() {
+      counter.store(counter.load(Ordering::Relaxed) >> 1, Ordering::Relaxed);
+    }
+  }
+}
+
+impl AdmissionPolicy for TinyLfu {
+  fn record(&self, hash: u64) {
+    for row in 0..DEPTH {
+      let _ = self.counters[self.index(row, hash)].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+        if count < MAX_FREQUENCY {
+          Some(count + 1)
+        } else {
+          None
+        }
+      });
+    }
+    if self.additions.fetch_add(1, Ordering::Relaxed) + 1 >= self.sample_size {
+      self.additions.store(0, Ordering::Relaxed);
+      self.age();
+    }
+  }
+
+  fn admit(&self, candidate: u64, victim: u64) -> bool {
+    self.frequency(candidate) > self.frequency(victim)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{AdmissionPolicy, TinyLfu, MAX_WIDTH};
+
+  #[test]
+  fn admits_hotter_candidates() {
+    let policy = TinyLfu::new(16);
+    policy.record(1);
+    policy.record(2);
+    policy.record(2);
+    assert!(policy.admit(2, 1));
+    assert!(!policy.admit(1, 2));
+    assert!(!policy.admit(1, 1));
+  }
+
+  #[test]
+  fn sizes_for_huge_capacities() {
+    let policy = TinyLfu::new(usize::MAX);
+    assert_eq!(policy.mask, MAX_WIDTH - 1);
+    assert_eq!(policy.sample_size, usize::MAX);
+    policy.record(1);
+    assert_eq!(policy.frequency(1), 1);
+  }
+
+  #[test]
+  fn frequencies_age() {
+    let policy = TinyLfu::new(64);
+    for _ in 0..20 {
+      policy.record(1);
+    }
+    assert_eq!(policy.frequency(1), 15);
+    for _ in 20..639 {
+      policy.record(2);
+    }
+    assert_eq!(policy.frequency(1), 15);
+    policy.record(2);
+    assert_eq!(policy.frequency(1), 7);
+  }
+
+  #[test]
+  fn formerly_hot_keys_age_out() {
+    let aging = TinyLfu::new(64).aging_period(16);
+    let never_aging = TinyLfu::new(64);
+    for policy in [&aging, &never_aging].iter() {
+      // phase 1: key 1 is hot
+      for _ in 0..20 {
+        policy.record(1);
+      }
+      assert!(!policy.admit(2, 1));
+      // phase 2: key 2 is, while key 1 no longer gets accessed
+      for _ in 0..64 {
+        policy.record(2);
+      }
+    }
+    assert!(aging.admit(2, 1));
+    assert!(!never_aging.admit(2, 1));
+  }
+}
@@ -0,0 +1,128 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::future::Future;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::asynchronous::CacheThrough;
+
+/// An asynchronous `CacheThrough` split into independently locked shards, each holding a share of
+/// the total capacity, as the `ShardedCache` does for the blocking one. Keys are assigned to a
+/// shard based on their hash, so that tasks only contend on the locks of the same shard.
+///
+/// ```
+/// use cachers::AsyncShardedCache;
+///
+/// # futures::executor::block_on(async {
+/// let cache = AsyncShardedCache::<i32, String>::with_shards(100, 4);
+/// let value = cache.get(42, |key| async move { Some(key.to_string()) }).await;
+/// assert_eq!(*value.unwrap(), "42");
+/// # });
+/// ```
+pub struct AsyncShardedCache<K, V> {
+  shards: Vec<CacheThrough<K, V>>,
+  hasher: RandomState,
+}
+
+impl<K, V> AsyncShardedCache<K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Copy,
+{
+  /// Creates a new `AsyncShardedCache` of the given `capacity`, using four shards per CPU
+  pub fn new(capacity: usize) -> AsyncShardedCache<K, V> {
+    AsyncShardedCache::with_shards(capacity, num_cpus::get() * 4)
+  }
+
+  /// Creates a new `AsyncShardedCache` of the given `capacity`, split across `shards`. There are
+  /// never more shards than `capacity`, so that each shard can hold at least one entry.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `capacity` is zero.
+  pub fn with_shards(capacity: usize, shards: usize) -> AsyncShardedCache<K, V> {
+    let shards = shards.min(capacity).max(1);
+    AsyncShardedCache {
+      shards: (0..shards)
+        .map(|shard| CacheThrough::new(capacity / shards + usize::from(shard < capacity % shards)))
+        .collect(),
+      hasher: RandomState::new(),
+    }
+  }
+
+  /// See `AsyncCacheThrough::get`
+  pub async fn get<Fut, F>(&self, key: K, populating_fn: F) -> Option<Arc<V>>
+  where
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = Option<V>>,
+  {
+    self.shard(&key).get(key, populating_fn).await
+  }
+
+  /// See `AsyncCacheThrough::update`
+  pub async fn update<Fut, F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
+  where
+    F: Fn(K, Option<Arc<V>>) -> Fut,
+    Fut: Future<Output = Option<V>>,
+  {
+    self.shard(&key).update(key, updating_fn).await
+  }
+
+  /// See `AsyncCacheThrough::remove`
+  pub async fn remove(&self, key: K) {
+    self.shard(&key).remove(key).await
+  }
+
+  fn shard(&self, key: &K) -> &CacheThrough<K, V> {
+    &self.shards[(self.hasher.hash_one(key) % self.shards.len() as u64) as usize]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::AsyncShardedCache;
+
+  #[test]
+  fn never_more_shards_than_capacity() {
+    let cache: AsyncShardedCache<i32, String> = AsyncShardedCache::with_shards(2, 4);
+    assert_eq!(cache.shards.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn routes_to_shards() {
+    let cache: AsyncShardedCache<i32, String> = AsyncShardedCache::with_shards(100, 4);
+    for key in 0..50 {
+      let value = cache.get(key, |key| async move { Some(key.to_string()) }).await;
+      assert_eq!(*value.unwrap(), key.to_string());
+    }
+    let len = |cache: &AsyncShardedCache<i32, String>| cache.shards.iter().map(|shard| shard.len()).sum::<usize>();
+    assert_eq!(len(&cache), 50);
+
+    for key in 0..50 {
+      let value = cache.get(key, |_| async { unimplemented!() }).await;
+      assert_eq!(*value.unwrap(), key.to_string());
+    }
+
+    let value = cache
+      .update(
+        42,
+        |_, value| async move { Some(value.unwrap().to_string() + " updated!") },
+      )
+      .await;
+    assert_eq!(*value.unwrap(), "42 updated!");
+    cache.remove(42).await;
+    assert_eq!(len(&cache), 49);
+  }
+}
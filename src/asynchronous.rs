@@ -1,59 +1,309 @@
-use futures::future::Future;
+use futures::future::{self, Future, FutureExt};
+use futures::stream::{TryStream, TryStreamExt};
+use std::cell::RefCell;
+use std::convert::Infallible;
 use std::ops::Fn;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use crate::segment2::Segment;
+use crate::segment2::{Population, Segment};
+use crate::softlock::{Failure, WaitError, Waiter};
 
 pub struct CacheThrough<K, V> {
   data: RwLock<Segment<K, V>>,
+  on_error: OnError,
+}
+
+thread_local! {
+  /// The locks held by the futures populating or updating entries being polled on this thread, as
+  /// the address of their segment and the lock id. A future awaiting one of these would wait on
+  /// itself, i.e. forever.
+  static HELD: RefCell<Vec<(usize, u64)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How a failing `populating_fn`'s error gets handed to the tasks waiting on it, and back.
+type Propagation<E> = (fn(&E) -> Failure, fn(&Failure) -> Option<E>);
+
+/// What tasks waiting on a `populating_fn` do, should it fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+  /// Waiting tasks are handed the error the `populating_fn` failed with.
+  Propagate,
+  /// Waiting tasks retry, with their own `populating_fn`, one at a time: the first task to get to
+  /// it invokes its `populating_fn`, while the others wait on it in turn. Should the backend keep
+  /// on failing, it gets invoked once per waiting task, which makes the last one wait for all the
+  /// others to fail first.
+  RetryEach,
 }
 
 impl<K, V> CacheThrough<K, V>
 where
   K: std::cmp::Eq + std::hash::Hash + Copy,
-  V: Clone,
 {
   /// Creates a new `CacheThrough` instance of the given `capacity`
   ///
   /// ```
-  /// use cachers::CacheThrough;
+  /// use cachers::AsyncCacheThrough;
   ///
-  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let cache = AsyncCacheThrough::<usize, String>::new(100);
   /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if `capacity` is zero.
   pub fn new(capacity: usize) -> CacheThrough<K, V> {
     CacheThrough {
       data: RwLock::new(Segment::new(capacity)),
+      on_error: OnError::RetryEach,
     }
   }
 
+  /// Creates a new `CacheThrough` instance of the given `capacity`, whose entries expire
+  /// `time_to_live` after they were last populated or updated. Expired entries are treated as
+  /// absent, i.e. `get` will invoke the `populating_fn` again.
+  ///
+  /// Entries that are being populated or updated don't expire, tasks interested in them keep
+  /// waiting on the future in flight.
+  ///
+  /// ```
+  /// use std::time::Duration;
+  ///
+  /// use cachers::asynchronous::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_secs(60));
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if `capacity` is zero, as `new` does.
+  pub fn with_time_to_live(capacity: usize, time_to_live: Duration) -> CacheThrough<K, V> {
+    let mut segment = Segment::new(capacity);
+    segment.expire_after(time_to_live);
+    CacheThrough {
+      data: RwLock::new(segment),
+      on_error: OnError::RetryEach,
+    }
+  }
+
+  /// Sets what tasks waiting on a failing `populating_fn` do, see
+  /// `get_or_try_populate_propagating`. Defaults to `OnError::RetryEach`.
+  ///
+  /// ```
+  /// use cachers::asynchronous::{CacheThrough, OnError};
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100).on_error(OnError::Propagate);
+  /// ```
+  pub fn on_error(mut self, on_error: OnError) -> CacheThrough<K, V> {
+    self.on_error = on_error;
+    self
+  }
+
   /// Retrieves a shared reference to the `V` for the given `key`.
   /// The `populating_fn` will be invoked to populate the cache, should there be no mapping
   /// for the `key` already present. The `populating_fn` receives the `key` as an argument.
   ///
-  /// It is guaranteed that `populating_fn` will only be invoked once. That is if multiple thread
-  /// race to populate the cache for a given `key`, only one thread will invoke the `populating_fn`.
-  /// Once `populating_fn` has returned `Some<V>`, the other threads waiting for the entry to
-  /// be populated will get the `Arc<V>` returned.
+  /// It is guaranteed that `populating_fn` will only be invoked once. That is if multiple tasks
+  /// race to populate the cache for a given `key`, only one task will invoke the `populating_fn`.
+  /// The other tasks wait for that future to resolve and get handed its value. The cache isn't
+  /// locked while the future executes, so that tasks interested in other keys can proceed.
   ///
-  /// In the case where `populating_fn` yield no results (i.e. returns `Option::None`), no
-  /// guarantees are made about how many times the `populating_fn` may be called.
+  /// In the case where `populating_fn` yield no results (i.e. returns `Option::None`), the tasks
+  /// that were waiting on it will get `None` as well, but no guarantees are made about how many
+  /// times the `populating_fn` may be called afterwards.
   ///
   /// If you want to cache misses, consider wrapping your `V` into an `Option`.
-  pub async fn get<Fut, F>(&self, key: K, populating_fn: F) -> Fut::Output
+  pub async fn get<Fut, F>(&self, key: K, populating_fn: F) -> Option<Arc<V>>
   where
     F: Fn(K) -> Fut,
     Fut: Future<Output = Option<V>>,
+  {
+    let result = self
+      .get_or_try_populate(key, |key| populating_fn(key).map(Ok::<_, Infallible>))
+      .await;
+    match result {
+      Ok(value) => value,
+      Err(never) => match never {},
+    }
+  }
+
+  /// Retrieves a shared reference to the `V` for the given `key`, same as `get` does, but lets the
+  /// `populating_fn` fail. Should it resolve to an `Err`, nothing gets cached and the error is
+  /// returned to the caller.
+  ///
+  /// Errors are not shared with the other tasks that were waiting on that `populating_fn`: they
+  /// each retry, with their own `populating_fn`, regardless of the cache's `OnError`. See
+  /// `get_or_try_populate_propagating` for errors that can be handed to them.
+  pub async fn get_or_try_populate<Fut, F, E>(&self, key: K, populating_fn: F) -> Result<Option<Arc<V>>, E>
+  where
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = Result<Option<V>, E>>,
+  {
+    self.try_populate(key, populating_fn, None).await
+  }
+
+  /// Same as `get_or_try_populate`, but for errors that can be shared: what the other tasks that
+  /// were waiting on a failing `populating_fn` do depends on the cache's `OnError`. They either get
+  /// handed a clone of the error, or each retry with their own `populating_fn`. Waiting tasks
+  /// expecting another error type always retry.
+  ///
+  /// ```
+  /// use cachers::asynchronous::{CacheThrough, OnError};
+  ///
+  /// # futures::executor::block_on(async {
+  /// let cache = CacheThrough::<usize, String>::new(100).on_error(OnError::Propagate);
+  /// let value = cache.get_or_try_populate_propagating(42, |_| async { Err("Boom!") }).await;
+  /// assert_eq!(value, Err("Boom!"));
+  /// # });
+  /// ```
+  pub async fn get_or_try_populate_propagating<Fut, F, E>(&self, key: K, populating_fn: F) -> Result<Option<Arc<V>>, E>
+  where
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = Result<Option<V>, E>>,
+    E: Clone + Send + Sync + 'static,
+  {
+    let propagation: Propagation<E> = (|error| Failure::new(error.clone()), Failure::downcast);
+    let propagation = if self.on_error == OnError::Propagate {
+      Some(propagation)
+    } else {
+      None
+    };
+    self.try_populate(key, populating_fn, propagation).await
+  }
+
+  /// Populates `key` as `get_or_try_populate` does, handing the error of a failing `populating_fn`
+  /// to the tasks waiting on it should there be a `propagation`.
+  async fn try_populate<Fut, F, E>(
+    &self,
+    key: K,
+    populating_fn: F,
+    propagation: Option<Propagation<E>>,
+  ) -> Result<Option<Arc<V>>, E>
+  where
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = Result<Option<V>, E>>,
   {
     if let Some(value) = self.data.read().unwrap().get(&key) {
-      return Some(value);
+      return Ok(Some(value));
     }
 
-    let option = self.data.write();
-    if option.is_ok() {
-      let mut guard = option.unwrap();
-      return guard.get_or_populate(key, populating_fn).await;
+    loop {
+      let population = self.data.write().unwrap().get_or_lock(key);
+      match population {
+        Population::Hit(value) => return Ok(Some(value)),
+        Population::Wait(waiter) => match self.wait_on(waiter).await {
+          Ok(value) => return Ok(value),
+          Err(WaitError::Failed(failure)) => {
+            if let Some(error) = propagation.and_then(|(_, downcast)| downcast(&failure)) {
+              return Err(error);
+            }
+          }
+          Err(WaitError::Abandoned) => (),
+        },
+        Population::Populate(id) => {
+          let guard = LockGuard::new(&self.data, key, id);
+          return match guard.hold(populating_fn(key)).await {
+            Ok(value) => Ok(guard.release(value)),
+            Err(error) => {
+              if let Some((share, _)) = propagation {
+                guard.fail(share(&error));
+              }
+              Err(error)
+            }
+          };
+        }
+      }
     }
-    None
+  }
+
+  /// Same as `get_or_try_populate`, but for a `populating_fn` returning a stream of chunks, e.g. of
+  /// a large value read from object storage, that gets collected into the `V` to cache once it
+  /// ends. The value is only cached, and handed to the tasks waiting on it, once whole.
+  ///
+  /// Should the stream yield an `Err`, the chunks received so far are discarded, nothing gets
+  /// cached, and the error is returned, as a failing `populating_fn` does: the tasks waiting on it
+  /// retry, see `get_streamed_propagating` for them to get handed the error instead.
+  ///
+  /// ```
+  /// use futures::stream;
+  ///
+  /// use cachers::AsyncCacheThrough;
+  ///
+  /// # futures::executor::block_on(async {
+  /// let cache = AsyncCacheThrough::<usize, String>::new(100);
+  /// let chunks = |_| stream::iter(vec![Ok::<_, ()>("4".to_string()), Ok("2".to_string())]);
+  /// let value = cache.get_streamed(42, chunks).await;
+  /// assert_eq!(*value.unwrap().unwrap(), "42");
+  /// # });
+  /// ```
+  pub async fn get_streamed<St, F, C, E>(&self, key: K, populating_fn: F) -> Result<Option<Arc<V>>, E>
+  where
+    F: Fn(K) -> St,
+    St: TryStream<Ok = C, Error = E>,
+    V: Default + Extend<C>,
+  {
+    self
+      .get_or_try_populate(key, |key| {
+        populating_fn(key).try_collect::<V>().map(|value| value.map(Some))
+      })
+      .await
+  }
+
+  /// Same as `get_streamed`, but for errors that can be shared with the tasks waiting on the stream,
+  /// as `get_or_try_populate_propagating` does, depending on the cache's `OnError`.
+  pub async fn get_streamed_propagating<St, F, C, E>(&self, key: K, populating_fn: F) -> Result<Option<Arc<V>>, E>
+  where
+    F: Fn(K) -> St,
+    St: TryStream<Ok = C, Error = E>,
+    V: Default + Extend<C>,
+    E: Clone + Send + Sync + 'static,
+  {
+    self
+      .get_or_try_populate_propagating(key, |key| {
+        populating_fn(key).try_collect::<V>().map(|value| value.map(Some))
+      })
+      .await
+  }
+
+  /// Retrieves a shared reference to the `V` for the given `key`, if the cache already holds one.
+  ///
+  /// Unlike `get`, this never populates the cache nor waits for anything, and only ever acquires
+  /// the read lock: entries that are being populated or updated are reported absent.
+  ///
+  /// ```
+  /// use cachers::AsyncCacheThrough;
+  ///
+  /// let cache = AsyncCacheThrough::<usize, String>::new(100);
+  /// assert_eq!(cache.get_if_present(&42), None);
+  /// ```
+  pub fn get_if_present(&self, key: &K) -> Option<Arc<V>> {
+    self.data.read().unwrap().get(key)
+  }
+
+  /// Retrieves a shared reference to the `V` for the given `key`, if the cache already holds one,
+  /// as `get_if_present` does, but without counting as an access: the entry is no less likely to
+  /// be evicted afterwards.
+  ///
+  /// ```
+  /// use cachers::AsyncCacheThrough;
+  ///
+  /// let cache = AsyncCacheThrough::<usize, String>::new(100);
+  /// assert_eq!(cache.peek(&42), None);
+  /// ```
+  pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+    self.data.read().unwrap().peek(key)
+  }
+
+  /// Whether the cache holds a value for the given `key`. As `peek`, this only acquires the read
+  /// lock, never waits on entries being populated or updated, and doesn't count as an access.
+  ///
+  /// ```
+  /// use cachers::AsyncCacheThrough;
+  ///
+  /// let cache = AsyncCacheThrough::<usize, String>::new(100);
+  /// assert!(!cache.contains_key(&42));
+  /// ```
+  pub fn contains_key(&self, key: &K) -> bool {
+    self.data.read().unwrap().contains_key(key)
   }
 
   /// Updates an entry in the cache, or populates it if absent.
@@ -62,49 +312,166 @@ where
   /// The `updating_fn` receives the `key`, as well as an `Option<Arc<V>>` which holds the previous
   /// value for the `key`, which would be `None` if the function is about to populate the cache.
   ///
-  /// It is guaranteed that the mapping will not be altered by another thread while the
-  /// `populating_fn` executes.
-  pub async fn update<Fut, F>(&self, key: K, updating_fn: F) -> Option<V>
+  /// It is guaranteed that the mapping will not be altered by another task while the
+  /// `updating_fn` executes, tasks interested in that `key` wait for the update to complete.
+  /// Should the future be dropped or panic, the previous mapping is left untouched.
+  ///
+  /// The `updating_fn`'s future must not itself `get` nor `update` the same `key` on this cache,
+  /// as it would then wait on its own update to complete: rather than deadlocking, such a call
+  /// panics. The same goes for a `populating_fn` getting its own `key`. Other keys are fine, as the
+  /// cache isn't locked while the future executes.
+  pub async fn update<Fut, F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
   where
-    F: Fn(K, Option<V>) -> Fut,
+    F: Fn(K, Option<Arc<V>>) -> Fut,
     Fut: Future<Output = Option<V>>,
   {
-    self.data.write().unwrap().update(key, updating_fn).await
+    loop {
+      let lock = self.data.write().unwrap().lock(key);
+      match lock {
+        Ok((id, previous)) => {
+          let guard = LockGuard::new(&self.data, key, id);
+          let value = guard.hold(updating_fn(key, previous)).await;
+          return guard.release(value);
+        }
+        Err(waiter) => {
+          let _ = self.wait_on(waiter).await;
+        }
+      }
+    }
   }
 
   /// Removes the entry for `key` from the cache.
   /// This is the equivalent of `cache.update(key, |_, _| None)`. Consider this a convenience method.
   pub async fn remove(&self, key: K) {
-    self.data.write().unwrap().update(key, |_, _| async { None }).await;
+    self.update(key, |_, _| async { None }).await;
+  }
+
+  /// Waits on the lock held by another task, after making sure that it isn't held by the caller.
+  async fn wait_on(&self, waiter: Waiter<Arc<V>>) -> Result<Option<Arc<V>>, WaitError> {
+    let lock = (address(&self.data), waiter.lock());
+    if HELD.with(|held| held.borrow().contains(&lock)) {
+      panic!("Reentrant call on a key being populated or updated by the caller itself, which would deadlock");
+    }
+    waiter.await
   }
 
   #[cfg(test)]
-  fn len(&self) -> usize {
+  pub(crate) fn len(&self) -> usize {
     self.data.read().unwrap().len()
   }
 }
 
+/// Abandons the lock on `key` when dropped, unless it was released with a value. This makes sure
+/// the entry doesn't stay locked forever, should the future populating it be dropped or panic.
+struct LockGuard<'a, K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Copy,
+{
+  data: &'a RwLock<Segment<K, V>>,
+  key: K,
+  id: u64,
+  released: bool,
+}
+
+impl<'a, K, V> LockGuard<'a, K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Copy,
+{
+  fn new(data: &'a RwLock<Segment<K, V>>, key: K, id: u64) -> LockGuard<'a, K, V> {
+    LockGuard {
+      data,
+      key,
+      id,
+      released: false,
+    }
+  }
+
+  /// Runs the `future` populating or updating the entry, recording the lock as held by the
+  /// current thread while the `future` is being polled.
+  async fn hold<Fut>(&self, future: Fut) -> Fut::Output
+  where
+    Fut: Future,
+  {
+    futures::pin_mut!(future);
+    let lock = (address(self.data), self.id);
+    future::poll_fn(|cx| {
+      HELD.with(|held| held.borrow_mut().push(lock));
+      let _held = Held;
+      future.as_mut().poll(cx)
+    })
+    .await
+  }
+
+  fn release(mut self, value: Option<V>) -> Option<Arc<V>> {
+    self.released = true;
+    self.data.write().unwrap().populated(self.key, self.id, value)
+  }
+
+  fn fail(mut self, failure: Failure) {
+    self.released = true;
+    self.data.write().unwrap().fail(self.key, self.id, failure);
+  }
+}
+
+impl<'a, K, V> Drop for LockGuard<'a, K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Copy,
+{
+  fn drop(&mut self) {
+    if !self.released {
+      if let Ok(mut segment) = self.data.write() {
+        segment.abandon(self.key, self.id);
+      }
+    }
+  }
+}
+
+/// Pops the lock pushed onto `HELD` when dropped, even should polling the future panic.
+struct Held;
+
+impl Drop for Held {
+  fn drop(&mut self) {
+    HELD.with(|held| held.borrow_mut().pop());
+  }
+}
+
+fn address<K, V>(data: &RwLock<Segment<K, V>>) -> usize {
+  data as *const RwLock<Segment<K, V>> as usize
+}
+
 #[cfg(test)]
 mod tests {
-  use super::CacheThrough;
+  use super::{CacheThrough, OnError};
+  use futures::channel::oneshot;
+  use futures::future::FutureExt;
+  use std::panic::AssertUnwindSafe;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::Arc;
+  use std::time::Duration;
 
   fn test_cache() -> CacheThrough<i32, String> {
     CacheThrough::new(3)
   }
 
+  #[test]
+  #[should_panic(expected = "capacity must be greater than zero")]
+  fn new_panics_on_zero_capacity() {
+    CacheThrough::<i32, String>::new(0);
+  }
+
   #[tokio::test]
   async fn hit_populates() {
     let cache: CacheThrough<i32, String> = test_cache();
     let our_key = 42;
     {
       let value = cache.get(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
 
     {
       let value = cache.get(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
   }
@@ -121,7 +488,7 @@ mod tests {
 
     {
       let value = cache.get(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
       cache.get(2, populate).await;
       cache.get(3, populate).await;
@@ -136,13 +503,13 @@ mod tests {
 
     {
       let value = cache.update(our_key, upsert).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
 
     {
       let value = cache.get(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
   }
@@ -154,19 +521,19 @@ mod tests {
 
     {
       let value = cache.get(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
 
     {
       let value = cache.update(our_key, update).await;
-      assert_eq!(value.unwrap(), "42 updated!");
+      assert_eq!(*value.unwrap(), "42 updated!");
       assert_eq!(cache.len(), 1);
     }
 
     {
       let value = cache.get(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42 updated!");
+      assert_eq!(*value.unwrap(), "42 updated!");
       assert_eq!(cache.len(), 1);
     }
   }
@@ -178,7 +545,7 @@ mod tests {
 
     {
       let value = cache.get(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
 
@@ -202,7 +569,7 @@ mod tests {
 
     {
       let value = cache.get(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(cache.len(), 1);
     }
 
@@ -217,11 +584,11 @@ mod tests {
     let cache: CacheThrough<i32, String> = test_cache();
 
     {
-      assert_eq!(cache.get(1, populate).await.unwrap(), "1"); // eviction candidate
+      assert_eq!(*cache.get(1, populate).await.unwrap(), "1"); // eviction candidate
       assert_eq!(cache.len(), 1);
-      assert_eq!(cache.get(2, populate).await.unwrap(), "2");
+      assert_eq!(*cache.get(2, populate).await.unwrap(), "2");
       assert_eq!(cache.len(), 2);
-      assert_eq!(cache.get(3, populate).await.unwrap(), "3");
+      assert_eq!(*cache.get(3, populate).await.unwrap(), "3");
       assert_eq!(cache.len(), 3);
 
       // Clock state & hand:
@@ -230,44 +597,70 @@ mod tests {
     }
 
     {
-      assert_eq!(cache.get(4, populate).await.unwrap(), "4"); // evicts 1
+      assert_eq!(*cache.get(4, populate).await.unwrap(), "4"); // evicts 1
       assert_eq!(cache.len(), 3);
       //  _
       // 100
 
-      assert_eq!(cache.get(2, do_not_invoke).await.unwrap(), "2");
+      assert_eq!(*cache.get(2, do_not_invoke).await.unwrap(), "2");
       assert_eq!(cache.len(), 3);
       //  _
       // 110
 
-      assert_eq!(cache.get(3, do_not_invoke).await.unwrap(), "3");
+      assert_eq!(*cache.get(3, do_not_invoke).await.unwrap(), "3");
       assert_eq!(cache.len(), 3);
       //  _
       // 111
     }
 
     {
-      assert_eq!(cache.get(5, populate).await.unwrap(), "5"); // evicts 3
+      assert_eq!(*cache.get(5, populate).await.unwrap(), "5"); // evicts 2
       assert_eq!(cache.len(), 3);
       //   _
       // 010
 
-      assert_eq!(cache.get(2, do_not_invoke).await.unwrap(), "2"); // 011
+      assert_eq!(*cache.get(3, do_not_invoke).await.unwrap(), "3"); // 011
       assert_eq!(cache.len(), 3);
-      assert_eq!(cache.get(4, do_not_invoke).await.unwrap(), "4"); // 111
+      assert_eq!(*cache.get(4, do_not_invoke).await.unwrap(), "4"); // 111
       assert_eq!(cache.len(), 3);
     }
 
     {
-      assert_eq!(cache.get(6, populate).await.unwrap(), "6"); // evicts 4
+      assert_eq!(*cache.get(6, populate).await.unwrap(), "6"); // full circle, evicts 3
       assert_eq!(cache.len(), 3);
-      assert_eq!(cache.get(5, do_not_invoke).await.unwrap(), "5");
+      assert_eq!(*cache.get(5, do_not_invoke).await.unwrap(), "5");
       assert_eq!(cache.len(), 3);
-      assert_eq!(cache.get(2, do_not_invoke).await.unwrap(), "2");
+      assert_eq!(*cache.get(4, do_not_invoke).await.unwrap(), "4");
       assert_eq!(cache.len(), 3);
     }
   }
 
+  #[tokio::test]
+  async fn entries_expire() {
+    let cache = CacheThrough::with_time_to_live(3, Duration::from_millis(200));
+    let our_key = 42;
+
+    assert_eq!(*cache.get(our_key, populate).await.unwrap(), "42");
+    assert_eq!(*cache.get(our_key, do_not_invoke).await.unwrap(), "42");
+    tokio::time::delay_for(Duration::from_millis(300)).await;
+    let value = cache
+      .get(our_key, |key| async move { Some(format!("{} again", key)) })
+      .await;
+    assert_eq!(*value.unwrap(), "42 again");
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn hits_share_the_value() {
+    struct NotClone(i32);
+    let cache: CacheThrough<i32, NotClone> = CacheThrough::new(3);
+
+    let populated = cache.get(42, |key| async move { Some(NotClone(key)) }).await.unwrap();
+    let hit = cache.get(42, |_| async { None }).await.unwrap();
+    assert_eq!(hit.0, 42);
+    assert!(Arc::ptr_eq(&populated, &hit));
+  }
+
   async fn miss(_key: i32) -> Option<String> {
     None
   }
@@ -276,21 +669,280 @@ mod tests {
     Some(key.to_string())
   }
 
-  async fn upsert(key: i32, value: Option<String>) -> Option<String> {
+  async fn upsert(key: i32, value: Option<Arc<String>>) -> Option<String> {
     assert_eq!(value, None);
     populate(key).await
   }
 
-  async fn update(_key: i32, value: Option<String>) -> Option<String> {
+  async fn update(_key: i32, value: Option<Arc<String>>) -> Option<String> {
     let previous = &value.unwrap();
-    Some(previous.clone() + " updated!")
+    Some(previous.to_string() + " updated!")
   }
 
-  async fn updel(_key: i32, value: Option<String>) -> Option<String> {
+  async fn updel(_key: i32, value: Option<Arc<String>>) -> Option<String> {
     assert!(value.is_some());
     None
   }
 
+  #[tokio::test]
+  async fn populates_once() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let invocations = AtomicUsize::new(0);
+    let populating_fn = |key: i32| {
+      invocations.fetch_add(1, Ordering::SeqCst);
+      async move {
+        tokio::time::delay_for(Duration::from_millis(1)).await;
+        Some(key.to_string())
+      }
+    };
+
+    let (first, second) = futures::join!(cache.get(42, populating_fn), cache.get(42, populating_fn));
+    assert_eq!(*first.unwrap(), "42");
+    assert_eq!(*second.unwrap(), "42");
+    assert_eq!(invocations.load(Ordering::SeqCst), 1);
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn populating_blocks_not_other_keys() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let (sender, receiver) = oneshot::channel::<()>();
+    let receiver = receiver.shared();
+
+    let blocked = cache.get(1, |key| {
+      let receiver = receiver.clone();
+      async move {
+        receiver.await.unwrap();
+        Some(key.to_string())
+      }
+    });
+    let other = async {
+      let value = cache.get(2, populate).await;
+      sender.send(()).unwrap();
+      value
+    };
+
+    let (first, second) = futures::join!(blocked, other);
+    assert_eq!(*first.unwrap(), "1");
+    assert_eq!(*second.unwrap(), "2");
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn get_if_present_never_waits() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    let mut pending = Box::pin(cache.get(our_key, |_| futures::future::pending()));
+    assert!(futures::poll!(pending.as_mut()).is_pending());
+    assert_eq!(cache.get_if_present(&our_key), None);
+    drop(pending);
+
+    cache.get(our_key, populate).await;
+    assert_eq!(*cache.get_if_present(&our_key).unwrap(), "42");
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn peeks_touch_not() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(1, populate).await;
+    cache.get(2, populate).await;
+    cache.get(3, populate).await;
+    cache.get(4, populate).await; // evicts 1
+    assert!(cache.peek(&1).is_none());
+    assert!(!cache.contains_key(&1));
+
+    // unlike gets, neither marks 2 as referenced
+    assert_eq!(*cache.peek(&2).unwrap(), "2");
+    assert!(cache.contains_key(&2));
+    cache.get(5, populate).await; // evicts 2
+    assert!(!cache.contains_key(&2));
+    assert!(cache.contains_key(&3));
+    assert_eq!(cache.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn peeks_never_wait() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    let mut pending = Box::pin(cache.get(our_key, |_| futures::future::pending()));
+    assert!(futures::poll!(pending.as_mut()).is_pending());
+    assert_eq!(cache.peek(&our_key), None);
+    assert!(!cache.contains_key(&our_key));
+  }
+
+  #[tokio::test]
+  async fn dropped_populate_unlocks() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let mut pending = Box::pin(cache.get(our_key, |_| futures::future::pending()));
+      assert!(futures::poll!(pending.as_mut()).is_pending());
+    }
+
+    {
+      let value = cache.get(our_key, populate).await;
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn panicking_update_unlocks() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      cache.get(our_key, populate).await;
+      let result = AssertUnwindSafe(cache.update(our_key, |_, _| async { panic!("Boom!") }))
+        .catch_unwind()
+        .await;
+      assert!(result.is_err());
+    }
+
+    {
+      let value = cache.get(our_key, do_not_invoke).await;
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn reentrant_update_panics() {
+    let cache: &CacheThrough<i32, String> = &test_cache();
+    let our_key = 42;
+
+    {
+      cache.get(our_key, populate).await;
+      let result = AssertUnwindSafe(cache.update(our_key, |key, _| async move {
+        cache.get(key, do_not_invoke).await;
+        None
+      }))
+      .catch_unwind()
+      .await;
+      assert!(result.is_err());
+    }
+
+    {
+      let value = cache.update(our_key, |key, _| async move {
+        cache.get(key + 1, populate).await;
+        Some("updated".to_string())
+      });
+      assert_eq!(*value.await.unwrap(), "updated");
+      assert_eq!(cache.len(), 2);
+    }
+  }
+
+  #[tokio::test]
+  async fn reentrant_populate_panics() {
+    let cache: &CacheThrough<i32, String> = &test_cache();
+    let our_key = 42;
+
+    let result = AssertUnwindSafe(cache.get(our_key, |key| async move {
+      cache.update(key, upsert).await;
+      None
+    }))
+    .catch_unwind()
+    .await;
+    assert!(result.is_err());
+    assert_eq!(*cache.get(our_key, populate).await.unwrap(), "42");
+  }
+
+  #[tokio::test]
+  async fn failed_populate_caches_nothing() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let value = cache.get_or_try_populate(our_key, fail).await;
+      assert_eq!(value, Err("Boom!"));
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      let value = cache.get_or_try_populate(our_key, |key| async move { Ok::<_, ()>(populate(key).await) });
+      assert_eq!(*value.await.unwrap().unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn streamed_values_are_cached_once_whole() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let chunks = |key: i32| futures::stream::iter(key.to_string().chars().map(Ok::<_, &str>).collect::<Vec<_>>());
+    let value = cache.get_streamed(42, chunks).await;
+    assert_eq!(*value.unwrap().unwrap(), "42");
+    assert_eq!(*cache.get(42, do_not_invoke).await.unwrap(), "42");
+
+    let failing = |_| futures::stream::iter(vec![Ok('1'), Err("Boom!"), Ok('2')]);
+    assert_eq!(cache.get_streamed(12, failing).await, Err("Boom!"));
+    assert_eq!(cache.get_if_present(&12), None);
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn waiters_retry_failed_populate() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    let failing = cache.get_or_try_populate(our_key, |_| async {
+      tokio::time::delay_for(Duration::from_millis(1)).await;
+      Err("Boom!")
+    });
+    let retrying = cache.get_or_try_populate(our_key, |key| async move { Ok::<_, &str>(populate(key).await) });
+
+    let (failed, retried) = futures::join!(failing, retrying);
+    assert_eq!(failed, Err("Boom!"));
+    assert_eq!(*retried.unwrap().unwrap(), "42");
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn waiters_get_propagated_failure() {
+    let cache: CacheThrough<i32, String> = test_cache().on_error(OnError::Propagate);
+    let our_key = 42;
+
+    let failing = cache.get_or_try_populate_propagating(our_key, |_| async {
+      tokio::time::delay_for(Duration::from_millis(1)).await;
+      Err("Boom!")
+    });
+    let waiting =
+      cache.get_or_try_populate_propagating(our_key, |key| async move { Ok::<_, &str>(populate(key).await) });
+
+    let (failed, waited) = futures::join!(failing, waiting);
+    assert_eq!(failed, Err("Boom!"));
+    assert_eq!(waited, Err("Boom!"));
+    assert_eq!(cache.len(), 0);
+  }
+
+  #[tokio::test]
+  async fn waiters_retry_unshareable_failures() {
+    #[derive(Debug, PartialEq)]
+    struct Unshareable;
+
+    let cache: CacheThrough<i32, String> = test_cache().on_error(OnError::Propagate);
+    let our_key = 42;
+
+    let failing = cache.get_or_try_populate(our_key, |_| async {
+      tokio::time::delay_for(Duration::from_millis(1)).await;
+      Err(Unshareable)
+    });
+    let retrying =
+      cache.get_or_try_populate_propagating(our_key, |key| async move { Ok::<_, &str>(populate(key).await) });
+
+    let (failed, retried) = futures::join!(failing, retrying);
+    assert_eq!(failed, Err(Unshareable));
+    assert_eq!(*retried.unwrap().unwrap(), "42");
+    assert_eq!(cache.len(), 1);
+  }
+
+  async fn fail(_key: i32) -> Result<Option<String>, &'static str> {
+    Err("Boom!")
+  }
+
   async fn do_not_invoke(_key: i32) -> Option<String> {
     panic!("I shall not be invoked!");
   }
@@ -0,0 +1,822 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::hash::{BuildHasher, Hash};
+use core::time::Duration;
+
+use crate::admission::AdmissionPolicy;
+use crate::error::ConfigError;
+use crate::eviction::{AnyEvictor, Eviction, Evictor};
+use crate::loader::{BoxedLoader, Loader};
+use crate::platform::{RandomState, Semaphore};
+use crate::segment::{EvictionListener, Segment};
+#[cfg(feature = "metrics")]
+use crate::stats::MetricNames;
+use crate::stats::{LoadListener, Stats};
+use crate::weigher::{BoxedWeigher, Weigher};
+use crate::writer::{BoxedWriter, Writer};
+use crate::{CacheThrough, Executor};
+
+/// Configures and creates a `CacheThrough`. All options but the `capacity` are optional.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use cachers::{CacheThrough, Eviction};
+///
+/// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+///   .capacity(100)
+///   .time_to_live(Duration::from_secs(60))
+///   .eviction(Eviction::Clock)
+///   .on_evict(|key, _| println!("evicted {}", key))
+///   .build();
+/// ```
+pub struct CacheThroughBuilder<K, V, S = RandomState> {
+  capacity: usize,
+  initial_map_capacity: Option<usize>,
+  hasher: S,
+  eviction: Eviction,
+  evictor: Option<Box<dyn Evictor<K> + Send + Sync>>,
+  admission: Option<Box<dyn AdmissionPolicy + Send + Sync>>,
+  time_to_live: Option<Duration>,
+  time_to_idle: Option<Duration>,
+  negative_time_to_live: Option<Duration>,
+  refresh_ahead: Option<(Duration, Executor)>,
+  on_evict: Option<EvictionListener<K, V>>,
+  weigher: Option<(BoxedWeigher<K, V>, usize)>,
+  max_entry_weight_ratio: Option<f64>,
+  low_water_ratio: Option<f64>,
+  max_concurrent_loads: Option<usize>,
+  reject_excess_loads: bool,
+  on_load: Option<LoadListener>,
+  loader: Option<BoxedLoader<K, V>>,
+  writer: Option<BoxedWriter<K, V>>,
+  #[cfg(feature = "metrics")]
+  metrics_prefix: Option<String>,
+}
+
+impl<K, V> CacheThroughBuilder<K, V> {
+  pub fn new() -> CacheThroughBuilder<K, V> {
+    CacheThroughBuilder {
+      capacity: 0,
+      initial_map_capacity: None,
+      hasher: RandomState::new(),
+      eviction: Eviction::default(),
+      evictor: None,
+      admission: None,
+      time_to_live: None,
+      time_to_idle: None,
+      negative_time_to_live: None,
+      refresh_ahead: None,
+      on_evict: None,
+      weigher: None,
+      max_entry_weight_ratio: None,
+      low_water_ratio: None,
+      max_concurrent_loads: None,
+      reject_excess_loads: false,
+      on_load: None,
+      loader: None,
+      writer: None,
+      #[cfg(feature = "metrics")]
+      metrics_prefix: None,
+    }
+  }
+}
+
+impl<K, V> Default for CacheThroughBuilder<K, V> {
+  fn default() -> CacheThroughBuilder<K, V> {
+    CacheThroughBuilder::new()
+  }
+}
+
+impl<K, V, S> CacheThroughBuilder<K, V, S>
+where
+  K: Eq + Hash + Clone,
+  S: BuildHasher,
+{
+  /// The maximum number of entries the cache holds, which needs to be greater than zero.
+  pub fn capacity(mut self, capacity: usize) -> Self {
+    self.capacity = capacity;
+    self
+  }
+
+  /// Allocates room for `initial_map_capacity` entries upfront, rather than growing the map as
+  /// entries get added, e.g. to avoid rehashing while warming up. The map starts empty otherwise.
+  ///
+  /// The number of entries the map ends up holding can differ from the `capacity`: cached misses
+  /// take up entries too, and with a `weigher` the number of entries depends on their weights.
+  /// This only sizes the initial allocation, and doesn't bound anything.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(10_000)
+  ///   .weigher(1 << 20, |_: &usize, value: &String| value.len())
+  ///   .initial_map_capacity(1_000)
+  ///   .build();
+  /// ```
+  pub fn initial_map_capacity(mut self, initial_map_capacity: usize) -> Self {
+    self.initial_map_capacity = Some(initial_map_capacity);
+    self
+  }
+
+  /// Hashes keys with `hasher` instead of the default `RandomState`.
+  pub fn hasher<H: BuildHasher>(self, hasher: H) -> CacheThroughBuilder<K, V, H> {
+    CacheThroughBuilder {
+      capacity: self.capacity,
+      initial_map_capacity: self.initial_map_capacity,
+      hasher,
+      eviction: self.eviction,
+      evictor: self.evictor,
+      admission: self.admission,
+      time_to_live: self.time_to_live,
+      time_to_idle: self.time_to_idle,
+      negative_time_to_live: self.negative_time_to_live,
+      refresh_ahead: self.refresh_ahead,
+      on_evict: self.on_evict,
+      weigher: self.weigher,
+      max_entry_weight_ratio: self.max_entry_weight_ratio,
+      low_water_ratio: self.low_water_ratio,
+      max_concurrent_loads: self.max_concurrent_loads,
+      reject_excess_loads: self.reject_excess_loads,
+      on_load: self.on_load,
+      loader: self.loader,
+      writer: self.writer,
+      #[cfg(feature = "metrics")]
+      metrics_prefix: self.metrics_prefix,
+    }
+  }
+
+  /// The strategy picking entries to evict, `Eviction::Clock` unless specified otherwise.
+  pub fn eviction(mut self, eviction: Eviction) -> Self {
+    self.eviction = eviction;
+    self
+  }
+
+  /// Evicts entries with a custom `evictor` rather than one of the built-in `Eviction` strategies,
+  /// e.g. one picked at runtime. The cache's capacity is then the `evictor`'s own, regardless of
+  /// the `capacity` option.
+  pub fn evictor(mut self, evictor: Box<dyn Evictor<K> + Send + Sync>) -> Self {
+    self.evictor = Some(evictor);
+    self
+  }
+
+  /// Consults `policy` before evicting an entry to make room for a new one, see
+  /// `CacheThrough::with_admission_policy`.
+  pub fn admission_policy<P>(mut self, policy: P) -> Self
+  where
+    P: AdmissionPolicy + Send + Sync + 'static,
+  {
+    self.admission = Some(Box::new(policy));
+    self
+  }
+
+  /// Expires entries `time_to_live` after they were last populated or updated.
+  #[cfg(feature = "std")]
+  pub fn time_to_live(mut self, time_to_live: Duration) -> Self {
+    self.time_to_live = Some(time_to_live);
+    self
+  }
+
+  /// Expires entries that weren't read nor updated for `time_to_idle`, e.g. the sessions of users
+  /// that went away, regardless of their `time_to_live`, if any.
+  #[cfg(feature = "std")]
+  pub fn time_to_idle(mut self, time_to_idle: Duration) -> Self {
+    self.time_to_idle = Some(time_to_idle);
+    self
+  }
+
+  /// Caches misses, i.e. `populating_fn` returning `None`, for `negative_time_to_live`, so that
+  /// keys missing from the backend don't hit it on every `get`. Cached misses count against the
+  /// capacity, but expire independently of the `time_to_live` of actual values.
+  #[cfg(feature = "std")]
+  pub fn negative_time_to_live(mut self, negative_time_to_live: Duration) -> Self {
+    self.negative_time_to_live = Some(negative_time_to_live);
+    self
+  }
+
+  /// Refreshes entries ahead of their expiry, once within `refresh_window` of it, using the
+  /// `executor` to run the refreshes, see `CacheThrough::with_refresh_ahead`. Requires a
+  /// `time_to_live` longer than `refresh_window`.
+  #[cfg(feature = "std")]
+  pub fn refresh_ahead<E>(mut self, refresh_window: Duration, executor: E) -> Self
+  where
+    E: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+  {
+    self.refresh_ahead = Some((refresh_window, Box::new(executor)));
+    self
+  }
+
+  /// Caps the cache by the total weight of its entries, as weighed by `weigher`, on top of its
+  /// `capacity`: whenever an entry is populated or updated and the total exceeds `max_weight`,
+  /// entries get evicted, as picked by the eviction strategy, until it fits again. Without a
+  /// weigher, every entry weighs `1`, see `Unweighted`.
+  ///
+  /// An entry is weighed anew whenever its value changes, e.g. on `update`. Should it get heavier,
+  /// other entries may be evicted to make room, or even the updated entry itself, should it weigh
+  /// more than `max_weight` on its own. Cached misses weigh nothing.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .weigher(1024, |_: &usize, value: &String| value.len())
+  ///   .build();
+  /// ```
+  pub fn weigher<W>(mut self, max_weight: usize, weigher: W) -> Self
+  where
+    W: Weigher<K, V> + Send + Sync + 'static,
+  {
+    self.weigher = Some((Box::new(weigher), max_weight));
+    self
+  }
+
+  /// Refuses to retain values weighing more than `ratio` of the `weigher`'s `max_weight`, e.g.
+  /// `0.1` for a tenth, so that a single huge value doesn't evict many useful small ones. Such
+  /// values are still returned by `get` & `update`, they just aren't cached. `ratio` needs to be
+  /// greater than `0` and at most `1`, and building a cache without a `weigher` fails with
+  /// `ConfigError::MissingWeigher`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .weigher(1024, |_: &usize, value: &String| value.len())
+  ///   .max_entry_weight_ratio(0.5)
+  ///   .build();
+  /// assert_eq!(cache.get(&42, |_| Some("x".repeat(600))).unwrap().len(), 600);
+  /// assert!(cache.is_empty());
+  /// ```
+  pub fn max_entry_weight_ratio(mut self, ratio: f64) -> Self {
+    self.max_entry_weight_ratio = Some(ratio);
+    self
+  }
+
+  /// Once the total weight of the entries exceeds the `weigher`'s `max_weight`, evicts entries
+  /// until it is down to `ratio` of it, e.g. `0.9`, rather than just enough for it to fit again.
+  /// This frees room for the next few entries in one pass, instead of evicting on every single
+  /// one of them while the cache is full. `ratio` needs to be greater than `0` and at most `1`,
+  /// the latter being the default. It only bounds the weight: building a cache without a
+  /// `weigher` fails with `ConfigError::MissingWeigher`, the capacity being enforced one entry at
+  /// a time regardless.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .weigher(10, |_: &usize, value: &String| value.len())
+  ///   .low_water_ratio(0.5)
+  ///   .build();
+  /// for key in 0..6 {
+  ///   cache.get(&key, |_| Some("xx".to_string()));
+  /// }
+  /// assert_eq!(cache.len(), 2);
+  /// ```
+  pub fn low_water_ratio(mut self, ratio: f64) -> Self {
+    self.low_water_ratio = Some(ratio);
+    self
+  }
+
+  /// Caps the number of misses populated at once across the whole cache to `limit`, which needs to
+  /// be greater than zero, e.g. to protect a fragile backend. Misses beyond it wait for one of the
+  /// loads in flight to complete before invoking their `loader` or `populating_fn`, unless
+  /// `reject_excess_loads` is set.
+  ///
+  /// Every load counts: `get` and its variants, `get_with_timeout`, refreshes ahead of expiry or
+  /// by `refresh_stale`, and `get_many`, whose `populate_missing` counts as a single load.
+  ///
+  /// This composes with the guarantee that a key is only ever populated by one thread at a time:
+  /// threads missing on a key another one is populating wait for it, and get its value, without
+  /// taking up any of the `limit`. Misses waiting for a load to complete keep other keys sharing
+  /// their populating lock waiting too, though.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .max_concurrent_loads(4)
+  ///   .build();
+  /// ```
+  pub fn max_concurrent_loads(mut self, limit: usize) -> Self {
+    self.max_concurrent_loads = Some(limit);
+    self
+  }
+
+  /// Misses finding `max_concurrent_loads` loads in flight return `None`, without populating the
+  /// cache, rather than waiting for one of them to complete. `CacheThrough::get_reporting` tells
+  /// them apart, as `Source::Throttled`. Refreshes are skipped likewise, leaving the current values
+  /// in place. Only applies along with `max_concurrent_loads`.
+  ///
+  /// ```
+  /// use cachers::{CacheThrough, Source};
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .max_concurrent_loads(1)
+  ///   .reject_excess_loads()
+  ///   .build();
+  /// let value = cache.get(&1, |key| {
+  ///   assert_eq!(cache.get_reporting(&2, |_| unreachable!()), (None, Source::Throttled));
+  ///   Some(key.to_string())
+  /// });
+  /// assert_eq!(*value.unwrap(), "1");
+  /// ```
+  pub fn reject_excess_loads(mut self) -> Self {
+    self.reject_excess_loads = true;
+    self
+  }
+
+  /// Reports the cache's hits, misses, updates & evictions as counters, and how long its
+  /// `populating_fn` takes as a histogram, to whatever recorder is installed for the `metrics`
+  /// facade. Every metric name is prefixed with `name_prefix`, e.g. `users_hits`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .metrics_prefix("users")
+  ///   .build();
+  /// ```
+  #[cfg(feature = "metrics")]
+  pub fn metrics_prefix<P: Into<String>>(mut self, name_prefix: P) -> Self {
+    self.metrics_prefix = Some(name_prefix.into());
+    self
+  }
+
+  /// Invokes `listener` with the key & value of every entry evicted to make room for another one.
+  /// Entries that are removed, updated away or that expire aren't reported.
+  ///
+  /// The listener is invoked while holding the cache's write lock, so it should be quick and must
+  /// not access the cache itself.
+  pub fn on_evict<L>(mut self, listener: L) -> Self
+  where
+    L: Fn(&K, &Arc<V>) + Send + Sync + 'static,
+  {
+    self.on_evict = Some(Box::new(listener));
+    self
+  }
+
+  /// Invokes `listener` with how long the `populating_fn` took, every time one gets invoked on a
+  /// miss, e.g. to track the latency of the backend the cache sits in front of. Hits aren't timed.
+  ///
+  /// ```
+  /// use std::sync::{Arc, Mutex};
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let slowest = Arc::new(Mutex::new(Duration::from_secs(0)));
+  /// let tracked = slowest.clone();
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .on_load(move |took| {
+  ///     let mut slowest = tracked.lock().unwrap();
+  ///     *slowest = (*slowest).max(took);
+  ///   })
+  ///   .build();
+  /// ```
+  pub fn on_load<L>(mut self, listener: L) -> Self
+  where
+    L: Fn(Duration) + Send + Sync + 'static,
+  {
+    self.on_load = Some(Box::new(listener));
+    self
+  }
+
+  /// Consults `loader` on misses, before invoking the `populating_fn`, which makes the cache the
+  /// first of two tiers: values found by the `loader` are promoted into the cache, and only keys
+  /// it doesn't hold are populated from the origin.
+  ///
+  /// Like the `populating_fn`, the `loader` is invoked only once per miss, however many threads
+  /// race to populate the same key.
+  ///
+  /// ```
+  /// use std::collections::HashMap;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let mut disk = HashMap::new();
+  /// disk.insert(42, "42".to_string());
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder()
+  ///   .capacity(100)
+  ///   .loader(move |key: &usize| disk.get(key).cloned())
+  ///   .build();
+  /// assert_eq!(*cache.get(&42, |_| unreachable!()).unwrap(), "42");
+  /// assert_eq!(*cache.get(&7, |key| Some(key.to_string())).unwrap(), "7");
+  /// ```
+  pub fn loader<L>(mut self, loader: L) -> Self
+  where
+    L: Loader<K, V> + Send + Sync + 'static,
+  {
+    self.loader = Some(Box::new(loader));
+    self
+  }
+
+  /// Writes every `update` & `remove` through to `writer`, e.g. the backing store a `loader`
+  /// reads from, as well as every other change made to the cache's values: `compute_if_present`,
+  /// `get_or_insert`, `replace_if` & `modify` included. The `writer` is invoked under the cache's
+  /// write lock, before the cache itself is altered, so that changes reach the store in the order
+  /// the cache applies them. Should it fail, the change is aborted and the cache left untouched,
+  /// see `CacheThrough::try_update`.
+  ///
+  /// Entries populated on misses, evicted or expiring aren't written through, as they already
+  /// mirror the store. Neither are those removed by `retain`, `invalidate_all`,
+  /// `invalidate_namespace` or `drain`, which only affect the cache.
+  pub fn writer<W>(mut self, writer: W) -> Self
+  where
+    W: Writer<K, V> + Send + Sync + 'static,
+  {
+    self.writer = Some(Box::new(writer));
+    self
+  }
+
+  /// Creates the configured `CacheThrough`.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, see `try_build` for the non-panicking version.
+  pub fn build(self) -> CacheThrough<K, V, S> {
+    match self.try_build() {
+      Ok(cache) => cache,
+      Err(err) => panic!("Invalid cache configuration: {}", err),
+    }
+  }
+
+  /// Creates the configured `CacheThrough`, or returns the `ConfigError` describing why the
+  /// configuration is invalid.
+  pub fn try_build(self) -> Result<CacheThrough<K, V, S>, ConfigError> {
+    let evictor = match self.evictor {
+      Some(evictor) if evictor.capacity() == 0 => return Err(ConfigError::ZeroCapacity),
+      Some(evictor) => AnyEvictor::Custom(evictor),
+      None if self.capacity == 0 => return Err(ConfigError::ZeroCapacity),
+      None => {
+        if let Eviction::Slru { protected_percent } = self.eviction {
+          if protected_percent > 100 {
+            return Err(ConfigError::InvalidProtectedPercent);
+          }
+        }
+        self.eviction.evictor(self.capacity)
+      }
+    };
+    let mut segment = Segment::with_evictor(evictor, self.hasher);
+    if let Some(initial_map_capacity) = self.initial_map_capacity {
+      segment.reserve(initial_map_capacity);
+    }
+    if let Some(policy) = self.admission {
+      segment.admit_with(policy);
+    }
+    if let Some(time_to_live) = self.time_to_live {
+      segment.expire_after(time_to_live);
+    }
+    if let Some(time_to_idle) = self.time_to_idle {
+      segment.expire_when_idle_for(time_to_idle);
+    }
+    if let Some(negative_time_to_live) = self.negative_time_to_live {
+      segment.cache_misses_for(negative_time_to_live);
+    }
+    let executor = match self.refresh_ahead {
+      Some((refresh_window, executor)) => {
+        match self.time_to_live {
+          Some(time_to_live) if refresh_window < time_to_live => segment.refresh_ahead(refresh_window),
+          Some(_) => return Err(ConfigError::RefreshWindowTooLong),
+          None => return Err(ConfigError::MissingTimeToLive),
+        }
+        Some(executor)
+      }
+      None => None,
+    };
+    if let Some(listener) = self.on_evict {
+      segment.on_evict(listener);
+    }
+    if let Some(ratio) = self.max_entry_weight_ratio {
+      if !(ratio > 0.0 && ratio <= 1.0) {
+        return Err(ConfigError::InvalidEntryWeightRatio);
+      }
+      if self.weigher.is_none() {
+        return Err(ConfigError::MissingWeigher);
+      }
+    }
+    if let Some(ratio) = self.low_water_ratio {
+      if !(ratio > 0.0 && ratio <= 1.0) {
+        return Err(ConfigError::InvalidLowWaterRatio);
+      }
+      if self.weigher.is_none() {
+        return Err(ConfigError::MissingWeigher);
+      }
+    }
+    if let Some((weigher, max_weight)) = self.weigher {
+      if let Some(ratio) = self.max_entry_weight_ratio {
+        segment.cap_entry_weight((max_weight as f64 * ratio) as usize);
+      }
+      if let Some(ratio) = self.low_water_ratio {
+        segment.shed_weight_to((max_weight as f64 * ratio) as usize);
+      }
+      segment.weigh_with(weigher, max_weight);
+    }
+    if let Some(writer) = self.writer {
+      segment.write_through(writer);
+    }
+    if self.max_concurrent_loads == Some(0) {
+      return Err(ConfigError::ZeroConcurrentLoads);
+    }
+    #[cfg(feature = "metrics")]
+    let names = self.metrics_prefix.map(|prefix| MetricNames::new(&prefix));
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &names {
+      segment.report_metrics(names.clone());
+    }
+    let mut stats = Stats::default();
+    if let Some(listener) = self.on_load {
+      stats.on_load(listener);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(names) = names {
+      stats.report_to(names);
+    }
+    let mut cache = CacheThrough::from_segment(segment, executor);
+    cache.loader = self.loader.map(Arc::new);
+    cache.loads = self.max_concurrent_loads.map(|limit| Arc::new(Semaphore::new(limit)));
+    cache.reject_excess_loads = self.reject_excess_loads;
+    cache.stats = Arc::new(stats);
+    Ok(cache)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CacheThroughBuilder;
+  use crate::{CacheThrough, ConfigError, Eviction};
+  use std::collections::hash_map::DefaultHasher;
+  use std::hash::BuildHasherDefault;
+  use std::sync::{Arc, Mutex};
+  use std::thread;
+  use std::time::Duration;
+
+  #[test]
+  fn notifies_evictions() {
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let listener = evicted.clone();
+    let cache: CacheThrough<i32, String, _> = CacheThroughBuilder::new()
+      .capacity(2)
+      .hasher(BuildHasherDefault::<DefaultHasher>::default())
+      .on_evict(move |key: &i32, value: &Arc<String>| listener.lock().unwrap().push((*key, value.to_string())))
+      .build();
+
+    for key in 1..4 {
+      cache.get(&key, |key| Some(key.to_string()));
+    }
+    cache.remove(3);
+
+    assert_eq!(*evicted.lock().unwrap(), vec![(1, "1".to_string())]);
+  }
+
+  #[test]
+  fn allocates_the_initial_map_capacity_upfront() {
+    let lazy: CacheThrough<i32, String> = CacheThroughBuilder::new().capacity(2).build();
+    let eager: CacheThrough<i32, String> = CacheThroughBuilder::new().capacity(2).initial_map_capacity(100).build();
+    let buckets = eager.estimated_memory_bytes(|_| 0) - lazy.estimated_memory_bytes(|_| 0);
+    assert!(buckets >= 100 * std::mem::size_of::<(i32, String)>());
+    assert_eq!(eager.capacity(), 2);
+  }
+
+  #[test]
+  fn slru_keeps_hit_keys() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .eviction(Eviction::Slru { protected_percent: 50 })
+      .build();
+    cache.get(&1, |key| Some(key.to_string()));
+    cache.get(&1, |_| unimplemented!());
+
+    for key in 100..110 {
+      cache.get(&key, |key| Some(key.to_string()));
+    }
+
+    assert_eq!(*cache.get(&1, |_| unimplemented!()).unwrap(), "1");
+    assert_eq!(cache.len(), 3);
+  }
+
+  #[test]
+  fn two_queue_keeps_requested_again_keys() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder().capacity(4).eviction(Eviction::TwoQueue).build();
+    for key in 1..=5 {
+      cache.get(&key, |key| Some(key.to_string()));
+    }
+    // 1 got evicted, but is remembered when requested again
+    assert_eq!(*cache.get(&1, |key| Some(key.to_string())).unwrap(), "1");
+
+    for key in 100..110 {
+      cache.get(&key, |key| Some(key.to_string()));
+    }
+
+    assert_eq!(*cache.get(&1, |_| unimplemented!()).unwrap(), "1");
+    assert_eq!(cache.len(), 4);
+  }
+
+  #[test]
+  fn size_aware_evicts_less_than_clock_on_bimodal_sizes() {
+    fn evictions(eviction: Eviction) -> usize {
+      let evicted = Arc::new(Mutex::new(0));
+      let listener = evicted.clone();
+      let cache: CacheThrough<i32, String> = CacheThroughBuilder::new()
+        .capacity(1000)
+        .eviction(eviction)
+        .weigher(1000, |_: &i32, value: &String| value.len())
+        .on_evict(move |_, _| *listener.lock().unwrap() += 1)
+        .build();
+      for key in 0..1000 {
+        // one in ten values is a hundred times larger than the others
+        let len = if key % 10 == 0 { 100 } else { 1 };
+        cache.get(&key, |_| Some("x".repeat(len)));
+      }
+      let evicted = *evicted.lock().unwrap();
+      evicted
+    }
+
+    let size_aware = evictions(Eviction::SizeAware);
+    let clock = evictions(Eviction::Clock);
+    assert!(size_aware * 2 < clock, "{} vs {} evictions", size_aware, clock);
+  }
+
+  #[test]
+  fn idle_entries_expire() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .time_to_idle(Duration::from_millis(200))
+      .build();
+    cache.get(&1, |key| Some(key.to_string()));
+
+    // each access pushes the expiry back, past the idle window since the entry got populated
+    thread::sleep(Duration::from_millis(120));
+    assert_eq!(*cache.get(&1, |_| unimplemented!()).unwrap(), "1");
+    thread::sleep(Duration::from_millis(120));
+    assert_eq!(*cache.get(&1, |_| unimplemented!()).unwrap(), "1");
+
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(cache.get_if_present(&1), None);
+    assert_eq!(*cache.get(&1, |_| Some("one".to_string())).unwrap(), "one");
+  }
+
+  #[test]
+  fn evicts_by_weight() {
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let listener = evicted.clone();
+    let cache: CacheThrough<i32, String> = CacheThroughBuilder::new()
+      .capacity(10)
+      .weigher(6, |_: &i32, value: &String| value.len())
+      .on_evict(move |key, _| listener.lock().unwrap().push(*key))
+      .build();
+    cache.get(&1, |_| Some("aa".to_string()));
+    cache.get(&2, |_| Some("bb".to_string()));
+    cache.get(&3, |_| Some("cc".to_string()));
+    assert_eq!(cache.len(), 3);
+
+    cache.get(&4, |_| Some("dd".to_string()));
+    assert_eq!(cache.len(), 3);
+    assert_eq!(evicted.lock().unwrap().len(), 1);
+
+    cache.update(4, |_, _| Some("dddddd".to_string()));
+    assert_eq!(cache.len(), 1);
+    assert_eq!(*cache.get_if_present(&4).unwrap(), "dddddd");
+
+    cache.update(4, |_, _| Some("ddddddd".to_string()));
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn retains_not_entries_too_heavy() {
+    let cache: CacheThrough<i32, String> = CacheThroughBuilder::new()
+      .capacity(10)
+      .weigher(10, |_: &i32, value: &String| value.len())
+      .max_entry_weight_ratio(0.5)
+      .build();
+    cache.get(&1, |_| Some("aaaaa".to_string()));
+    assert_eq!(*cache.get(&2, |_| Some("bbbbbb".to_string())).unwrap(), "bbbbbb");
+    assert_eq!(cache.keys(), vec![1]);
+
+    assert_eq!(*cache.update(1, |_, _| Some("aaaaaa".to_string())).unwrap(), "aaaaaa");
+    assert!(cache.is_empty());
+    assert_eq!(*cache.update(3, |_, _| Some("ccccc".to_string())).unwrap(), "ccccc");
+    assert_eq!(cache.keys(), vec![3]);
+  }
+
+  #[test]
+  fn sheds_weight_down_to_the_low_water_mark() {
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let listener = evicted.clone();
+    let cache: CacheThrough<i32, String> = CacheThroughBuilder::new()
+      .capacity(10)
+      .weigher(10, |_: &i32, value: &String| value.len())
+      .low_water_ratio(0.5)
+      .on_evict(move |key, _| listener.lock().unwrap().push(*key))
+      .build();
+    for key in 0..5 {
+      cache.get(&key, |_| Some("aa".to_string()));
+    }
+    assert_eq!(cache.weight(), 10);
+    assert!(evicted.lock().unwrap().is_empty());
+
+    cache.get(&5, |_| Some("aa".to_string()));
+    assert_eq!(evicted.lock().unwrap().len(), 4);
+    assert_eq!(cache.weight(), 4);
+    assert_eq!(cache.len(), 2);
+
+    for key in 6..9 {
+      cache.get(&key, |_| Some("aa".to_string()));
+    }
+    assert_eq!(evicted.lock().unwrap().len(), 4);
+    assert_eq!(cache.weight(), 10);
+  }
+
+  #[test]
+  fn rejects_conflicting_options() {
+    assert_eq!(
+      CacheThrough::<i32, String>::builder().try_build().err(),
+      Some(ConfigError::ZeroCapacity)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .refresh_ahead(Duration::from_secs(1), |_| {})
+        .try_build()
+        .err(),
+      Some(ConfigError::MissingTimeToLive)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .time_to_live(Duration::from_secs(1))
+        .refresh_ahead(Duration::from_secs(2), |_| {})
+        .try_build()
+        .err(),
+      Some(ConfigError::RefreshWindowTooLong)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .eviction(Eviction::Slru { protected_percent: 101 })
+        .try_build()
+        .err(),
+      Some(ConfigError::InvalidProtectedPercent)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .max_entry_weight_ratio(1.5)
+        .try_build()
+        .err(),
+      Some(ConfigError::InvalidEntryWeightRatio)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .low_water_ratio(0.0)
+        .try_build()
+        .err(),
+      Some(ConfigError::InvalidLowWaterRatio)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .low_water_ratio(0.5)
+        .try_build()
+        .err(),
+      Some(ConfigError::MissingWeigher)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .max_entry_weight_ratio(0.5)
+        .try_build()
+        .err(),
+      Some(ConfigError::MissingWeigher)
+    );
+    assert_eq!(
+      CacheThrough::<i32, String>::builder()
+        .capacity(2)
+        .max_concurrent_loads(0)
+        .try_build()
+        .err(),
+      Some(ConfigError::ZeroConcurrentLoads)
+    );
+  }
+}
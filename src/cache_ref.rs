@@ -0,0 +1,73 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt;
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+use crate::platform::RwLockReadGuard;
+use crate::segment::Segment;
+
+/// A reference to a value held by a `CacheThrough`, as returned by `CacheThrough::get_ref`, that
+/// derefs to the `V` without bumping the reference count of the `Arc` holding it.
+///
+/// The `CacheRef` holds the cache's read lock for as long as it lives: other threads can still
+/// read from the cache, but none can write to it meanwhile. Don't hold on to it across a call that
+/// populates, updates or removes another key, as the thread holding it would deadlock on it, see
+/// `CacheThrough::with_ref` for a borrow that can't outlive the call.
+///
+/// As the lock it holds, a `CacheRef` can't be sent to another thread:
+///
+/// ```compile_fail
+/// use cachers::CacheThrough;
+///
+/// fn send<T: Send>(_: T) {}
+///
+/// let cache = CacheThrough::<usize, String>::new(100);
+/// cache.get(&42, |key| Some(key.to_string()));
+/// send(cache.get_ref(&42));
+/// ```
+pub struct CacheRef<'a, K, V, S> {
+  _segment: RwLockReadGuard<'a, Segment<K, V, S>>,
+  value: NonNull<V>,
+}
+
+impl<'a, K, V, S> CacheRef<'a, K, V, S> {
+  pub(crate) fn new(segment: RwLockReadGuard<'a, Segment<K, V, S>>, value: NonNull<V>) -> CacheRef<'a, K, V, S> {
+    CacheRef {
+      _segment: segment,
+      value,
+    }
+  }
+}
+
+impl<K, V, S> Deref for CacheRef<'_, K, V, S> {
+  type Target = V;
+
+  fn deref(&self) -> &V {
+    // SAFETY: `value` points into the heap allocation of an `Arc` held by a `CacheEntry` of the
+    // segment, which stays put when the map rehashes. Replacing or dropping that `Arc`, or
+    // mutating the `V` in it, e.g. through `Arc::make_mut` in `modify`, all take a `&mut Segment`,
+    // i.e. the write lock, which can't be acquired while we hold the read lock. Under the read
+    // lock, only the entries' atomic metadata gets updated, never their `value`. The `NonNull`
+    // keeps the `CacheRef` from being sent to, or shared with, threads not holding that lock.
+    unsafe { self.value.as_ref() }
+  }
+}
+
+impl<K, V: fmt::Debug, S> fmt::Debug for CacheRef<'_, K, V, S> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Debug::fmt(&**self, f)
+  }
+}
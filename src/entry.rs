@@ -0,0 +1,71 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use core::hash::{BuildHasher, Hash};
+
+use crate::registry::WriteGuard;
+
+/// A view into a single entry of a `CacheThrough`, as returned by `CacheThrough::entry`, to
+/// inspect and alter it under the write lock, much like `HashMap`'s entry API. Unlike with the
+/// latter, each of its methods looks the key up anew: `and_modify` followed by `or_insert` makes
+/// two lookups, though no other thread can alter the entry in between.
+///
+/// The `Entry` holds the cache's write lock for as long as it lives: no other thread can read
+/// from nor write to the cache meanwhile, and the thread holding it would deadlock on any other
+/// call to the cache. Keep its scope short, by consuming it right away with `or_insert_with` or
+/// `or_insert`.
+pub struct Entry<'a, K, V, S> {
+  segment: WriteGuard<'a, K, V, S>,
+  key: K,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+  K: Eq + Hash + Clone,
+  S: BuildHasher,
+{
+  pub(crate) fn new(segment: WriteGuard<'a, K, V, S>, key: K) -> Entry<'a, K, V, S> {
+    Entry { segment, key }
+  }
+
+  /// The key of this entry.
+  pub fn key(&self) -> &K {
+    &self.key
+  }
+
+  /// Mutates the value in place, should the entry hold one, as `CacheThrough::modify` does.
+  pub fn and_modify<F>(mut self, modifying_fn: F) -> Self
+  where
+    V: Clone,
+    F: FnOnce(&mut V),
+  {
+    self.segment.modify(&self.key, modifying_fn);
+    self
+  }
+
+  /// Returns the entry's value, inserting `default` should it hold none.
+  pub fn or_insert(mut self, default: V) -> Arc<V> {
+    self.segment.get_or_insert_with(self.key, || default)
+  }
+
+  /// Returns the entry's value, inserting the one `default` returns should it hold none. Unlike
+  /// with `CacheThrough::get`, `default` is invoked while holding the write lock.
+  pub fn or_insert_with<F>(mut self, default: F) -> Arc<V>
+  where
+    F: FnOnce() -> V,
+  {
+    self.segment.get_or_insert_with(self.key, default)
+  }
+}
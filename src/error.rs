@@ -0,0 +1,134 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Describes why a cache configuration was rejected at construction time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+  /// The cache was configured to hold no entries at all.
+  ZeroCapacity,
+  /// The refresh-ahead window was configured to span the entire time-to-live, or more.
+  RefreshWindowTooLong,
+  /// Refreshing entries ahead of their expiry was configured, without them ever expiring.
+  MissingTimeToLive,
+  /// The protected segment of an SLRU eviction was configured to take more than 100% of the
+  /// capacity.
+  InvalidProtectedPercent,
+  /// The maximum weight of an entry was configured as a ratio of the maximum total weight that
+  /// isn't greater than zero, or is greater than one.
+  InvalidEntryWeightRatio,
+  /// The number of loads in flight at once was capped to zero.
+  ZeroConcurrentLoads,
+  /// The weight to shed down to was configured as a ratio of the maximum total weight that isn't
+  /// greater than zero, or is greater than one.
+  InvalidLowWaterRatio,
+  /// The maximum weight of an entry, or the weight to shed down to, was configured without the
+  /// entries ever being weighed.
+  MissingWeigher,
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigError::ZeroCapacity => write!(f, "capacity must be greater than zero"),
+      ConfigError::RefreshWindowTooLong => write!(f, "refresh window must be shorter than the time-to-live"),
+      ConfigError::MissingTimeToLive => write!(f, "refresh ahead requires a time-to-live"),
+      ConfigError::InvalidProtectedPercent => write!(f, "protected segment can't exceed 100% of the capacity"),
+      ConfigError::InvalidEntryWeightRatio => write!(f, "entry weight ratio must be in (0, 1]"),
+      ConfigError::ZeroConcurrentLoads => write!(f, "maximum concurrent loads must be greater than zero"),
+      ConfigError::InvalidLowWaterRatio => write!(f, "low-water ratio must be in (0, 1]"),
+      ConfigError::MissingWeigher => write!(f, "entry weight & low-water ratios require a weigher"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for ConfigError {}
+
+/// Returned by non-blocking operations, when the cache's lock is held by another thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WouldBlock;
+
+impl fmt::Display for WouldBlock {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "cache is locked by another thread")
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for WouldBlock {}
+
+/// Returned by `CacheThrough::get_with_timeout`, when populating the entry took too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeout;
+
+impl fmt::Display for Timeout {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "populating the entry timed out")
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for Timeout {}
+
+/// Returned by a `Writer` that failed to persist a change, which the cache then doesn't apply
+/// either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteError;
+
+impl fmt::Display for WriteError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "backing store failed to persist the change")
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for WriteError {}
+
+/// Returned by `CacheThrough::update_if_unlocked`, which neither waits for the cache's lock nor
+/// hides a failure of its `Writer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryUpdateError {
+  /// The cache's lock is held by another thread, see `WouldBlock`.
+  WouldBlock,
+  /// The cache's `Writer` failed to persist the update, see `WriteError`.
+  WriteError,
+}
+
+impl From<WouldBlock> for TryUpdateError {
+  fn from(_: WouldBlock) -> Self {
+    TryUpdateError::WouldBlock
+  }
+}
+
+impl From<WriteError> for TryUpdateError {
+  fn from(_: WriteError) -> Self {
+    TryUpdateError::WriteError
+  }
+}
+
+impl fmt::Display for TryUpdateError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TryUpdateError::WouldBlock => WouldBlock.fmt(f),
+      TryUpdateError::WriteError => WriteError.fmt(f),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for TryUpdateError {}
@@ -0,0 +1,110 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+
+/// The number of events a subscriber can lag behind, before the cache drops the ones that don't
+/// fit in its channel anymore.
+#[cfg(feature = "std")]
+pub const SUBSCRIPTION_BUFFER: usize = 1024;
+
+/// A change to a cache's entries, as sent to the subscribers of `CacheThrough::subscribe`.
+/// Cached misses and expiries aren't reported.
+#[derive(Debug, PartialEq)]
+pub enum CacheEvent<K, V> {
+  /// The key got mapped to a value, either on a miss or by an update.
+  Insert(K, Arc<V>),
+  /// The value of a present key got replaced.
+  Update(K, Arc<V>),
+  /// The key got removed.
+  Remove(K),
+  /// The entry got evicted to make room for others.
+  Evict(K, Arc<V>),
+}
+
+impl<K: Clone, V> Clone for CacheEvent<K, V> {
+  fn clone(&self) -> Self {
+    match self {
+      CacheEvent::Insert(key, value) => CacheEvent::Insert(key.clone(), value.clone()),
+      CacheEvent::Update(key, value) => CacheEvent::Update(key.clone(), value.clone()),
+      CacheEvent::Remove(key) => CacheEvent::Remove(key.clone()),
+      CacheEvent::Evict(key, value) => CacheEvent::Evict(key.clone(), value.clone()),
+    }
+  }
+}
+
+/// The channels a cache sends its events to. Events are only built should there be any, and never
+/// block the cache: a subscriber whose channel is full misses them, and disconnected ones are
+/// forgotten about. Without `std` there are no channels, so that nothing is ever published.
+pub struct Subscribers<K, V> {
+  #[cfg(feature = "std")]
+  senders: Vec<SyncSender<CacheEvent<K, V>>>,
+  #[cfg(feature = "std")]
+  dropped: u64,
+  #[cfg(not(feature = "std"))]
+  events: PhantomData<CacheEvent<K, V>>,
+}
+
+impl<K: Clone, V> Subscribers<K, V> {
+  pub fn new() -> Subscribers<K, V> {
+    Subscribers {
+      #[cfg(feature = "std")]
+      senders: Vec::new(),
+      #[cfg(feature = "std")]
+      dropped: 0,
+      #[cfg(not(feature = "std"))]
+      events: PhantomData,
+    }
+  }
+
+  #[cfg(feature = "std")]
+  pub fn subscribe(&mut self) -> Receiver<CacheEvent<K, V>> {
+    let (sender, receiver) = sync_channel(SUBSCRIPTION_BUFFER);
+    self.senders.push(sender);
+    receiver
+  }
+
+  /// The number of events subscribers missed, as their channel was full.
+  #[cfg(feature = "std")]
+  pub fn dropped(&self) -> u64 {
+    self.dropped
+  }
+
+  /// Sends the event built by `event` to every subscriber.
+  #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+  pub fn publish<F: FnOnce() -> CacheEvent<K, V>>(&mut self, event: F) {
+    #[cfg(feature = "std")]
+    {
+      if self.senders.is_empty() {
+        return;
+      }
+      let event = event();
+      let dropped = &mut self.dropped;
+      self.senders.retain(|sender| match sender.try_send(event.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+          *dropped += 1;
+          true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+      });
+    }
+  }
+}
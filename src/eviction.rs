@@ -12,19 +12,292 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
-use std::sync::RwLock;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::hash::Hash;
+#[cfg(feature = "eviction-metrics")]
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use crate::platform::HashMap;
+use crate::size_aware::SizeAwareEvictor;
+use crate::slru::SlruEvictor;
+use crate::two_queue::TwoQueueEvictor;
+
+/// The strategy used to pick which entry to evict, once a cache reaches its capacity.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Eviction {
+  /// Approximates LRU by sweeping a clock of reference bits, cleared as the hand passes them.
+  #[default]
+  Clock,
+  /// Segmented LRU, which keeps keys that were hit at least once in a protected segment, taking up
+  /// to `protected_percent` percent of the capacity. New keys are evicted first, which makes it
+  /// resistant to scans.
+  Slru { protected_percent: u8 },
+  /// 2Q, which holds new keys in a FIFO queue taking a quarter of the capacity, and only deems hot
+  /// those added again shortly after they got evicted from it, which go to an LRU queue. Like
+  /// SLRU, it is resistant to scans, but a single hit doesn't suffice for a key to be kept.
+  TwoQueue,
+  /// Evicts the heaviest entry first, as weighed by the cache's weigher, so that making room for a
+  /// large entry takes fewer evictions. Recency is ignored altogether.
+  SizeAware,
+}
+
+impl Eviction {
+  pub(crate) fn evictor<K: Hash>(self, capacity: usize) -> AnyEvictor<K> {
+    match self {
+      Eviction::Clock => AnyEvictor::Clock(ClockEvictor::new(capacity)),
+      Eviction::Slru { protected_percent } => AnyEvictor::Slru(SlruEvictor::new(capacity, protected_percent)),
+      Eviction::TwoQueue => AnyEvictor::TwoQueue(TwoQueueEvictor::new(capacity)),
+      Eviction::SizeAware => AnyEvictor::SizeAware(SizeAwareEvictor::new(capacity)),
+    }
+  }
+}
+
+/// Tracks the keys of a cache, to pick the ones to evict once it reaches its capacity. Every key is
+/// held in a slot, identified by its index, below the evictor's `capacity`.
+///
+/// The built-in strategies are picked with `Eviction`, others can be plugged into a cache with
+/// `CacheThrough::with_boxed_strategy`.
 pub trait Evictor<K> {
+  /// Starts tracking `key`, returning the slot it got and, should the evictor be full, the key
+  /// evicted to free it.
   fn add(&mut self, key: K) -> (usize, Option<K>);
+  /// Records an access to the key at `index`. This is invoked under the cache's read lock, so any
+  /// state it updates needs to be synchronized.
   fn touch(&self, index: usize);
+  /// The key the next `add` would evict, if any. Only peeks at it: the keys tracked, and how
+  /// recently they were used, are left as they are, should the caller not go on to `add`.
+  fn candidate(&mut self) -> Option<&K>;
+  /// Evicts the key the evictor would pick next, even though it isn't full, e.g. for a cache to
+  /// shed weight, and frees its slot. Returns `None` if it tracks no key.
+  fn evict(&mut self) -> Option<K>;
+  /// Forgets about the key at `index`, e.g. because its entry got removed from the cache, so that
+  /// its slot can be reused by the next `add`.
+  fn remove(&mut self, index: usize) -> Option<K>;
+  /// The number of keys tracked at most.
+  fn capacity(&self) -> usize;
+  /// Changes the number of keys tracked at most, evicting keys to fit when shrinking. Keys held
+  /// in slots beyond the new capacity are moved to free ones.
+  fn resize(&mut self, capacity: usize) -> Resized<K>;
+  /// Records the weight of the key at `index`, whenever its entry gets weighed anew by a cache
+  /// with a weigher. Ignored unless the strategy takes weights into account.
+  fn set_weight(&mut self, _index: usize, _weight: usize) {}
+  /// Pins the key at `index`, so that it never gets picked for eviction, or unpins it. Returns
+  /// whether it did: pinning the keys of all slots is refused, so that there always is one to
+  /// evict. Ignored, and refused, unless the strategy supports pinning.
+  fn pin(&mut self, _index: usize, _pinned: bool) -> bool {
+    false
+  }
+}
+
+/// What resizing an evictor did to the keys it tracks.
+#[derive(Debug, PartialEq)]
+pub struct Resized<K> {
+  /// The keys evicted to fit in the new capacity.
+  pub evicted: Vec<K>,
+  /// The `(from, to)` slots of the keys moved out of the truncated range.
+  pub relocated: Vec<(usize, usize)>,
+}
+
+/// Moves the keys in slots beyond `capacity` to free slots below it.
+pub(crate) fn relocate<K>(mapping: &mut HashMap<usize, K>, capacity: usize) -> Vec<(usize, usize)> {
+  let mut from: Vec<usize> = mapping.keys().cloned().filter(|index| *index >= capacity).collect();
+  from.sort_unstable();
+  let relocated: Vec<(usize, usize)> = from
+    .into_iter()
+    .zip(free_slots(mapping, capacity).into_iter().rev())
+    .collect();
+  for &(from, to) in relocated.iter() {
+    if let Some(key) = mapping.remove(&from) {
+      mapping.insert(to, key);
+    }
+  }
+  relocated
+}
+
+/// The slots below `capacity` holding no key, lowest last so that they get `pop`ed first.
+pub(crate) fn free_slots<K>(mapping: &HashMap<usize, K>, capacity: usize) -> Vec<usize> {
+  (0..capacity)
+    .rev()
+    .filter(|index| !mapping.contains_key(index))
+    .collect()
+}
+
+/// Any of the built-in evictors, as picked by `Eviction`, or a custom one.
+pub enum AnyEvictor<K> {
+  Clock(ClockEvictor<K>),
+  Slru(SlruEvictor<K>),
+  TwoQueue(TwoQueueEvictor<K>),
+  SizeAware(SizeAwareEvictor<K>),
+  Custom(Box<dyn Evictor<K> + Send + Sync>),
+}
+
+impl<K: Hash> Evictor<K> for AnyEvictor<K> {
+  fn add(&mut self, key: K) -> (usize, Option<K>) {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.add(key),
+      AnyEvictor::Slru(evictor) => evictor.add(key),
+      AnyEvictor::TwoQueue(evictor) => evictor.add(key),
+      AnyEvictor::SizeAware(evictor) => evictor.add(key),
+      AnyEvictor::Custom(evictor) => evictor.add(key),
+    }
+  }
+
+  fn touch(&self, index: usize) {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.touch(index),
+      AnyEvictor::Slru(evictor) => evictor.touch(index),
+      AnyEvictor::TwoQueue(evictor) => evictor.touch(index),
+      AnyEvictor::SizeAware(evictor) => evictor.touch(index),
+      AnyEvictor::Custom(evictor) => evictor.touch(index),
+    }
+  }
+
+  fn candidate(&mut self) -> Option<&K> {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.candidate(),
+      AnyEvictor::Slru(evictor) => evictor.candidate(),
+      AnyEvictor::TwoQueue(evictor) => evictor.candidate(),
+      AnyEvictor::SizeAware(evictor) => evictor.candidate(),
+      AnyEvictor::Custom(evictor) => evictor.candidate(),
+    }
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.evict(),
+      AnyEvictor::Slru(evictor) => evictor.evict(),
+      AnyEvictor::TwoQueue(evictor) => evictor.evict(),
+      AnyEvictor::SizeAware(evictor) => evictor.evict(),
+      AnyEvictor::Custom(evictor) => evictor.evict(),
+    }
+  }
+
+  fn remove(&mut self, index: usize) -> Option<K> {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.remove(index),
+      AnyEvictor::Slru(evictor) => evictor.remove(index),
+      AnyEvictor::TwoQueue(evictor) => evictor.remove(index),
+      AnyEvictor::SizeAware(evictor) => evictor.remove(index),
+      AnyEvictor::Custom(evictor) => evictor.remove(index),
+    }
+  }
+
+  fn capacity(&self) -> usize {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.capacity(),
+      AnyEvictor::Slru(evictor) => evictor.capacity(),
+      AnyEvictor::TwoQueue(evictor) => evictor.capacity(),
+      AnyEvictor::SizeAware(evictor) => evictor.capacity(),
+      AnyEvictor::Custom(evictor) => evictor.capacity(),
+    }
+  }
+
+  fn resize(&mut self, capacity: usize) -> Resized<K> {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.resize(capacity),
+      AnyEvictor::Slru(evictor) => evictor.resize(capacity),
+      AnyEvictor::TwoQueue(evictor) => evictor.resize(capacity),
+      AnyEvictor::SizeAware(evictor) => evictor.resize(capacity),
+      AnyEvictor::Custom(evictor) => evictor.resize(capacity),
+    }
+  }
+
+  fn set_weight(&mut self, index: usize, weight: usize) {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.set_weight(index, weight),
+      AnyEvictor::Slru(evictor) => evictor.set_weight(index, weight),
+      AnyEvictor::TwoQueue(evictor) => evictor.set_weight(index, weight),
+      AnyEvictor::SizeAware(evictor) => evictor.set_weight(index, weight),
+      AnyEvictor::Custom(evictor) => evictor.set_weight(index, weight),
+    }
+  }
+
+  fn pin(&mut self, index: usize, pinned: bool) -> bool {
+    match self {
+      AnyEvictor::Clock(evictor) => evictor.pin(index, pinned),
+      AnyEvictor::Slru(evictor) => evictor.pin(index, pinned),
+      AnyEvictor::TwoQueue(evictor) => evictor.pin(index, pinned),
+      AnyEvictor::SizeAware(evictor) => evictor.pin(index, pinned),
+      AnyEvictor::Custom(evictor) => evictor.pin(index, pinned),
+    }
+  }
+}
+
+impl<K> AnyEvictor<K> {
+  /// The clock's metrics, `None` for other strategies.
+  #[cfg(feature = "eviction-metrics")]
+  pub fn metrics(&self) -> Option<EvictionMetrics> {
+    match self {
+      AnyEvictor::Clock(evictor) => Some(evictor.metrics()),
+      AnyEvictor::Slru(_) | AnyEvictor::TwoQueue(_) | AnyEvictor::SizeAware(_) | AnyEvictor::Custom(_) => None,
+    }
+  }
+
+  /// The clock, `None` for other strategies.
+  #[cfg(feature = "debug-internals")]
+  pub fn clock(&self) -> Option<&ClockEvictor<K>> {
+    match self {
+      AnyEvictor::Clock(evictor) => Some(evictor),
+      AnyEvictor::Slru(_) | AnyEvictor::TwoQueue(_) | AnyEvictor::SizeAware(_) | AnyEvictor::Custom(_) => None,
+    }
+  }
+}
+
+/// Counts what the clock eviction did so far, which helps telling whether it suits a workload:
+/// long scans per eviction mean most entries get touched between two evictions, i.e. the clock
+/// struggles to tell hot entries from cold ones.
+#[cfg(feature = "eviction-metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionMetrics {
+  /// The number of entries evicted.
+  pub evictions: u64,
+  /// The number of times the hand moved past a touched slot, clearing its reference bit.
+  pub hand_advances: u64,
+  /// The number of slots inspected while looking for the entries to evict.
+  pub scanned: u64,
+  /// The number of times an entry got touched, i.e. its reference bit set.
+  pub touches: u64,
+  /// The most slots the hand went over looking for a single entry to evict, which never exceeds
+  /// two turns of the clock, i.e. twice its capacity.
+  pub longest_sweep: u64,
+}
+
+#[cfg(feature = "eviction-metrics")]
+impl EvictionMetrics {
+  /// The average number of slots inspected per eviction, `0.0` if nothing got evicted yet.
+  pub fn average_scan_length(&self) -> f64 {
+    if self.evictions == 0 {
+      return 0.0;
+    }
+    self.scanned as f64 / self.evictions as f64
+  }
 }
 
 pub struct ClockEvictor<K> {
   capacity: usize,
   current_pos: usize,
-  clock: RwLock<Vec<bool>>,
+  /// The reference bits, atomic so that `touch`ing only requires a shared reference.
+  clock: Vec<AtomicBool>,
+  /// The slots the hand skips, whatever their reference bit.
+  pinned: Vec<bool>,
   mapping: HashMap<usize, K>,
+  /// The slots vacated by `remove`, recycled by the next `add`s, so that the slots in use never
+  /// go beyond `capacity`.
+  free: Vec<usize>,
+  #[cfg(feature = "eviction-metrics")]
+  metrics: ClockMetrics,
+}
+
+#[cfg(feature = "eviction-metrics")]
+#[derive(Default)]
+struct ClockMetrics {
+  evictions: u64,
+  hand_advances: u64,
+  scanned: u64,
+  touches: AtomicU64,
+  longest_sweep: u64,
 }
 
 impl<K> ClockEvictor<K> {
@@ -32,41 +305,117 @@ impl<K> ClockEvictor<K> {
     ClockEvictor {
       capacity,
       current_pos: 0,
-      clock: RwLock::new(vec![false; capacity]),
+      clock: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+      pinned: (0..capacity).map(|_| false).collect(),
       mapping: HashMap::with_capacity(capacity),
+      free: Vec::new(),
+      #[cfg(feature = "eviction-metrics")]
+      metrics: ClockMetrics::default(),
     }
   }
 
-  fn victim(&mut self) -> (usize, Option<K>) {
-    let flip_and_match = |touched: &mut bool| {
-      let victim = !*touched;
-      *touched = false;
-      victim
-    };
-    let mut clock = self.clock.write().unwrap();
-
-    let offset = match clock[self.current_pos..].iter_mut().position(flip_and_match) {
-      Some(index) => index,
-      None => match clock[..self.current_pos].iter_mut().position(flip_and_match) {
-        Some(index) => index,
-        None => self.current_pos,
-      },
-    };
-    let mut index = self.current_pos + offset;
-    if index >= self.capacity {
-      index %= self.capacity;
+  #[cfg(feature = "eviction-metrics")]
+  pub fn metrics(&self) -> EvictionMetrics {
+    EvictionMetrics {
+      evictions: self.metrics.evictions,
+      hand_advances: self.metrics.hand_advances,
+      scanned: self.metrics.scanned,
+      touches: self.metrics.touches.load(Ordering::Relaxed),
+      longest_sweep: self.metrics.longest_sweep,
+    }
+  }
+
+  /// The slot the hand points at, where the next sweep starts.
+  #[cfg(any(test, feature = "debug-internals"))]
+  pub fn hand_position(&self) -> usize {
+    self.current_pos
+  }
+
+  /// The reference bit of every slot, in order.
+  #[cfg(any(test, feature = "debug-internals"))]
+  pub fn reference_bits(&self) -> Vec<bool> {
+    self
+      .clock
+      .iter()
+      .map(|referenced| referenced.load(Ordering::Relaxed))
+      .collect()
+  }
+
+  /// Sweeps the clock, clearing reference bits, until it finds the victim's slot. The hand is left
+  /// pointing at it, so that sweeping again, without touching it in between, yields the same slot.
+  ///
+  /// As every bit passed gets cleared, the hand finds a victim within one full turn of the clock
+  /// at most when it is full: should every slot have been touched, it ends up back where it
+  /// started. Free & pinned slots are skipped, which can take another turn to get back to the keys
+  /// whose bit got cleared. Should there be no unpinned key in the clock, there is no victim. Either
+  /// way, a sweep is bounded to two turns of the clock, so that inserts never take longer.
+  fn sweep(&mut self) -> Option<usize> {
+    for step in 0..2 * self.capacity {
+      let index = (self.current_pos + step) % self.capacity;
+      if self.pinned[index] {
+        continue;
+      }
+      #[cfg(feature = "eviction-metrics")]
+      {
+        self.metrics.scanned += 1;
+      }
+      let referenced = self.clock[index].get_mut();
+      if *referenced {
+        *referenced = false;
+        #[cfg(feature = "eviction-metrics")]
+        {
+          self.metrics.hand_advances += 1;
+        }
+      } else if self.mapping.contains_key(&index) {
+        self.current_pos = index;
+        #[cfg(feature = "eviction-metrics")]
+        self.swept(step + 1);
+        return Some(index);
+      }
+    }
+    #[cfg(feature = "eviction-metrics")]
+    self.swept(2 * self.capacity);
+    None
+  }
+
+  /// The slot `sweep` would stop at, without clearing any reference bit on the way: the first
+  /// unreferenced key from the hand on or, should all be referenced, the one at the hand.
+  fn peek(&self) -> Option<usize> {
+    let slots = (0..self.capacity)
+      .map(|step| (self.current_pos + step) % self.capacity)
+      .filter(|index| !self.pinned[*index] && self.mapping.contains_key(index));
+    slots
+      .clone()
+      .find(|index| !self.clock[*index].load(Ordering::Relaxed))
+      .or_else(|| slots.clone().next())
+  }
+
+  #[cfg(feature = "eviction-metrics")]
+  fn swept(&mut self, length: usize) {
+    self.metrics.longest_sweep = self.metrics.longest_sweep.max(length as u64);
+  }
+
+  fn victim(&mut self) -> Option<(usize, Option<K>)> {
+    let index = self.sweep()?;
+    #[cfg(feature = "eviction-metrics")]
+    {
+      self.metrics.evictions += 1;
     }
-    self.current_pos = index + 1;
-    (index, self.mapping.remove(&index))
+    // the hand moves past the victim, wrapping around at the end of the clock
+    self.current_pos = (index + 1) % self.capacity;
+    Some((index, self.mapping.remove(&index)))
   }
 }
 
 impl<K> Evictor<K> for ClockEvictor<K> {
   fn add(&mut self, key: K) -> (usize, Option<K>) {
+    // slots freed by `remove` come first, all others are in use up to `mapping.len()`
     let (index, victim) = if self.mapping.len() < self.capacity {
-      (self.mapping.len(), None)
+      (self.free.pop().unwrap_or(self.mapping.len()), None)
     } else {
-      self.victim()
+      self
+        .victim()
+        .expect("pins leave at least one key of a full clock unpinned")
     };
 
     self.mapping.insert(index, key);
@@ -75,14 +424,83 @@ impl<K> Evictor<K> for ClockEvictor<K> {
   }
 
   fn touch(&self, index: usize) {
-    let mut clock = self.clock.write().unwrap();
-    clock[index] = true;
+    self.clock[index].store(true, Ordering::Relaxed);
+    #[cfg(feature = "eviction-metrics")]
+    self.metrics.touches.fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn candidate(&mut self) -> Option<&K> {
+    if self.mapping.len() < self.capacity {
+      return None;
+    }
+    let index = self.peek()?;
+    self.mapping.get(&index)
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    let (index, victim) = self.victim()?;
+    self.free.push(index);
+    victim
+  }
+
+  fn remove(&mut self, index: usize) -> Option<K> {
+    let key = self.mapping.remove(&index)?;
+    *self.clock[index].get_mut() = false;
+    self.pinned[index] = false;
+    self.free.push(index);
+    Some(key)
+  }
+
+  fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  fn resize(&mut self, capacity: usize) -> Resized<K> {
+    // pins would otherwise take up all the slots of the shrunk clock
+    if self.pinned.iter().filter(|pinned| **pinned).count() >= capacity {
+      self.pinned.iter_mut().for_each(|pinned| *pinned = false);
+    }
+    let mut evicted = Vec::new();
+    while self.mapping.len() > capacity {
+      match self.victim() {
+        Some((_, Some(key))) => evicted.push(key),
+        Some((_, None)) => (),
+        None => break,
+      }
+    }
+    let relocated = relocate(&mut self.mapping, capacity);
+    for &(from, to) in relocated.iter() {
+      let referenced = *self.clock[from].get_mut();
+      *self.clock[to].get_mut() = referenced;
+      self.pinned[to] = self.pinned[from];
+    }
+    self.clock.resize_with(capacity, || AtomicBool::new(false));
+    self.pinned.resize(capacity, false);
+    self.free = free_slots(&self.mapping, capacity);
+    self.capacity = capacity;
+    // the hand may point past the end of the shrunk clock
+    if self.current_pos >= capacity {
+      self.current_pos = 0;
+    }
+    Resized { evicted, relocated }
+  }
+
+  fn pin(&mut self, index: usize, pinned: bool) -> bool {
+    if !self.mapping.contains_key(&index) {
+      return false;
+    }
+    if pinned && !self.pinned[index] && self.pinned.iter().filter(|pinned| **pinned).count() + 1 >= self.capacity {
+      return false;
+    }
+    self.pinned[index] = pinned;
+    true
   }
 }
 
+#[cfg(test)]
 mod tests {
-  #[allow(unused_imports)]
-  use super::{ClockEvictor, Evictor};
+  use super::{ClockEvictor, Evictor, Ordering, Resized};
+  use proptest::prelude::*;
 
   #[test]
   fn test_it_works() {
@@ -103,15 +521,209 @@ mod tests {
 
     evictor.touch(3);
 
-    assert_eq!(evictor.add("11"), (0, Some("9")));
-    assert_eq!(evictor.add("12"), (1, Some("6")));
+    assert_eq!(evictor.add("11"), (1, Some("6")));
+    assert_eq!(evictor.add("12"), (3, Some("8")));
+  }
+
+  #[test]
+  fn test_hand_and_reference_bits() {
+    let mut evictor = ClockEvictor::new(3);
+    for key in 1..=3 {
+      evictor.add(key);
+    }
+    assert_eq!(evictor.hand_position(), 0);
+    assert_eq!(evictor.reference_bits(), vec![true, true, true]);
+
+    assert_eq!(evictor.add(4), (0, Some(1)));
+    assert_eq!(evictor.hand_position(), 1);
+    assert_eq!(evictor.reference_bits(), vec![true, false, false]);
+
+    evictor.touch(1);
+    evictor.touch(2);
+    assert_eq!(evictor.reference_bits(), vec![true, true, true]);
+    assert_eq!(evictor.add(5), (1, Some(2)));
+    assert_eq!(evictor.hand_position(), 2);
+    assert_eq!(evictor.reference_bits(), vec![false, true, false]);
+  }
+
+  #[test]
+  fn test_candidate_is_next_victim() {
+    let mut evictor = ClockEvictor::new(2);
+    assert_eq!(evictor.candidate(), None);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.candidate(), Some(&"1"));
+    assert_eq!(evictor.candidate(), Some(&"1"));
+    assert_eq!(evictor.add("3"), (0, Some("1")));
+    evictor.touch(1);
+    assert_eq!(evictor.candidate(), Some(&"2"));
+    assert_eq!(evictor.add("4"), (1, Some("2")));
+  }
+
+  #[test]
+  fn test_candidate_leaves_reference_bits_alone() {
+    let mut evictor = ClockEvictor::new(3);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    evictor.clock[1].store(false, Ordering::Relaxed);
+    assert_eq!(evictor.candidate(), Some(&"2"));
+    assert_eq!(evictor.reference_bits(), vec![true, false, true]);
+    assert_eq!(evictor.hand_position(), 0);
+    assert_eq!(evictor.add("4"), (1, Some("2")));
+  }
+
+  #[test]
+  fn test_removed_slots_get_reused() {
+    let mut evictor = ClockEvictor::new(3);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(evictor.remove(1), Some("2"));
+    assert_eq!(evictor.remove(1), None);
+    assert_eq!(evictor.candidate(), None);
+    assert_eq!(evictor.add("4"), (1, None));
+    assert_eq!(evictor.remove(0), Some("1"));
+    assert_eq!(evictor.remove(2), Some("3"));
+    assert_eq!(evictor.add("5"), (2, None));
+    assert_eq!(evictor.add("6"), (0, None));
+    assert_eq!(evictor.add("7"), (0, Some("6")));
+  }
+
+  #[test]
+  fn test_full_circle_evicts_at_hand() {
+    let mut evictor = ClockEvictor::new(3);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(evictor.add("4"), (0, Some("1")));
+    (0..3).for_each(|index| evictor.touch(index));
+    assert_eq!(evictor.add("5"), (1, Some("2")));
+    (0..3).for_each(|index| evictor.touch(index));
+    assert_eq!(evictor.add("6"), (2, Some("3")));
+    assert_eq!(evictor.add("7"), (0, Some("4")));
+    assert_eq!(evictor.add("8"), (1, Some("5")));
+  }
+
+  #[test]
+  fn test_slots_stay_within_capacity() {
+    let mut evictor = ClockEvictor::new(4);
+    for key in 0..4 {
+      evictor.add(key);
+    }
+    for key in 4..100 {
+      let index = (key * 7) % 4;
+      evictor.remove(index);
+      assert_eq!(evictor.add(key), (index, None));
+      let (index, _) = evictor.add(key + 100);
+      assert!(index < 4);
+    }
+    assert_eq!(evictor.clock.len(), 4);
+    assert_eq!(evictor.mapping.len(), 4);
+    assert!(evictor.free.is_empty());
+  }
+
+  #[test]
+  fn test_evict_when_not_full() {
+    let mut evictor = ClockEvictor::new(4);
+    assert_eq!(evictor.evict(), None);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.evict(), Some("1"));
+    assert_eq!(evictor.add("3").1, None);
+    assert_eq!(evictor.evict(), Some("2"));
+    assert_eq!(evictor.evict(), Some("3"));
+    assert_eq!(evictor.evict(), None);
+  }
+
+  #[test]
+  fn test_resize_evicts() {
+    let mut evictor = ClockEvictor::new(3);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(
+      evictor.resize(1),
+      Resized {
+        evicted: vec!["1", "2"],
+        relocated: vec![(2, 0)],
+      }
+    );
+    assert_eq!(evictor.capacity(), 1);
+    assert_eq!(evictor.add("4"), (0, Some("3")));
+  }
+
+  #[test]
+  fn test_resize_relocates() {
+    let mut evictor = ClockEvictor::new(4);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(evictor.add("4"), (3, None));
+    assert_eq!(evictor.add("5"), (0, Some("1"))); // hand moves to 1
+    evictor.remove(0);
+    evictor.remove(1);
+    assert_eq!(
+      evictor.resize(2),
+      Resized {
+        evicted: vec![],
+        relocated: vec![(2, 0), (3, 1)],
+      }
+    );
+    assert_eq!(evictor.add("6"), (1, Some("4")));
+
+    assert_eq!(
+      evictor.resize(3),
+      Resized {
+        evicted: vec![],
+        relocated: vec![],
+      }
+    );
+    assert_eq!(evictor.add("7"), (2, None));
+    assert_eq!(evictor.add("8"), (0, Some("3")));
+  }
+
+  #[test]
+  #[cfg(feature = "eviction-metrics")]
+  fn test_metrics() {
+    let mut evictor = ClockEvictor::new(3);
+    evictor.add("1");
+    evictor.add("2");
+    evictor.add("3");
+    evictor.touch(1);
+    assert_eq!(evictor.metrics().touches, 4);
+    assert_eq!(evictor.metrics().average_scan_length(), 0.0);
+
+    assert_eq!(evictor.add("4"), (0, Some("1")));
+    let metrics = evictor.metrics();
+    assert_eq!(metrics.evictions, 1);
+    assert_eq!(metrics.hand_advances, 3);
+    assert_eq!(metrics.scanned, 4);
+    assert_eq!(metrics.touches, 5);
+    assert_eq!(metrics.average_scan_length(), 4.0);
+    assert_eq!(metrics.longest_sweep, 4);
+  }
+
+  #[test]
+  #[cfg(feature = "eviction-metrics")]
+  fn test_sweeps_are_bounded() {
+    // the worst case: a single, touched, key right before the hand, all other slots being free
+    let mut evictor = ClockEvictor::new(4);
+    for key in 0..4 {
+      evictor.add(key);
+    }
+    for index in 0..3 {
+      evictor.remove(index);
+    }
+    assert_eq!(evictor.evict(), Some(3));
+    assert_eq!(evictor.metrics().longest_sweep, 8);
   }
 
   #[test]
   fn test_hammered_key_never_evicted() {
     let mut evictor = ClockEvictor::new(4);
     assert_eq!(evictor.add(1), (0, None));
-    evictor.touch(1); // todo, pathological case where the clock goes full circle!
+    evictor.touch(1);
     assert_eq!(evictor.add(2), (1, None));
     evictor.touch(1);
     assert_eq!(evictor.add(3), (2, None));
@@ -122,4 +734,73 @@ mod tests {
       assert_ne!(evictor.add(x), (1, Some(2)));
     }
   }
+
+  #[test]
+  fn test_pinned_keys_never_evicted() {
+    let mut evictor = ClockEvictor::new(3);
+    evictor.add(1);
+    evictor.add(2);
+    evictor.add(3);
+    assert!(evictor.pin(0, true));
+    assert!(evictor.pin(1, true));
+    // the last unpinned slot can't be pinned
+    assert!(!evictor.pin(2, true));
+    for key in 4..10 {
+      assert_eq!(evictor.add(key), (2, Some(key - 1)));
+    }
+    assert!(evictor.pin(1, false));
+    assert_eq!(evictor.add(10), (1, Some(2)));
+    assert_eq!(evictor.evict(), Some(9));
+    assert_eq!(evictor.evict(), Some(10));
+    assert_eq!(evictor.evict(), None);
+  }
+
+  #[derive(Debug, Clone)]
+  enum Op {
+    Add(u32),
+    Touch(usize),
+    Evict,
+    Remove(usize),
+    Pin(usize, bool),
+  }
+
+  proptest! {
+    #[test]
+    fn test_hand_stays_within_the_clock(
+      capacity in 1usize..16,
+      ops in prop::collection::vec(
+        prop_oneof![
+          any::<u32>().prop_map(Op::Add),
+          (0usize..16).prop_map(Op::Touch),
+          Just(Op::Evict),
+          (0usize..16).prop_map(Op::Remove),
+          (0usize..16, any::<bool>()).prop_map(|(index, pinned)| Op::Pin(index, pinned)),
+        ],
+        0..256,
+      ),
+    ) {
+      let mut evictor = ClockEvictor::new(capacity);
+      for op in ops {
+        match op {
+          Op::Add(key) => {
+            let (index, _) = evictor.add(key);
+            prop_assert!(index < capacity);
+          }
+          Op::Touch(index) => evictor.touch(index % capacity),
+          Op::Evict => {
+            evictor.evict();
+          }
+          Op::Remove(index) => {
+            evictor.remove(index % capacity);
+          }
+          Op::Pin(index, pinned) => {
+            evictor.pin(index % capacity, pinned);
+          }
+        }
+        prop_assert!(evictor.current_pos < capacity);
+        #[cfg(feature = "eviction-metrics")]
+        prop_assert!(evictor.metrics().longest_sweep <= 2 * capacity as u64);
+      }
+    }
+  }
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 #![cfg_attr(feature = "unstable", feature(test))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 //! # Cachers [WIP!]
 //!
@@ -29,27 +30,134 @@
 //!
 //! This is all very much _work in progress_. Fundamentally, it's just me having fun with Rust...
 //!
+//! ## Features
+//!
+//! | Feature            | Default | What it adds                                                        |
+//! |--------------------|---------|---------------------------------------------------------------------|
+//! | `sync`             | yes     | The blocking `CacheThrough` and `ShardedCache`, on `std` only       |
+//! | `async`            | no      | The `AsyncCacheThrough`, whose `populating_fn` returns a future,    |
+//! |                    |         | the `AsyncShardedCache`, and `CacheThrough::get_blocking_async`     |
+//! | `no_std`           | no      | Builds the `CacheThrough` on `alloc` only, in place of `sync`       |
+//! | `prometheus-text`  | no      | `CacheThrough::render_prometheus`                                   |
+//! | `eviction-metrics` | no      | `CacheThrough::eviction_metrics`                                    |
+//! | `debug-internals`  | no      | `CacheThrough::clock_hand_position` and `clock_reference_bits`      |
+//! | `metrics`          | no      | `CacheThroughBuilder::metrics_prefix`, reporting to `metrics`       |
+//! | `serde`            | no      | A serializable `CacheSnapshot`, to persist `CacheThrough::snapshot` |
+//!
+//! Only `async` depends on `futures`, so that users of the blocking cache don't pull in any async
+//! dependency. With `async`, populating an entry doesn't block other tasks, those interested in
+//! the same key wait for the future in flight.
+//!
+//! Disabling the default features, in favor of `no_std`, builds the `CacheThrough` using spin
+//! locks. There is no clock to expire entries with, nor threads to refresh them on, so that the
+//! time-to-live options are left out, as are the `AsyncCacheThrough` and the `ShardedCache`.
+//!
 
+#[cfg(all(not(feature = "std"), not(feature = "no_std")))]
+compile_error!("either the `std` or the `no_std` feature needs to be enabled");
+
+extern crate alloc;
+
+mod admission;
+#[cfg(feature = "async")]
+mod async_sharded;
+#[cfg(feature = "async")]
 pub mod asynchronous;
+mod builder;
+mod cache_ref;
+mod entry;
+mod error;
+mod events;
 mod eviction;
+mod loader;
+mod namespace;
+mod platform;
+mod registry;
 mod segment;
+#[cfg(feature = "async")]
 mod segment2;
+#[cfg(feature = "std")]
+mod sharded;
+mod size_aware;
+mod slru;
+#[cfg(feature = "serde")]
+mod snapshot;
+#[cfg(feature = "async")]
+mod softlock;
+mod source;
+mod stats;
+mod two_queue;
+mod weigher;
+mod writer;
 
-use std::ops::Fn;
-use std::sync::{Arc, RwLock};
+use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
+#[cfg(feature = "prometheus-text")]
+use alloc::format;
+#[cfg(feature = "prometheus-text")]
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::iter::FromIterator;
+use core::ops::Fn;
+use core::ptr::NonNull;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "std")]
+use std::sync::Condvar;
+#[cfg(feature = "std")]
+use std::thread::{self, JoinHandle};
 
+use crate::loader::BoxedLoader;
+use crate::platform::{DefaultHasher, Mutex, RandomState, RwLock, RwLockReadGuard, Semaphore, SemaphorePermit};
+use crate::registry::WriteGuard;
 use crate::segment::Segment;
+use crate::stats::Stats;
+
+pub use crate::admission::{AdmissionPolicy, TinyLfu};
+#[cfg(feature = "async")]
+pub use crate::async_sharded::AsyncShardedCache;
+#[cfg(feature = "async")]
+pub use crate::asynchronous::CacheThrough as AsyncCacheThrough;
+pub use crate::builder::CacheThroughBuilder;
+pub use crate::cache_ref::CacheRef;
+pub use crate::entry::Entry;
+pub use crate::error::{ConfigError, Timeout, TryUpdateError, WouldBlock, WriteError};
+pub use crate::events::CacheEvent;
+#[cfg(feature = "eviction-metrics")]
+pub use crate::eviction::EvictionMetrics;
+pub use crate::eviction::{Eviction, Evictor, Resized};
+pub use crate::loader::Loader;
+pub use crate::namespace::Namespaced;
+pub use crate::registry::CacheRegistry;
+pub use crate::segment::EntryMeta;
+#[cfg(feature = "std")]
+pub use crate::sharded::ShardedCache;
+#[cfg(feature = "serde")]
+pub use crate::snapshot::CacheSnapshot;
+pub use crate::source::Source;
+pub use crate::weigher::{Unweighted, Weigher};
+pub use crate::writer::Writer;
 
 /// A thread-safe cache that will populate entries on misses using the provided
 /// function, aka a cache-through cache. Uses interior mutability, which means you can simply
-/// share a non-mutable reference to both read & insert/update entries to the cache.
+/// share a non-mutable reference to both read & insert/update entries to the cache. Cloning it is
+/// cheap too, as clones are handles to the same entries, sharing their configuration & statistics.
 ///
 /// The `CacheThrough` cache uses clock eviction to free elements when it reaches capacity.
 ///
 ///
-/// In the example below, we create a `CacheThrough` shared across the main thread and another
-/// thread we span ourselves. The main thread will populate the `42` entry and the other thread
-/// will read it back:
+/// In the example below, we create a `CacheThrough` cloned into another thread we span ourselves.
+/// The main thread will populate the `42` entry and the other thread will read it back:
 ///
 /// ```
 /// use std::sync::{Arc, Barrier};
@@ -57,7 +165,7 @@ use crate::segment::Segment;
 ///
 /// use cachers::CacheThrough;
 ///
-/// let cache: Arc<CacheThrough<i32, String>> = Arc::new(CacheThrough::new(100));
+/// let cache: CacheThrough<i32, String> = CacheThrough::new(100);
 /// let other_cache = cache.clone();
 /// let our_key = 42;
 ///
@@ -66,23 +174,96 @@ use crate::segment::Segment;
 ///
 /// let t = thread::spawn(move || {
 ///   other_barrier.wait(); // wait for main thread to populate
-///   let value = other_cache.get(our_key, |_| unimplemented!() ); // entry should be there!
+///   let value = other_cache.get(&our_key, |_| unimplemented!() ); // entry should be there!
 ///   assert_eq!(*value.unwrap(), "42");
 /// });
 ///
-/// let value = cache.get(our_key, |key| Some(key.to_string()) ); // miss, so populating
+/// let value = cache.get(&our_key, |key| Some(key.to_string()) ); // miss, so populating
 /// assert_eq!(*value.unwrap(), "42");
 /// barrier.wait(); // let the other thread proceed
 ///
 /// t.join().unwrap();
 /// ```
-pub struct CacheThrough<K, V> {
-  data: RwLock<Segment<K, V>>,
+pub struct CacheThrough<K, V, S = RandomState> {
+  data: Arc<RwLock<Segment<K, V, S>>>,
+  /// The locks populating keys hash to, so that misses on different keys are populated
+  /// concurrently, while a key is only ever populated by one thread at a time.
+  populating: Arc<[Mutex<()>]>,
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  executor: Option<Arc<Executor>>,
+  loader: Option<Arc<BoxedLoader<K, V>>>,
+  /// Caps the number of misses populated at once, see `CacheThroughBuilder::max_concurrent_loads`.
+  loads: Option<Arc<Semaphore>>,
+  reject_excess_loads: bool,
+  stats: Arc<Stats>,
+  #[cfg(feature = "std")]
+  timed_loads: Arc<Mutex<TimedLoads<K, V>>>,
+}
+
+impl<K, V, S> Clone for CacheThrough<K, V, S> {
+  fn clone(&self) -> Self {
+    CacheThrough {
+      data: self.data.clone(),
+      populating: self.populating.clone(),
+      executor: self.executor.clone(),
+      loader: self.loader.clone(),
+      loads: self.loads.clone(),
+      reject_excess_loads: self.reject_excess_loads,
+      stats: self.stats.clone(),
+      #[cfg(feature = "std")]
+      timed_loads: self.timed_loads.clone(),
+    }
+  }
+}
+
+/// The number of locks misses are populated under, two keys hashing to the same one being
+/// populated one after the other.
+const POPULATING_LOCKS: usize = 64;
+
+/// The keys `get_with_timeout` has a worker loading.
+#[cfg(feature = "std")]
+type TimedLoads<K, V> = HashMap<K, Arc<TimedLoad<V>>>;
+
+/// A load `get_with_timeout` handed to a worker, whose outcome all the calls for its key wait on.
+#[cfg(feature = "std")]
+struct TimedLoad<V> {
+  /// The value loaded once the worker is done, or `Err` should the `populating_fn` have panicked.
+  outcome: std::sync::Mutex<Option<Result<Option<Arc<V>>, ()>>>,
+  done: Condvar,
 }
 
+#[cfg(feature = "std")]
+impl<V> TimedLoad<V> {
+  fn new() -> TimedLoad<V> {
+    TimedLoad {
+      outcome: std::sync::Mutex::new(None),
+      done: Condvar::new(),
+    }
+  }
+
+  fn finish(&self, outcome: Result<Option<Arc<V>>, ()>) {
+    *self.outcome.lock().unwrap() = Some(outcome);
+    self.done.notify_all();
+  }
+
+  /// Waits for the worker to be done for up to `timeout`, returning `None` should it not be.
+  fn wait(&self, timeout: Duration) -> Option<Result<Option<Arc<V>>, ()>> {
+    let outcome = self.outcome.lock().unwrap();
+    let (outcome, _) = self
+      .done
+      .wait_timeout_while(outcome, timeout, |outcome| outcome.is_none())
+      .unwrap();
+    outcome.clone()
+  }
+}
+
+/// Runs the background refreshes of a `CacheThrough` configured to refresh entries ahead of
+/// their expiry, e.g. by spawning them on a thread pool.
+type Executor = Box<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>;
+
 impl<K, V> CacheThrough<K, V>
 where
-  K: std::cmp::Eq + std::hash::Hash + Copy,
+  K: core::cmp::Eq + core::hash::Hash + Clone,
 {
   /// Creates a new `CacheThrough` instance of the given `capacity`
   ///
@@ -91,9 +272,177 @@ where
   ///
   /// let cache = CacheThrough::<usize, String>::new(100);
   /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, see `try_build` for the non-panicking version.
   pub fn new(capacity: usize) -> CacheThrough<K, V> {
+    CacheThrough::builder().capacity(capacity).build()
+  }
+
+  /// Creates a new `CacheThrough` instance of the given `capacity`, validating the configuration
+  /// first. Returns the `ConfigError` describing the problem, should it be invalid.
+  ///
+  /// ```
+  /// use cachers::{CacheThrough, ConfigError};
+  ///
+  /// let cache = CacheThrough::<usize, String>::try_build(0);
+  /// assert_eq!(cache.err(), Some(ConfigError::ZeroCapacity));
+  /// ```
+  pub fn try_build(capacity: usize) -> Result<CacheThrough<K, V>, ConfigError> {
+    CacheThrough::builder().capacity(capacity).try_build()
+  }
+
+  /// Starts configuring a new `CacheThrough`, see `CacheThroughBuilder` for all options.
+  pub fn builder() -> CacheThroughBuilder<K, V> {
+    CacheThroughBuilder::new()
+  }
+
+  /// Creates a new `CacheThrough` instance of the given `capacity`, that consults the
+  /// `AdmissionPolicy` before evicting an entry to make room for a new one. Should the policy
+  /// reject the new entry, its value is still returned to the caller, but it isn't cached.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, as `new` does.
+  pub fn with_admission_policy<P>(capacity: usize, policy: P) -> CacheThrough<K, V>
+  where
+    P: AdmissionPolicy + Send + Sync + 'static,
+  {
+    CacheThrough::builder()
+      .capacity(capacity)
+      .admission_policy(policy)
+      .build()
+  }
+
+  /// Creates a new `CacheThrough` instance that evicts entries with the given `evictor`, e.g. a
+  /// custom `Evictor` picked at runtime, rather than one of the built-in `Eviction` strategies.
+  /// The cache's capacity is the `evictor`'s.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, as `new` does.
+  pub fn with_boxed_strategy(evictor: Box<dyn Evictor<K> + Send + Sync>) -> CacheThrough<K, V> {
+    CacheThrough::builder().evictor(evictor).build()
+  }
+
+  /// Creates a new `CacheThrough` instance of the given `capacity`, whose entries expire
+  /// `time_to_live` after they were last populated or updated. Expired entries are treated as
+  /// absent, i.e. `get` will invoke the `populating_fn` again.
+  ///
+  /// ```
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_secs(60));
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, as `new` does.
+  #[cfg(feature = "std")]
+  pub fn with_time_to_live(capacity: usize, time_to_live: Duration) -> CacheThrough<K, V> {
+    CacheThrough::builder()
+      .capacity(capacity)
+      .time_to_live(time_to_live)
+      .build()
+  }
+
+  /// Creates a new `CacheThrough` instance of the given `capacity`, whose entries expire after
+  /// `time_to_live`, but get refreshed ahead of it by `get_refreshing` once they enter the
+  /// `refresh_window`. The refreshes are handed to the `executor`, which is expected to run them
+  /// in the background, e.g. on a thread of its own.
+  ///
+  /// ```
+  /// use std::thread;
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_refresh_ahead(
+  ///   100,
+  ///   Duration::from_secs(60),
+  ///   Duration::from_secs(10),
+  ///   |refresh| {
+  ///     thread::spawn(refresh);
+  ///   },
+  /// );
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, as `new` does, or if the `refresh_window` isn't
+  /// shorter than the `time_to_live`.
+  #[cfg(feature = "std")]
+  pub fn with_refresh_ahead<E>(
+    capacity: usize,
+    time_to_live: Duration,
+    refresh_window: Duration,
+    executor: E,
+  ) -> CacheThrough<K, V>
+  where
+    E: Fn(Box<dyn FnOnce() + Send>) + Send + Sync + 'static,
+  {
+    CacheThrough::builder()
+      .capacity(capacity)
+      .time_to_live(time_to_live)
+      .refresh_ahead(refresh_window, executor)
+      .build()
+  }
+}
+
+impl<K, V, S> CacheThrough<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone,
+  S: BuildHasher,
+{
+  /// Creates a new `CacheThrough` instance of the given `capacity`, that uses `hasher` to hash
+  /// its keys. The default `RandomState` protects against HashDoS attacks, but is slower than
+  /// other hashers for small keys, e.g. integers.
+  ///
+  /// ```
+  /// use std::collections::hash_map::DefaultHasher;
+  /// use std::hash::BuildHasherDefault;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String, _>::with_hasher(100, BuildHasherDefault::<DefaultHasher>::default());
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics if the configuration is invalid, as `new` does.
+  pub fn with_hasher(capacity: usize, hasher: S) -> CacheThrough<K, V, S> {
+    CacheThrough::builder().capacity(capacity).hasher(hasher).build()
+  }
+
+  /// Whether a thread panicked while holding one of the cache's locks, e.g. in a `populating_fn`.
+  /// The cache keeps on operating regardless, as every operation leaves the entries in a
+  /// consistent state before invoking user-provided functions.
+  pub fn is_poisoned(&self) -> bool {
+    self.data.is_poisoned() || self.populating.iter().any(Mutex::is_poisoned)
+  }
+
+  fn read(&self) -> RwLockReadGuard<'_, Segment<K, V, S>> {
+    self.data.read()
+  }
+
+  fn write(&self) -> WriteGuard<'_, K, V, S> {
+    WriteGuard::new(self.data.write())
+  }
+
+  fn from_segment(segment: Segment<K, V, S>, executor: Option<Executor>) -> CacheThrough<K, V, S> {
     CacheThrough {
-      data: RwLock::new(Segment::new(capacity)),
+      data: Arc::new(RwLock::new(segment)),
+      populating: (0..POPULATING_LOCKS).map(|_| Mutex::new(())).collect(),
+      executor: executor.map(Arc::new),
+      loader: None,
+      loads: None,
+      reject_excess_loads: false,
+      stats: Arc::new(Stats::default()),
+      #[cfg(feature = "std")]
+      timed_loads: Arc::new(Mutex::new(HashMap::new())),
     }
   }
 
@@ -106,237 +455,3202 @@ where
   /// Once `populating_fn` has returned `Some<V>`, the other threads waiting for the entry to
   /// be populated will get the `Arc<V>` returned.
   ///
+  /// The `populating_fn` is invoked without holding the cache's write lock, only one of a fixed
+  /// set of locks keys are hashed to: populating a key doesn't block readers, nor threads
+  /// populating other keys, unless they happen to hash to the same lock. Should the key get
+  /// updated while it is being populated, the update wins and is what all threads get back.
+  ///
   /// In the case where `populating_fn` yield no results (i.e. returns `Option::None`), no
   /// guarantees are made about how many times the `populating_fn` may be called.
   ///
-  /// If you want to cache misses, consider wrapping your `V` into an `Option`.
-  pub fn get<F>(&self, key: K, populating_fn: F) -> Option<Arc<V>>
+  /// If you want to cache misses, use `get_caching_misses`, or configure a `negative_time_to_live`
+  /// on the `CacheThroughBuilder`.
+  ///
+  /// Should the cache have a `Loader`, it is consulted first and `populating_fn` only gets invoked
+  /// for the keys it doesn't hold.
+  ///
+  /// Much like `HashMap::get`, the `key` may be any borrowed form of `K`, e.g. a `&str` for
+  /// `String` keys. An owned `K` is only created from it when the cache needs populating.
+  pub fn get<Q, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
   where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
     F: Fn(&K) -> Option<V>,
   {
-    if let Some(value) = self.data.read().unwrap().get(&key) {
-      return Some(value);
-    }
-    let option = self.data.write();
-    if option.is_ok() {
-      let mut guard = option.unwrap();
-      return guard.get_or_populate(key, populating_fn);
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return value;
     }
-    None
+    self.populate(key.to_owned(), populating_fn, false)
   }
 
-  /// Updates an entry in the cache, or populates it if absent.
+  /// Same as `get`, but for an async `populating_fn`, whose future gets run to completion on the
+  /// calling thread, e.g. to share async populating logic with synchronous code without using an
+  /// `AsyncCacheThrough`. The future is polled by a minimal executor, `futures`' `block_on`,
+  /// rather than by a runtime such as Tokio.
   ///
-  /// The update can be an actual update or a remove, should the `updating_fn` return a `None`.
-  /// The `updating_fn` receives the `key`, as well as an `Option<Arc<V>>` which holds the previous
-  /// value for the `key`, which would be `None` if the function is about to populate the cache.
+  /// This blocks the calling thread until the future resolves, holding the lock `key` hashes to.
+  /// Never call it from within an async runtime: the future may depend on tasks that the blocked
+  /// thread would otherwise run, which then deadlocks, and so does a future depending on resources
+  /// of that runtime, e.g. its timers or I/O reactor, which `block_on` doesn't drive.
   ///
-  /// It is guaranteed that the mapping will not be altered by another thread while the
-  /// `populating_fn` executes.
-  pub fn update<F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let value = cache.get_blocking_async(&42, |key| {
+  ///   let key = *key;
+  ///   async move { Some(key.to_string()) }
+  /// });
+  /// assert_eq!(*value.unwrap(), "42");
+  /// ```
+  #[cfg(feature = "async")]
+  pub fn get_blocking_async<Q, Fut, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
   where
-    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Fut,
+    Fut: core::future::Future<Output = Option<V>>,
   {
-    self.data.write().unwrap().update(key, updating_fn)
-  }
-
-  /// Removes the entry for `key` from the cache.
-  /// This is the equivalent of `cache.update(key, |_, _| None)`. Consider this a convenience method.
-  pub fn remove(&self, key: K) {
-    self.data.write().unwrap().update(key, |_, _| None);
+    self.get(key, |key| futures::executor::block_on(populating_fn(key)))
   }
 
-  #[cfg(test)]
-  fn len(&self) -> usize {
-    self.data.read().unwrap().len()
+  /// Same as `get`, but also reports where the value came from: the cache itself, this very call
+  /// invoking `populating_fn`, or another thread populating the same `key` concurrently, e.g. to
+  /// set a response header accordingly.
+  ///
+  /// ```
+  /// use cachers::{CacheThrough, Source};
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let (value, source) = cache.get_reporting(&42, |key| Some(key.to_string()));
+  /// assert_eq!((value.unwrap().as_str(), source), ("42", Source::Loaded));
+  /// let (value, source) = cache.get_reporting(&42, |_| unreachable!());
+  /// assert_eq!((value.unwrap().as_str(), source), ("42", Source::Hit));
+  /// ```
+  pub fn get_reporting<Q, F>(&self, key: &Q, populating_fn: F) -> (Option<Arc<V>>, Source)
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V>,
+  {
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return (value, Source::Hit);
+    }
+    self.populate_reporting(key.to_owned(), populating_fn, false)
   }
-}
-
-#[cfg(test)]
-mod tests {
-  use super::CacheThrough;
-  use std::sync::Arc;
 
-  fn test_cache() -> CacheThrough<i32, String> {
-    CacheThrough::new(3)
+  /// Same as `get`, but caches a `None` returned by `populating_fn` as a miss, which takes up an
+  /// entry of the cache's capacity: later calls for that `key` return `None` without invoking
+  /// `populating_fn` again, until the miss expires, after the cache's `negative_time_to_live` if
+  /// any, or is evicted, updated or removed.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// assert_eq!(cache.get_caching_misses(&42, |_| None), None);
+  /// assert_eq!(cache.get_caching_misses(&42, |_| unreachable!()), None);
+  /// assert_eq!(cache.len(), 1);
+  /// ```
+  pub fn get_caching_misses<Q, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V>,
+  {
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return value;
+    }
+    self.populate(key.to_owned(), populating_fn, true)
   }
 
-  #[test]
-  fn hit_populates() {
-    let cache: CacheThrough<i32, String> = test_cache();
-    let our_key = 42;
-    {
-      let value = cache.get(our_key, populate);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+  /// Same as `get`, but for a `populating_fn` that needs mutable access to its state, e.g. a
+  /// reusable buffer or a random number generator. Such a `populating_fn` is invoked at most once,
+  /// on a miss, while holding the cache's write lock: unlike with `get`, populating the `key`
+  /// blocks all other threads, readers included, so it should be quick. As with `get`, threads
+  /// missing on the same `key` wait for it, and it counts towards `max_concurrent_loads`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let mut loads = 0;
+  /// let mut populating_fn = |key: &usize| {
+  ///   loads += 1;
+  ///   Some(key.to_string())
+  /// };
+  /// cache.get_mut_fn(&42, &mut populating_fn);
+  /// cache.get_mut_fn(&42, &mut populating_fn);
+  /// cache.get_mut_fn(&7, &mut populating_fn);
+  /// assert_eq!(loads, 2);
+  /// ```
+  pub fn get_mut_fn<Q, F>(&self, key: &Q, mut populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: FnMut(&K) -> Option<V>,
+  {
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return value;
     }
-
-    {
-      let value = cache.get(our_key, do_not_invoke);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+    self.stats.miss();
+    let owned = key.to_owned();
+    let _populating = self.populating_lock(&owned).lock();
+    // another thread may have populated the key while we were waiting for the lock
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.coalesced();
+      return value;
     }
+    let _permit = self.load_permit().ok()?;
+    self.write().get_or_populate(owned, |key| {
+      match self.loader.as_ref().and_then(|loader| loader.load(key)) {
+        Some(value) => Some(value),
+        None => self.stats.time_load(|| populating_fn(key)),
+      }
+    })
   }
 
-  #[test]
-  fn miss_populates_not() {
-    let cache: CacheThrough<i32, String> = test_cache();
-    let our_key = 42;
-    {
-      let value = cache.get(our_key, miss);
-      assert_eq!(value, None);
-      assert_eq!(cache.len(), 0);
-    }
-
-    {
-      let value = cache.get(our_key, populate);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
-      cache.get(2, populate);
-      cache.get(3, populate);
-      cache.get(4, populate);
+  /// Same as `get`, but for a `key` that may already be owned, which then gets moved into the
+  /// cache on a miss rather than cloned. Borrowed keys are only turned into an owned `K` on
+  /// misses, so that hits never allocate either way.
+  ///
+  /// ```
+  /// use std::borrow::Cow;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<String, usize>::new(100);
+  /// let key = "42".to_string();
+  /// assert_eq!(*cache.get_cow(Cow::<str>::Owned(key), |key| key.parse().ok()).unwrap(), 42);
+  /// assert_eq!(*cache.get_cow(Cow::Borrowed("42"), |_| unreachable!()).unwrap(), 42);
+  /// ```
+  pub fn get_cow<Q, F>(&self, key: Cow<'_, Q>, populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V>,
+  {
+    if let Some(value) = self.read().get_cached(key.as_ref()) {
+      self.stats.hit();
+      return value;
     }
+    self.populate(key.into_owned(), populating_fn, false)
   }
 
-  #[test]
-  fn update_populates() {
-    let cache: CacheThrough<i32, String> = test_cache();
-    let our_key = 42;
-
-    {
-      let value = cache.update(our_key, upsert);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+  /// Same as `get`, but gives up waiting on the `populating_fn` after `timeout`, returning a
+  /// `Timeout` error instead, e.g. for a backend that can hang. The `populating_fn` is invoked on
+  /// a worker thread of its own, which can't be interrupted: should it eventually return, its
+  /// value populates the cache, unless another thread populated the key in the meantime, whose
+  /// value is then kept. The cache's `Loader`, if any, isn't consulted.
+  ///
+  /// Once timed out, the key can be populated by other threads, e.g. with `get`, concurrently
+  /// with the `populating_fn` still in flight. Later calls to `get_with_timeout` for the key wait
+  /// on that worker rather than spawning another one, so that a hanging backend ties up at most
+  /// one thread per key.
+  ///
+  /// ```
+  /// use std::time::Duration;
+  ///
+  /// use cachers::{CacheThrough, Timeout};
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let hanging = cache.get_with_timeout(&42, Duration::from_millis(10), |key| {
+  ///   std::thread::sleep(Duration::from_secs(1));
+  ///   Some(key.to_string())
+  /// });
+  /// assert_eq!(hanging, Err(Timeout));
+  /// let value = cache.get_with_timeout(&7, Duration::from_secs(1), |key| Some(key.to_string()));
+  /// assert_eq!(*value.unwrap().unwrap(), "7");
+  /// ```
+  ///
+  /// # Panics
+  ///
+  /// Panics should the `populating_fn` panic before `timeout`, as do all the calls waiting on it.
+  #[cfg(feature = "std")]
+  pub fn get_with_timeout<Q, F>(&self, key: &Q, timeout: Duration, populating_fn: F) -> Result<Option<Arc<V>>, Timeout>
+  where
+    K: Borrow<Q> + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: FnOnce(&K) -> Option<V> + Send + 'static,
+  {
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return Ok(value);
     }
-
-    {
-      let value = cache.get(our_key, do_not_invoke);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+    self.stats.miss();
+    let owned = key.to_owned();
+    let _populating = self.populating_lock(&owned).lock();
+    let load = {
+      // workers install their value, and stop taking waiters, under this lock
+      let mut timed_loads = self.timed_loads.lock();
+      if let Some(value) = self.read().get_cached(key) {
+        self.stats.coalesced();
+        return Ok(value);
+      }
+      match timed_loads.get::<K>(&owned) {
+        Some(load) => load.clone(),
+        None => {
+          // rejecting excess loads can't wait, while waiting for a permit counts towards the timeout
+          let permit = match &self.loads {
+            Some(_) if self.reject_excess_loads => match self.load_permit() {
+              Ok(permit) => permit,
+              Err(_) => return Ok(None),
+            },
+            _ => None,
+          };
+          let key = owned;
+          let load = Arc::new(TimedLoad::new());
+          timed_loads.insert(key.clone(), load.clone());
+          let (data, loads, stats, timed_loads, worker_load) = (
+            self.data.clone(),
+            self.loads.clone(),
+            self.stats.clone(),
+            self.timed_loads.clone(),
+            load.clone(),
+          );
+          thread::spawn(move || {
+            let permit = permit.or_else(|| loads.map(|loads| loads.acquire()));
+            let loaded = panic::catch_unwind(AssertUnwindSafe(|| stats.time_load(|| populating_fn(&key))));
+            drop(permit);
+            let mut timed_loads = timed_loads.lock();
+            timed_loads.remove::<K>(&key);
+            match loaded {
+              Ok(value) => {
+                let value = WriteGuard::new(data.write()).get_or_populate(key, |_| value);
+                drop(timed_loads);
+                worker_load.finish(Ok(value));
+              }
+              Err(panicked) => {
+                drop(timed_loads);
+                worker_load.finish(Err(()));
+                panic::resume_unwind(panicked)
+              }
+            }
+          });
+          load
+        }
+      }
+    };
+    match load.wait(timeout) {
+      Some(Ok(value)) => Ok(value),
+      Some(Err(())) => panic!("populating_fn panicked"),
+      None => Err(Timeout),
     }
   }
 
-  #[test]
-  fn update_updates() {
-    let cache: CacheThrough<i32, String> = test_cache();
-    let our_key = 42;
+  /// Populates the given `keys` that are absent from the cache on a background thread, e.g. to
+  /// warm it up with a known hot set, returning a handle to join it on or to be dropped. Keys are
+  /// populated one after the other, as `get` does: concurrent calls for the same key wait for the
+  /// prefetched value, rather than invoking their own `populating_fn`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.prefetch(vec![1, 2, 3], |key| Some(key.to_string())).join().unwrap();
+  /// assert_eq!(cache.len(), 3);
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn prefetch<F>(&self, keys: Vec<K>, populating_fn: F) -> JoinHandle<()>
+  where
+    K: Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    F: Fn(&K) -> Option<V> + Send + 'static,
+  {
+    let cache = self.clone();
+    thread::spawn(move || {
+      for key in keys {
+        if cache.read().get_cached(&key).is_none() {
+          cache.populate(key, &populating_fn, false);
+        }
+      }
+    })
+  }
 
-    {
-      let value = cache.get(our_key, populate);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
-    }
+  /// Retrieves the `V` for the given `key`, computing it with `mapping_fn` should it be absent.
+  /// This is the same as `get`, for those used to the `compute_if_absent` naming: `mapping_fn`
+  /// only ever receives the `key`, never an existing value, and is invoked only once, even if
+  /// multiple threads race to populate the same `key`.
+  ///
+  /// Should `mapping_fn` yield no result (i.e. return `None`), nothing gets cached and no
+  /// guarantees are made about how many times it may be called.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// assert_eq!(*cache.compute_if_absent(&42, |key| Some(key.to_string())).unwrap(), "42");
+  /// assert_eq!(*cache.compute_if_absent(&42, |_| unreachable!()).unwrap(), "42");
+  /// ```
+  pub fn compute_if_absent<Q, F>(&self, key: &Q, mapping_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V>,
+  {
+    self.get(key, mapping_fn)
+  }
 
-    {
-      let value = cache.update(our_key, update);
-      assert_eq!(*value.unwrap(), "42 updated!");
-      assert_eq!(cache.len(), 1);
+  /// Retrieves the `V`s for all the given `keys` at once, in the same order. The values for all
+  /// keys missing from the cache are populated with a single call to `populate_missing`, e.g. so
+  /// that they can be fetched with one batched request to the backend. `populate_missing`
+  /// receives the missing keys and is expected to return their values in the same order.
+  ///
+  /// Hits are collected under the read lock, while the misses are all populated under a single
+  /// acquisition of the write lock, during which `populate_missing` is invoked.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&1, |key| Some(key.to_string()));
+  /// let values = cache.get_many(&[1, 2], |missing| {
+  ///   assert_eq!(missing, &[2]);
+  ///   missing.iter().map(|key| Some(key.to_string())).collect()
+  /// });
+  /// assert_eq!(*values[0].clone().unwrap(), "1");
+  /// assert_eq!(*values[1].clone().unwrap(), "2");
+  /// ```
+  pub fn get_many<F>(&self, keys: &[K], populate_missing: F) -> Vec<Option<Arc<V>>>
+  where
+    F: FnOnce(&[K]) -> Vec<Option<V>>,
+  {
+    // `Some(None)` being a cached miss
+    let mut values: Vec<Option<Option<Arc<V>>>> = {
+      let segment = self.read();
+      keys.iter().map(|key| segment.get_cached(key)).collect()
+    };
+    for value in values.iter() {
+      match value {
+        Some(_) => self.stats.hit(),
+        None => self.stats.miss(),
+      }
+    }
+    if values.iter().all(Option::is_some) {
+      return values.into_iter().map(Option::flatten).collect();
     }
 
-    {
-      let value = cache.get(our_key, do_not_invoke);
-      assert_eq!(*value.unwrap(), "42 updated!");
-      assert_eq!(cache.len(), 1);
+    // the whole batch counts as a single load
+    let permit = match self.load_permit() {
+      Ok(permit) => permit,
+      Err(_) => return values.into_iter().map(Option::flatten).collect(),
+    };
+    let mut segment = self.write();
+    let mut indices = Vec::new();
+    let mut missing = Vec::new();
+    for (index, key) in keys.iter().enumerate() {
+      if values[index].is_none() {
+        // might have been populated while we weren't holding any lock
+        match segment.get_cached(key) {
+          Some(value) => values[index] = Some(value),
+          None => {
+            indices.push(index);
+            missing.push(key.clone());
+          }
+        }
+      }
+    }
+    if !missing.is_empty() {
+      let populated = populate_missing(&missing);
+      drop(permit);
+      for ((index, key), value) in indices.into_iter().zip(missing).zip(populated) {
+        values[index] = Some(segment.get_or_populate(key, |_| value));
+      }
     }
+    values.into_iter().map(Option::flatten).collect()
   }
 
-  #[test]
+  /// Same as `get`, but should the entry for `key` be within the refresh window configured with
+  /// `with_refresh_ahead`, its current value is returned right away while a refresh using the
+  /// `populating_fn` is handed to the executor. Only one refresh is ever in flight per entry, no
+  /// matter how many threads read it concurrently.
+  ///
+  /// Should the refresh yield no value, the current one is kept until it expires.
+  #[cfg(feature = "std")]
+  pub fn get_refreshing<Q, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q> + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V> + Send + 'static,
+  {
+    let hit = self.read().get_or_claim_refresh(key);
+    match hit {
+      Some((value, refresh)) => {
+        self.stats.hit();
+        if let (true, Some(executor)) = (refresh, &self.executor) {
+          let key = key.to_owned();
+          // a refresh rejected for lack of a permit leaves the entry to be claimed again
+          let permit = match &self.loads {
+            Some(_) if self.reject_excess_loads => match self.load_permit() {
+              Ok(permit) => permit,
+              Err(_) => {
+                self.write().refreshed(&key, None);
+                return Some(value);
+              }
+            },
+            _ => None,
+          };
+          let data = self.data.clone();
+          let loads = self.loads.clone();
+          executor(Box::new(move || {
+            let permit = permit.or_else(|| loads.map(|loads| loads.acquire()));
+            let value = populating_fn(&key);
+            drop(permit);
+            WriteGuard::new(data.write()).refreshed(&key, value);
+          }));
+        }
+        Some(value)
+      }
+      None => self.populate(key.to_owned(), populating_fn, false),
+    }
+  }
+
+  /// Refreshes all the entries expiring within `within` at once, e.g. periodically, so that they
+  /// are fetched from the backend in a single round-trip. `refresh_fn` is invoked once with all
+  /// their keys, without holding the cache's lock, and is expected to return their fresh values.
+  /// Entries it returns no value for are removed. Does nothing, should no entry expire soon.
+  ///
+  /// Entries updated while `refresh_fn` runs are overwritten with the refreshed value.
+  ///
+  /// ```
+  /// use std::collections::HashMap;
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_secs(60));
+  /// cache.get(&1, |key| Some(key.to_string()));
+  /// cache.get(&2, |key| Some(key.to_string()));
+  /// cache.refresh_stale(Duration::from_secs(60), |keys| {
+  ///   keys.iter().filter(|key| **key == 1).map(|key| (*key, "fresh".to_string())).collect()
+  /// });
+  /// assert_eq!(*cache.get_if_present(&1).unwrap(), "fresh");
+  /// assert_eq!(cache.get_if_present(&2), None);
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn refresh_stale<F>(&self, within: Duration, refresh_fn: F)
+  where
+    F: FnOnce(&[K]) -> HashMap<K, V>,
+  {
+    let keys = self.read().expiring_keys(within);
+    if keys.is_empty() {
+      return;
+    }
+    let permit = match self.load_permit() {
+      Ok(permit) => permit,
+      Err(_) => return,
+    };
+    let values = refresh_fn(&keys);
+    drop(permit);
+    self.write().refreshed_all(keys, values);
+  }
+
+  /// Same as `get_if_present`, but never blocks: should another thread hold the write lock, e.g.
+  /// while populating the cache, `WouldBlock` is returned instead of waiting for it, so that the
+  /// caller can fall back to reading from the backend directly.
+  ///
+  /// An `Ok(None)` is a genuine miss, as observed while holding the read lock.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(*cache.try_get_if_present(&42).unwrap().unwrap(), "42");
+  /// assert_eq!(cache.try_get_if_present(&7), Ok(None));
+  /// ```
+  pub fn try_get_if_present<Q>(&self, key: &Q) -> Result<Option<Arc<V>>, WouldBlock>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let segment = self.data.try_read().ok_or(WouldBlock)?;
+    match segment.get_cached(key) {
+      Some(value) => {
+        self.stats.hit();
+        Ok(value)
+      }
+      None => {
+        self.stats.miss();
+        Ok(None)
+      }
+    }
+  }
+
+  /// Same as `get`, but for a `populating_fn` that returns an `Arc<V>`, which the cache then holds
+  /// on to as is, rather than wrapping it into another `Arc`. This is useful when `V` is already
+  /// shared outside of the cache: hits return a clone of that very same `Arc<V>`.
+  ///
+  /// ```
+  /// use std::sync::Arc;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let shared = Arc::new("42".to_string());
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let value = cache.get_shared(&42, |_| Some(shared.clone())).unwrap();
+  /// assert!(Arc::ptr_eq(&value, &shared));
+  /// ```
+  pub fn get_shared<Q, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<Arc<V>>,
+  {
+    if let Some(value) = self.read().get_cached(key) {
+      self.stats.hit();
+      return value;
+    }
+    self.populate(key.to_owned(), populating_fn, false)
+  }
+
+  /// Populates `key` on a miss, caching a `None` from `populating_fn` should `cache_misses` be set,
+  /// or the cache have a `negative_time_to_live`.
+  fn populate<T, F>(&self, key: K, populating_fn: F, cache_misses: bool) -> Option<Arc<V>>
+  where
+    T: Into<Arc<V>>,
+    F: Fn(&K) -> Option<T>,
+  {
+    self.populate_reporting(key, populating_fn, cache_misses).0
+  }
+
+  /// Same as `populate`, but also reports whether the value got loaded or coalesced.
+  fn populate_reporting<T, F>(&self, key: K, populating_fn: F, cache_misses: bool) -> (Option<Arc<V>>, Source)
+  where
+    T: Into<Arc<V>>,
+    F: Fn(&K) -> Option<T>,
+  {
+    self.stats.miss();
+    let _populating = self.populating_lock(&key).lock();
+    // another thread may have populated the key while we were waiting for the lock
+    if let Some(value) = self.read().get_cached(&key) {
+      self.stats.coalesced();
+      return (value, Source::Coalesced);
+    }
+    let permit = match self.load_permit() {
+      Ok(permit) => permit,
+      Err(throttled) => return (None, throttled),
+    };
+    let value: Option<Arc<V>> = match self.loader.as_ref().and_then(|loader| loader.load(&key)) {
+      Some(value) => Some(Arc::new(value)),
+      None => self.stats.time_load(|| populating_fn(&key)).map(Into::into),
+    };
+    drop(permit);
+    // should the key have been updated in the meantime, the update wins
+    let value = if cache_misses {
+      self.write().get_or_populate_caching_misses(key, |_| value)
+    } else {
+      self.write().get_or_populate(key, |_| value)
+    };
+    (value, Source::Loaded)
+  }
+
+  /// Takes a permit to load from the backend, should loads be capped, see `max_concurrent_loads`.
+  /// Every load, whatever the path it's on, holds one while in flight: they're only released
+  /// before taking the write lock to install what got loaded. Should excess loads be rejected and
+  /// no permit be left, the load is reported as `Source::Throttled` instead.
+  fn load_permit(&self) -> Result<Option<SemaphorePermit>, Source> {
+    match &self.loads {
+      Some(loads) if self.reject_excess_loads => loads.try_acquire().map(Some).ok_or(Source::Throttled),
+      Some(loads) => Ok(Some(loads.acquire())),
+      None => Ok(None),
+    }
+  }
+
+  fn populating_lock(&self, key: &K) -> &Mutex<()> {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    &self.populating[hasher.finish() as usize % POPULATING_LOCKS]
+  }
+
+  /// Retrieves a shared reference to the `V` for the given `key`, if the cache already holds one.
+  ///
+  /// Unlike `get`, this never populates the cache and only ever acquires the read lock, so a miss
+  /// doesn't contend with other threads reading from or populating the cache.
+  pub fn get_if_present<Q>(&self, key: &Q) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    match self.read().get_cached(key) {
+      Some(value) => {
+        self.stats.hit();
+        value
+      }
+      None => {
+        self.stats.miss();
+        None
+      }
+    }
+  }
+
+  /// Retrieves a shared reference to the `V` for the given `key` if the cache already holds one,
+  /// or to the `default` otherwise, e.g. for config-like lookups.
+  ///
+  /// The `default` is **not** inserted into the cache, unlike with `get_or_insert`: the key stays
+  /// absent, so that it can still be populated later on. As `get_if_present`, this only ever
+  /// acquires the read lock.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// assert_eq!(*cache.get_or_default(&42, "default".to_string()), "default");
+  /// assert!(cache.is_empty());
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(*cache.get_or_default(&42, "default".to_string()), "42");
+  /// ```
+  pub fn get_or_default<Q>(&self, key: &Q, default: V) -> Arc<V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.get_if_present(key).unwrap_or_else(|| Arc::new(default))
+  }
+
+  /// Same as `get_if_present`, but borrows the value rather than cloning the `Arc` holding it,
+  /// sparing hot reads the atomic increment and decrement of its reference count.
+  ///
+  /// The returned `CacheRef` holds the read lock until it is dropped: don't hold on to it while
+  /// populating, updating or removing another key, as that would deadlock.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(cache.get_ref(&42).unwrap().len(), 2);
+  /// assert!(cache.get_ref(&7).is_none());
+  /// ```
+  pub fn get_ref<Q>(&self, key: &Q) -> Option<CacheRef<'_, K, V, S>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let segment = self.read();
+    let value = match segment.get_cached_ref(key) {
+      Some(value) => {
+        self.stats.hit();
+        NonNull::from(value?)
+      }
+      None => {
+        self.stats.miss();
+        return None;
+      }
+    };
+    Some(CacheRef::new(segment, value))
+  }
+
+  /// Same as `get_ref`, but hands the borrowed value to `f`, returning what it returns, rather than
+  /// a `CacheRef`: the read lock is released as soon as `f` returns.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(cache.with_ref(&42, |value| value.len()), Some(2));
+  /// assert_eq!(cache.with_ref(&7, |value| value.len()), None);
+  /// ```
+  pub fn with_ref<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    F: FnOnce(&V) -> R,
+  {
+    match self.read().get_cached_ref(key) {
+      Some(value) => {
+        self.stats.hit();
+        value.map(f)
+      }
+      None => {
+        self.stats.miss();
+        None
+      }
+    }
+  }
+
+  /// The time left until the entry for `key` expires, after its time-to-live or time-to-idle,
+  /// e.g. for callers driving their own refreshes. An entry that expired, but wasn't evicted yet,
+  /// has zero left, while one that never expires has `Duration::MAX`. Returns `None` should the
+  /// cache hold no value for `key`.
+  ///
+  /// This only acquires the read lock, and doesn't count as an access: neither hits nor the entry's
+  /// time-to-idle are affected.
+  ///
+  /// ```
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_secs(60));
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert!(cache.ttl_remaining(&42).unwrap() > Duration::from_secs(59));
+  /// assert_eq!(cache.ttl_remaining(&7), None);
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn ttl_remaining<Q>(&self, key: &Q) -> Option<Duration>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.read().ttl_remaining(key)
+  }
+
+  /// Same as `get_if_present`, but also returns the entry's `EntryMeta`: how long ago it was last
+  /// populated or updated, and how many times it got hit since, this lookup included.
+  ///
+  /// Hits are counted atomically, so that this, like `get`, only ever acquires the read lock.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// cache.get(&42, |_| unreachable!());
+  /// let (value, meta) = cache.get_with_metadata(&42).unwrap();
+  /// assert_eq!(*value, "42");
+  /// assert_eq!(meta.hit_count, 2);
+  /// ```
+  pub fn get_with_metadata<Q>(&self, key: &Q) -> Option<(Arc<V>, EntryMeta)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    match self.read().get_with_metadata(key) {
+      Some(found) => {
+        self.stats.hit();
+        Some(found)
+      }
+      None => {
+        self.stats.miss();
+        None
+      }
+    }
+  }
+
+  /// Updates an entry in the cache, or populates it if absent.
+  ///
+  /// The update can be an actual update or a remove, should the `updating_fn` return a `None`.
+  /// The `updating_fn` receives the `key`, as well as an `Option<Arc<V>>` which holds the previous
+  /// value for the `key`, which would be `None` if the function is about to populate the cache.
+  ///
+  /// It is guaranteed that the mapping will not be altered by another thread while the
+  /// `populating_fn` executes.
+  ///
+  /// Should the cache write through to a `Writer` that fails to persist the update, the mapping
+  /// is left untouched and `None` is returned, see `try_update`.
+  pub fn update<F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
+  where
+    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+  {
+    self.try_update(key, updating_fn).unwrap_or(None)
+  }
+
+  /// Same as `update`, but tells a failure of the cache's `Writer` apart: the backing store is
+  /// written to under the write lock, before the cache is altered, so that an `Err` means neither
+  /// the store nor the cache changed.
+  ///
+  /// ```
+  /// use cachers::{CacheThrough, WriteError, Writer};
+  ///
+  /// struct ReadOnly;
+  ///
+  /// impl Writer<usize, String> for ReadOnly {
+  ///   fn write(&self, _: &usize, _: &String) -> Result<(), WriteError> {
+  ///     Err(WriteError)
+  ///   }
+  ///
+  ///   fn delete(&self, _: &usize) -> Result<(), WriteError> {
+  ///     Err(WriteError)
+  ///   }
+  /// }
+  ///
+  /// let cache: CacheThrough<usize, String> = CacheThrough::builder().capacity(100).writer(ReadOnly).build();
+  /// assert_eq!(cache.try_update(42, |key, _| Some(key.to_string())), Err(WriteError));
+  /// assert_eq!(cache.get_if_present(&42), None);
+  /// ```
+  pub fn try_update<F>(&self, key: K, updating_fn: F) -> Result<Option<Arc<V>>, WriteError>
+  where
+    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+  {
+    self.stats.update();
+    self.write().try_update(key, updating_fn)
+  }
+
+  /// Same as `try_update`, but never blocks: should another thread hold the cache's lock,
+  /// `TryUpdateError::WouldBlock` is returned instead of waiting for it, and the `updating_fn`
+  /// isn't invoked. This suits non-critical updates, e.g. from a background task, that can be
+  /// dropped or retried later. A failure of the cache's `Writer` is returned as
+  /// `TryUpdateError::WriteError`, the mapping then being left untouched.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let value = cache.update_if_unlocked(42, |key, _| Some(key.to_string()));
+  /// assert_eq!(*value.unwrap().unwrap(), "42");
+  /// ```
+  pub fn update_if_unlocked<F>(&self, key: K, updating_fn: F) -> Result<Option<Arc<V>>, TryUpdateError>
+  where
+    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+  {
+    let mut segment = self.data.try_write().map(WriteGuard::new).ok_or(WouldBlock)?;
+    self.stats.update();
+    Ok(segment.try_update(key, updating_fn)?)
+  }
+
+  /// Same as `update`, but the `updating_fn` returns the entry's time-to-live alongside its
+  /// value. A `Some` duration overrides the cache-wide time-to-live for that entry, while `None`
+  /// has it fall back to the cache-wide one, if any.
+  ///
+  /// ```
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_secs(60));
+  /// cache.update_with_ttl(42, |key, _| Some((key.to_string(), Some(Duration::from_secs(3600)))));
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn update_with_ttl<F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
+  where
+    F: Fn(&K, Option<Arc<V>>) -> Option<(V, Option<Duration>)>,
+  {
+    self.stats.update();
+    self.write().update_with_ttl(key, updating_fn)
+  }
+
+  /// Updates the entry for `key`, only should it be present in the cache. Unlike `update`, the
+  /// `computing_fn` is not invoked for absent keys, and `None` is returned without populating
+  /// anything.
+  ///
+  /// The `computing_fn` receives the `key` and its current value. Should it return `None`, the
+  /// entry is removed, as with `update`. The computed value keeps the entry's time-to-live, such
+  /// as set by `update_with_ttl`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// assert_eq!(cache.compute_if_present(&42, |_, value| Some(value.to_string() + "!")), None);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(*cache.compute_if_present(&42, |_, value| Some(value.to_string() + "!")).unwrap(), "42!");
+  /// ```
+  pub fn compute_if_present<Q, F>(&self, key: &Q, computing_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    F: Fn(&K, Arc<V>) -> Option<V>,
+  {
+    self.write().compute_if_present(key, computing_fn)
+  }
+
+  /// Gets the `Entry` for `key`, to inspect and alter it in place, as with `HashMap::entry`.
+  ///
+  /// The entry holds the cache's write lock until it is dropped, which its `or_insert_with` &
+  /// `or_insert` do: don't hold on to it, nor call into the cache while holding it, as this would
+  /// deadlock.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, usize>::new(100);
+  /// assert_eq!(*cache.entry(42).and_modify(|count| *count += 1).or_insert(0), 0);
+  /// assert_eq!(*cache.entry(42).and_modify(|count| *count += 1).or_insert(0), 1);
+  /// assert_eq!(*cache.entry(7).or_insert_with(|| 7), 7);
+  /// ```
+  pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+    Entry::new(self.write(), key)
+  }
+
+  /// Retrieves the `V` for the given `key`, inserting `value` should it be absent. This is `get`
+  /// for when the value is already at hand, rather than computed by a `populating_fn`.
+  ///
+  /// Should multiple threads race to insert for the same `key`, the first one wins: the others get
+  /// the `Arc<V>` it inserted, and their own `value` is dropped.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// assert_eq!(*cache.get_or_insert(42, "42".to_string()), "42");
+  /// assert_eq!(*cache.get_or_insert(42, "43".to_string()), "42");
+  /// ```
+  pub fn get_or_insert(&self, key: K, value: V) -> Arc<V> {
+    if let Some(Some(existing)) = self.read().get_cached(&key) {
+      self.stats.hit();
+      return existing;
+    }
+    self.stats.miss();
+    self.write().get_or_insert_with(key, || value)
+  }
+
+  /// Replaces the value for `key` with `new`, only should the cached one equal `expected`, e.g. to
+  /// implement optimistic concurrency. Returns whether the value got replaced, which it never
+  /// does for absent keys, nor for cached misses.
+  ///
+  /// The comparison and the swap happen under the write lock, so that no other thread can alter
+  /// the mapping in between. The `new` value keeps the entry's time-to-live.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert!(!cache.replace_if(&42, &"43".to_string(), "44".to_string()));
+  /// assert!(cache.replace_if(&42, &"42".to_string(), "43".to_string()));
+  /// assert_eq!(*cache.get_if_present(&42).unwrap(), "43");
+  /// ```
+  pub fn replace_if<Q>(&self, key: &Q, expected: &V, new: V) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    V: PartialEq,
+  {
+    self.write().replace_if(key, expected, new)
+  }
+
+  /// Maps `key` to the given `Arc<V>`, overriding any existing mapping. As with `get_shared`, the
+  /// cache holds on to `value` itself, so that hits return clones of it.
+  pub fn insert_shared(&self, key: K, value: Arc<V>) {
+    self.write().update_with_ttl(key, |_, _| Some((value, None)));
+  }
+
+  /// Inserts all `entries` at once, e.g. to warm the cache up from a snapshot, while holding the
+  /// write lock only once. Existing mappings get overridden, and entries compete for capacity as
+  /// they would when populated one by one, i.e. later entries may evict earlier ones.
+  ///
+  /// Returns the keys that got evicted while inserting, which being non-empty indicates the cache
+  /// is too small to hold the whole of `entries`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(2);
+  /// let evicted = cache.insert_many((1..=3).map(|key| (key, key.to_string())));
+  /// assert_eq!(evicted.len(), 1);
+  /// assert_eq!(cache.len(), 2);
+  /// ```
+  pub fn insert_many<I>(&self, entries: I) -> Vec<K>
+  where
+    I: IntoIterator<Item = (K, V)>,
+  {
+    self.write().insert_many(entries)
+  }
+
+  /// Mutates the value for `key` in place, only should it be present in the cache, and returns it.
+  /// Unlike `compute_if_present`, the value is only cloned should it be shared, i.e. should
+  /// another `Arc<V>` to it still be around, e.g. the one returned by a previous `get`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, Vec<usize>>::new(100);
+  /// assert_eq!(cache.modify(&42, |value| value.push(43)), None);
+  /// cache.get(&42, |key| Some(vec![*key]));
+  /// assert_eq!(*cache.modify(&42, |value| value.push(43)).unwrap(), vec![42, 43]);
+  /// ```
+  pub fn modify<Q, F>(&self, key: &Q, modifying_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    V: Clone,
+    F: FnOnce(&mut V),
+  {
+    self.write().modify(key, modifying_fn)
+  }
+
+  /// Removes the entry for `key` from the cache, returning the value it held, if any, e.g. for the
+  /// caller to clean it up. Expired entries are removed too, but their value isn't returned.
+  /// This is the equivalent of `cache.update(key, |_, _| None)`. Consider this a convenience method.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(*cache.remove(42).unwrap(), "42");
+  /// assert_eq!(cache.remove(42), None);
+  /// ```
+  ///
+  /// Should the cache write through to a `Writer` that fails to persist the removal, the entry is
+  /// kept and `None` is returned, see `try_remove`.
+  pub fn remove(&self, key: K) -> Option<Arc<V>> {
+    self.write().remove(&key)
+  }
+
+  /// Removes the entries for all `keys` while holding the write lock only once, e.g. to invalidate
+  /// a set of keys at once, and returns how many of them the cache held a value for. Each removal
+  /// goes through the cache's `Writer`, as `remove` does.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.insert_many((1..=3).map(|key| (key, key.to_string())));
+  /// assert_eq!(cache.remove_many(vec![1, 3, 5]), 2);
+  /// assert_eq!(cache.len(), 1);
+  /// ```
+  pub fn remove_many<I>(&self, keys: I) -> usize
+  where
+    I: IntoIterator<Item = K>,
+  {
+    self.write().remove_many(keys)
+  }
+
+  /// Same as `remove`, but tells a failure of the cache's `Writer` apart, as `try_update` does.
+  pub fn try_remove(&self, key: K) -> Result<Option<Arc<V>>, WriteError> {
+    self.write().try_remove(&key)
+  }
+
+  /// The number of entries evicted since the cache was created, be it to make room for others, or
+  /// by `resize`, `evict_to` or `shed`. Steadily increasing while misses are frequent hints at a
+  /// cache too small for its working set. Entries expiring or removed aren't counted.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(2);
+  /// for key in 0..5 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// assert_eq!(cache.eviction_count(), 3);
+  /// ```
+  pub fn eviction_count(&self) -> u64 {
+    self.read().evictions()
+  }
+
+  /// The number of misses that didn't invoke their `populating_fn`, as another thread was already
+  /// populating the same key: they waited for it to be done instead, and got its value. Compared
+  /// to the number of misses, this tells how many loads concurrent misses saved.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert_eq!(cache.coalesced_loads(), 0);
+  /// ```
+  pub fn coalesced_loads(&self) -> u64 {
+    self.stats.coalesced_loads()
+  }
+
+  /// Renders the cache's hits, misses, coalesced loads & evictions counters, as well as its current size and
+  /// capacity gauges, in the OpenMetrics text format. Every metric name is prefixed with
+  /// `name_prefix`, so that multiple caches can be told apart when served from the same
+  /// `/metrics` endpoint. Only the read lock is acquired, to read the gauges from.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// assert!(cache.render_prometheus("users").contains("users_misses_total 1\n"));
+  /// ```
+  #[cfg(feature = "prometheus-text")]
+  #[doc(alias = "export_metrics")]
+  pub fn render_prometheus(&self, name_prefix: &str) -> String {
+    let (size, capacity, evictions) = {
+      let segment = self.read();
+      (segment.len(), segment.capacity(), segment.evictions())
+    };
+    stats::Exposition::new()
+      .counter(&format!("{}_hits", name_prefix), self.stats.hits())
+      .counter(&format!("{}_misses", name_prefix), self.stats.misses())
+      .counter(
+        &format!("{}_coalesced_loads", name_prefix),
+        self.stats.coalesced_loads(),
+      )
+      .counter(&format!("{}_evictions", name_prefix), evictions)
+      .gauge(&format!("{}_size", name_prefix), size as u64)
+      .gauge(&format!("{}_capacity", name_prefix), capacity as u64)
+      .finish()
+  }
+
+  /// Removes every entry for which `f` returns `false`, e.g. all the entries of a tenant that got
+  /// deleted, leaving all others untouched. `f` receives each entry's key and value, and is
+  /// invoked while holding the write lock. Only the cache is affected: the removals aren't
+  /// written through to the cache's `writer`, if any.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&1, |key| Some(key.to_string()));
+  /// cache.get(&2, |key| Some(key.to_string()));
+  /// cache.retain(|key, _| key % 2 == 0);
+  /// assert_eq!(cache.keys(), vec![2]);
+  /// ```
+  pub fn retain<F>(&self, f: F)
+  where
+    F: Fn(&K, &Arc<V>) -> bool,
+  {
+    self.write().retain(f);
+  }
+
+  /// Pins the entry for `key`, so that it never gets evicted, e.g. default configuration that
+  /// must always be at hand. Pinned entries still take up capacity, and are still removed should
+  /// they be updated away, removed or invalidated. Pins are lost when `resize` shrinks the cache to
+  /// no more entries than there are pinned ones.
+  ///
+  /// Returns whether the entry got pinned. It doesn't if the cache holds no value for `key`,
+  /// should pinning it leave no entry to evict, i.e. all but one entry of the cache can be
+  /// pinned, or unless the eviction strategy is `Eviction::Clock`, the only one supporting pins.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(2);
+  /// cache.get(&0, |_| Some("defaults".to_string()));
+  /// assert!(cache.pin(&0));
+  /// for key in 1..10 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// assert_eq!(*cache.get(&0, |_| unreachable!()).unwrap(), "defaults");
+  /// ```
+  pub fn pin<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.write().pin(key, true)
+  }
+
+  /// Unpins the entry for `key`, making it evictable again. Returns whether the cache holds a
+  /// value for `key` to unpin.
+  pub fn unpin<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.write().pin(key, false)
+  }
+
+  /// Removes the entries of all keys in `namespace`, e.g. all those of a tenant that got deleted,
+  /// cached misses included, while holding the write lock once. Returns the number of entries
+  /// removed. As with `invalidate_all`, only the cache is affected: the removals aren't written
+  /// through to the cache's `writer`, if any.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<(&str, usize), String>::new(100);
+  /// cache.get(&("acme", 1), |(_, id)| Some(id.to_string()));
+  /// cache.get(&("acme", 2), |(_, id)| Some(id.to_string()));
+  /// cache.get(&("initech", 1), |(_, id)| Some(id.to_string()));
+  /// assert_eq!(cache.invalidate_namespace(&"acme"), 2);
+  /// assert_eq!(cache.keys(), vec![("initech", 1)]);
+  /// ```
+  pub fn invalidate_namespace(&self, namespace: &K::Namespace) -> usize
+  where
+    K: Namespaced,
+  {
+    self.write().remove_keys(|key| key.namespace() == namespace)
+  }
+
+  /// Removes all expired entries at once, rather than lazily as they get accessed, so that those
+  /// rarely accessed don't linger, taking up capacity. Returns the number of entries removed. As
+  /// for entries expiring on access, the eviction listener isn't notified of them.
+  ///
+  /// ```
+  /// use std::thread;
+  /// use std::time::Duration;
+  ///
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::with_time_to_live(100, Duration::from_millis(10));
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// thread::sleep(Duration::from_millis(20));
+  /// assert_eq!(cache.len(), 1);
+  /// assert_eq!(cache.run_maintenance(), 1);
+  /// assert!(cache.is_empty());
+  /// ```
+  pub fn run_maintenance(&self) -> usize {
+    self.write().remove_expired()
+  }
+
+  /// Runs `run_maintenance` every `interval`, on a background thread, for as long as the cache,
+  /// or any of its clones, is alive. The thread only holds on to the cache while sweeping it.
+  #[cfg(feature = "std")]
+  pub fn spawn_maintenance(&self, interval: Duration) -> JoinHandle<()>
+  where
+    K: Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: Send + Sync + 'static,
+  {
+    let data = Arc::downgrade(&self.data);
+    thread::spawn(move || loop {
+      thread::sleep(interval);
+      match data.upgrade() {
+        Some(data) => WriteGuard::new(data.write()).remove_expired(),
+        None => return,
+      };
+    })
+  }
+
+  /// Invalidates all entries at once, in constant time: entries populated before are treated as
+  /// expired from then on, so that they get repopulated as they are requested again. They keep
+  /// taking up room until then, or until they get evicted, and are still counted by `len`.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// cache.invalidate_all();
+  /// assert_eq!(cache.get_if_present(&42), None);
+  /// assert_eq!(*cache.get(&42, |_| Some("again".to_string())).unwrap(), "again");
+  /// ```
+  pub fn invalidate_all(&self) {
+    self.write().invalidate_all();
+  }
+
+  /// Snapshots the keys currently held by the cache, in no particular order. Expired entries
+  /// are left out.
+  ///
+  /// The snapshot is consistent, as it is taken under the read lock, but it allocates and is
+  /// linear in the cache's size: it is meant for occasional inspection, e.g. debugging or cache
+  /// warming, rather than for the hot path.
+  pub fn keys(&self) -> Vec<K> {
+    self.read().keys()
+  }
+
+  /// Snapshots the entries currently held by the cache as `(key, value)` pairs, in no particular
+  /// order. The same caveats as for `keys` apply.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// let entries = cache.iter();
+  /// assert_eq!(entries[0].0, 42);
+  /// assert_eq!(*entries[0].1, "42");
+  /// ```
+  pub fn iter(&self) -> Vec<(K, Arc<V>)> {
+    self.read().entries()
+  }
+
+  /// Copies the live entries of the cache out as `(key, value)` pairs, under the read lock, e.g. to
+  /// serialize them for a warm restart with `restore`. Expired entries and cached misses are left
+  /// out.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// let restarted = CacheThrough::<usize, String>::new(100);
+  /// restarted.restore(cache.snapshot());
+  /// assert_eq!(*restarted.get(&42, |_| unreachable!()).unwrap(), "42");
+  /// ```
+  pub fn snapshot(&self) -> Vec<(K, V)>
+  where
+    V: Clone,
+  {
+    self
+      .read()
+      .entries()
+      .into_iter()
+      .map(|(key, value)| (key, V::clone(&value)))
+      .collect()
+  }
+
+  /// Repopulates the cache with the `entries` of a `snapshot`, under the write lock, as if they
+  /// had been loaded on misses: keys the cache already holds a value for keep it, as it is more
+  /// recent than the snapshot, and nothing gets written through to the cache's `Writer`, so that
+  /// restoring an old snapshot never overrides the backing store with stale values.
+  ///
+  /// How recently entries were used isn't part of a snapshot: restored entries start fresh, as if
+  /// just populated, e.g. with their reference bit set under `Eviction::Clock`, and their
+  /// time-to-live starting over.
+  pub fn restore(&self, entries: Vec<(K, V)>) {
+    let mut segment = self.write();
+    for (key, value) in entries {
+      segment.get_or_populate(key, |_| Some(value));
+    }
+  }
+
+  /// Empties the cache and returns the entries it held, e.g. to persist them on shutdown, as a
+  /// single atomic step: no other thread can populate the cache in between. Expired entries and
+  /// cached misses are dropped. The cache remains usable afterwards, with its whole capacity
+  /// available again. The removals aren't written through to the cache's `writer`, if any: the
+  /// entries are handed back for the caller to persist.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// let entries = cache.drain();
+  /// assert_eq!(entries[0].0, 42);
+  /// assert_eq!(*entries[0].1, "42");
+  /// assert!(cache.is_empty());
+  /// ```
+  pub fn drain(&self) -> Vec<(K, Arc<V>)> {
+    self.write().drain()
+  }
+
+  /// Snapshots the counters of the clock eviction, to help deciding whether another `Eviction`
+  /// strategy would suit the workload better. Returns `None` when using another strategy.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(1);
+  /// cache.get(&1, |key| Some(key.to_string()));
+  /// cache.get(&2, |key| Some(key.to_string()));
+  /// assert_eq!(cache.eviction_metrics().unwrap().evictions, 1);
+  /// ```
+  #[cfg(feature = "eviction-metrics")]
+  pub fn eviction_metrics(&self) -> Option<EvictionMetrics> {
+    self.read().eviction_metrics()
+  }
+
+  /// The slot the hand of the clock eviction points at, i.e. the first one it inspects when
+  /// looking for the next entry to evict. Returns `None` when using another strategy.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(2);
+  /// for key in 0..3 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// assert_eq!(cache.clock_hand_position(), Some(1));
+  /// ```
+  #[cfg(feature = "debug-internals")]
+  pub fn clock_hand_position(&self) -> Option<usize> {
+    self.read().clock().map(|clock| clock.hand_position())
+  }
+
+  /// The reference bit of every slot of the clock eviction, set when its entry got accessed since
+  /// the hand last went past it. Returns `None` when using another strategy.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(2);
+  /// for key in 0..3 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// assert_eq!(cache.clock_reference_bits(), Some(vec![true, false]));
+  /// ```
+  #[cfg(feature = "debug-internals")]
+  pub fn clock_reference_bits(&self) -> Option<Vec<bool>> {
+    self.read().clock().map(|clock| clock.reference_bits())
+  }
+
+  /// The maximum number of entries the cache holds.
+  pub fn capacity(&self) -> usize {
+    self.read().capacity()
+  }
+
+  /// Changes the maximum number of entries the cache holds, e.g. in response to memory pressure.
+  /// When shrinking, entries are evicted down to the new `capacity`, as picked by the eviction
+  /// strategy. Returns a `ConfigError` and leaves the cache untouched should `capacity` be zero.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// for key in 0..100 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// cache.resize(10).unwrap();
+  /// assert_eq!(cache.capacity(), 10);
+  /// assert_eq!(cache.len(), 10);
+  /// ```
+  pub fn resize(&self, capacity: usize) -> Result<(), ConfigError> {
+    if capacity == 0 {
+      return Err(ConfigError::ZeroCapacity);
+    }
+    self.write().resize(capacity);
+    Ok(())
+  }
+
+  /// Evicts entries, as picked by the eviction strategy, until no more than `target_len` are left,
+  /// without changing the cache's capacity, e.g. to free memory ahead of time. Returns the number
+  /// of entries evicted, each of which is reported to the eviction listener.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// for key in 0..100 {
+  ///   cache.get(&key, |key| Some(key.to_string()));
+  /// }
+  /// assert_eq!(cache.evict_to(10), 90);
+  /// assert_eq!(cache.len(), 10);
+  /// assert_eq!(cache.capacity(), 100);
+  /// ```
+  pub fn evict_to(&self, target_len: usize) -> usize {
+    self.write().evict_to(target_len)
+  }
+
+  /// The total weight of the cache's entries, as weighed by its `Weigher`, or their number if it
+  /// has none.
+  pub fn weight(&self) -> usize {
+    self.read().weight()
+  }
+
+  /// Evicts entries, as picked by the eviction strategy, until at least `weight` got freed, e.g.
+  /// on behalf of a `CacheRegistry`. Returns the number of entries evicted, each of which is
+  /// reported to the eviction listener.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::builder()
+  ///   .capacity(100)
+  ///   .weigher(1000, |_: &usize, value: &String| value.len())
+  ///   .build();
+  /// cache.get(&1, |_| Some("ten chars!".to_string()));
+  /// cache.get(&2, |_| Some("ten chars!".to_string()));
+  /// assert_eq!(cache.weight(), 20);
+  /// assert_eq!(cache.shed(5), 1);
+  /// assert_eq!(cache.weight(), 10);
+  /// ```
+  pub fn shed(&self, weight: usize) -> usize {
+    self.write().shed(weight)
+  }
+
+  /// Subscribes to the cache's events: every entry inserted, updated, removed or evicted gets
+  /// reported over the returned channel, from then on and for as long as it isn't dropped.
+  ///
+  /// Events are sent while holding the cache's write lock, but never block: should the subscriber
+  /// lag more than 1024 events behind, the ones that don't fit in its channel are dropped, and
+  /// counted by `dropped_events`.
+  ///
+  /// ```
+  /// use cachers::{CacheEvent, CacheThrough};
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let events = cache.subscribe();
+  /// cache.get(&42, |key| Some(key.to_string()));
+  /// cache.remove(42);
+  /// assert!(matches!(events.try_recv(), Ok(CacheEvent::Insert(42, _))));
+  /// assert_eq!(events.try_recv(), Ok(CacheEvent::Remove(42)));
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn subscribe(&self) -> Receiver<CacheEvent<K, V>> {
+    self.write().subscribe()
+  }
+
+  /// The number of events subscribers missed so far, as they lagged too far behind.
+  #[cfg(feature = "std")]
+  pub fn dropped_events(&self) -> u64 {
+    self.read().dropped_events()
+  }
+
+  /// Approximates the memory the cache holds, in bytes, for capacity planning. This accounts for
+  /// the cache's structures and its live entries, sized as `size_of::<V>()` plus what
+  /// `heap_size_of` reports each value holds on the heap, e.g. a `String`'s capacity. Keys are
+  /// accounted for as `size_of::<K>()` only.
+  ///
+  /// ```
+  /// use cachers::CacheThrough;
+  ///
+  /// let cache = CacheThrough::<usize, String>::new(100);
+  /// let empty = cache.estimated_memory_bytes(String::capacity);
+  /// cache.get(&42, |_| Some("a rather long value".repeat(100)));
+  /// assert!(cache.estimated_memory_bytes(String::capacity) > empty + 1900);
+  /// ```
+  pub fn estimated_memory_bytes<F>(&self, heap_size_of: F) -> usize
+  where
+    F: Fn(&V) -> usize,
+  {
+    self.read().estimated_memory_bytes(heap_size_of)
+  }
+
+  /// The number of entries currently held by the cache.
+  pub fn len(&self) -> usize {
+    self.read().len()
+  }
+
+  /// Whether the cache currently holds no entries at all.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// Formats the cache's capacity & current length, as well as its keys when using the alternate
+/// `{:#?}` format. Values are never formatted, so that `V` doesn't need to implement `Debug`.
+///
+/// ```
+/// use cachers::CacheThrough;
+///
+/// let cache = CacheThrough::<usize, String>::new(100);
+/// cache.get(&42, |key| Some(key.to_string()));
+/// assert_eq!(format!("{:?}", cache), "CacheThrough { capacity: 100, len: 1 }");
+/// ```
+impl<K, V, S> fmt::Debug for CacheThrough<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone + fmt::Debug,
+  S: BuildHasher,
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let keys = f.alternate();
+    let segment = self.read();
+    let mut debug = f.debug_struct("CacheThrough");
+    segment.debug_fields(&mut debug, keys);
+    debug.finish()
+  }
+}
+
+/// The capacity of a `CacheThrough` created through `Default`.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Creates a `CacheThrough` holding up to 1024 entries.
+///
+/// Creating a cache allocates, so that it can't be done in a `const` context. Caches meant to be
+/// shared as `static`s can be lazily created instead, e.g. using `OnceLock`:
+///
+/// ```
+/// use std::sync::OnceLock;
+///
+/// use cachers::CacheThrough;
+///
+/// fn users() -> &'static CacheThrough<u64, String> {
+///   static USERS: OnceLock<CacheThrough<u64, String>> = OnceLock::new();
+///   USERS.get_or_init(CacheThrough::default)
+/// }
+///
+/// assert_eq!(*users().get(&42, |key| Some(key.to_string())).unwrap(), "42");
+/// ```
+impl<K, V, S> Default for CacheThrough<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone,
+  S: BuildHasher + Default,
+{
+  fn default() -> CacheThrough<K, V, S> {
+    CacheThrough::with_hasher(DEFAULT_CAPACITY, S::default())
+  }
+}
+
+/// Collects `(K, V)` pairs into a cache just large enough to hold them all, e.g. to seed it from
+/// config data. Later pairs override earlier ones for the same key.
+///
+/// ```
+/// use cachers::CacheThrough;
+///
+/// let cache: CacheThrough<usize, String> = (1..=3).map(|key| (key, key.to_string())).collect();
+/// assert_eq!(cache.capacity(), 3);
+/// assert_eq!(*cache.get(&2, |_| unreachable!()).unwrap(), "2");
+/// ```
+impl<K, V, S> FromIterator<(K, V)> for CacheThrough<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone,
+  S: BuildHasher + Default,
+{
+  fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> CacheThrough<K, V, S> {
+    let entries: Vec<(K, V)> = entries.into_iter().collect();
+    let cache = CacheThrough::with_hasher(entries.len().max(1), S::default());
+    cache.insert_many(entries);
+    cache
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    CacheEvent, CacheThrough, ConfigError, Evictor, Resized, Source, Timeout, TinyLfu, TryUpdateError, WouldBlock,
+    WriteError, Writer,
+  };
+  use std::collections::hash_map::DefaultHasher;
+  use std::collections::VecDeque;
+  use std::hash::BuildHasherDefault;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::{Arc, Mutex};
+  use std::thread;
+  use std::time::Duration;
+
+  fn test_cache() -> CacheThrough<i32, String> {
+    CacheThrough::new(3)
+  }
+
+  #[test]
+  fn hit_populates() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.get(&our_key, do_not_invoke);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
+  fn miss_populates_not() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+    {
+      let value = cache.get(&our_key, miss);
+      assert_eq!(value, None);
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+      cache.get(&2, populate);
+      cache.get(&3, populate);
+      cache.get(&4, populate);
+    }
+  }
+
+  #[test]
+  fn get_if_present_populates_not() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      assert_eq!(cache.get_if_present(&our_key), None);
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      cache.get(&our_key, populate);
+      let value = cache.get_if_present(&our_key);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
+  fn get_ref_borrows_the_value() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+    assert!(cache.get_ref(&our_key).is_none());
+
+    let value = cache.get(&our_key, populate).unwrap();
+    {
+      let cached = cache.get_ref(&our_key).unwrap();
+      assert_eq!(*cached, "42");
+      assert_eq!(Arc::strong_count(&value), 2);
+      // other readers aren't held back
+      assert_eq!(*cache.get_if_present(&our_key).unwrap(), "42");
+    }
+    assert_eq!(cache.with_ref(&our_key, |cached| cached.clone()).unwrap(), "42");
+    assert_eq!(Arc::strong_count(&value), 2);
+    assert_eq!(cache.with_ref(&7, |_| unreachable!()), None::<()>);
+    // the lock got released
+    cache.update(our_key, update);
+  }
+
+  #[test]
+  fn get_with_metadata_counts_hits() {
+    let cache = test_cache();
+    let our_key = 42;
+    assert_eq!(cache.get_with_metadata(&our_key), None);
+
+    cache.get(&our_key, populate);
+    cache.get(&our_key, do_not_invoke);
+    cache.get_if_present(&our_key);
+    let (value, meta) = cache.get_with_metadata(&our_key).unwrap();
+    assert_eq!(*value, "42");
+    assert_eq!(meta.hit_count, 3);
+
+    thread::sleep(Duration::from_millis(10));
+    assert!(cache.get_with_metadata(&our_key).unwrap().1.age >= Duration::from_millis(10));
+
+    cache.update(our_key, update);
+    assert_eq!(cache.get_with_metadata(&our_key).unwrap().1.hit_count, 1);
+  }
+
+  #[test]
+  fn update_populates() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let value = cache.update(our_key, upsert);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.get(&our_key, do_not_invoke);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
+  fn update_updates() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.update(our_key, update);
+      assert_eq!(*value.unwrap(), "42 updated!");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.get(&our_key, do_not_invoke);
+      assert_eq!(*value.unwrap(), "42 updated!");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
   fn update_removes() {
     let cache: CacheThrough<i32, String> = test_cache();
-    let our_key = 42;
+    let our_key = 42;
+
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.update(our_key, updel);
+      assert_eq!(value, None);
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      let value = cache.get(&our_key, miss);
+      assert_eq!(value, None);
+      assert_eq!(cache.len(), 0);
+    }
+  }
+
+  #[test]
+  fn modify_clones_shared_values_only() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(cache.modify(&1, |value| value.push('!')), None);
+
+    let original = cache.get(&1, populate).unwrap();
+    let modified = cache.modify(&1, |value| value.push('!')).unwrap();
+    assert_eq!(*original, "1");
+    assert_eq!(*modified, "1!");
+
+    drop(original);
+    let address = &*modified as *const String;
+    drop(modified);
+    let modified = cache.modify(&1, |value| value.push('!')).unwrap();
+    assert_eq!(*modified, "1!!");
+    assert_eq!(&*modified as *const String, address);
+  }
+
+  #[test]
+  fn get_or_insert_keeps_the_first_value() {
+    let cache = Arc::new(test_cache());
+    let handles: Vec<_> = (0..4)
+      .map(|i| {
+        let cache = cache.clone();
+        thread::spawn(move || cache.get_or_insert(42, i.to_string()))
+      })
+      .collect();
+    let values: Vec<Arc<String>> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+    for value in values.iter() {
+      assert!(Arc::ptr_eq(value, &values[0]));
+    }
+    assert!(Arc::ptr_eq(&cache.get_if_present(&42).unwrap(), &values[0]));
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[test]
+  fn replace_if_compares_and_sets() {
+    let cache = test_cache();
+    let our_key = 42;
+    assert!(!cache.replace_if(&our_key, &"42".to_string(), "43".to_string()));
+    assert_eq!(cache.len(), 0);
+
+    cache.get(&our_key, populate);
+    assert!(!cache.replace_if(&our_key, &"41".to_string(), "43".to_string()));
+    assert_eq!(*cache.get_if_present(&our_key).unwrap(), "42");
+    assert!(cache.replace_if(&our_key, &"42".to_string(), "43".to_string()));
+    assert_eq!(*cache.get_if_present(&our_key).unwrap(), "43");
+  }
+
+  #[test]
+  fn compute_if_absent_computes_once() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      assert_eq!(cache.compute_if_absent(&our_key, miss), None);
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      assert_eq!(*cache.compute_if_absent(&our_key, populate).unwrap(), "42");
+      assert_eq!(*cache.compute_if_absent(&our_key, do_not_invoke).unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
+  fn compute_if_present_skips_absent() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let value = cache.compute_if_present(&our_key, |_, _| unimplemented!());
+      assert_eq!(value, None);
+      assert_eq!(cache.len(), 0);
+    }
+
+    {
+      cache.get(&our_key, populate);
+      let value = cache.compute_if_present(&our_key, |_, value| Some(value.to_string() + " updated!"));
+      assert_eq!(*value.unwrap(), "42 updated!");
+      assert_eq!(*cache.get(&our_key, do_not_invoke).unwrap(), "42 updated!");
+    }
+
+    {
+      let value = cache.compute_if_present(&our_key, |_, _| None);
+      assert_eq!(value, None);
+      assert_eq!(cache.len(), 0);
+    }
+  }
+
+  #[test]
+  fn remove_removes() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let our_key = 42;
+
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      assert_eq!(*cache.remove(our_key).unwrap(), "42");
+      assert_eq!(cache.len(), 0);
+      assert_eq!(cache.remove(our_key), None);
+    }
+  }
+
+  #[test]
+  fn remove_many_frees_slots() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    for key in 1..=3 {
+      cache.get(&key, populate);
+    }
+    assert_eq!(cache.remove_many(vec![1, 2, 4]), 2);
+    assert_eq!(cache.len(), 1);
+
+    cache.get(&5, populate);
+    cache.get(&6, populate);
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.eviction_count(), 0);
+  }
+
+  #[test]
+  fn evicts() {
+    let cache: CacheThrough<i32, String> = test_cache();
+
+    {
+      assert_eq!(*cache.get(&1, populate).unwrap(), "1"); // eviction candidate
+      assert_eq!(cache.len(), 1);
+      assert_eq!(*cache.get(&2, populate).unwrap(), "2");
+      assert_eq!(cache.len(), 2);
+      assert_eq!(*cache.get(&3, populate).unwrap(), "3");
+      assert_eq!(cache.len(), 3);
+
+      // Clock state & hand:
+      // _
+      // 111
+    }
+
+    {
+      assert_eq!(*cache.get(&4, populate).unwrap(), "4"); // evicts 1
+      assert_eq!(cache.len(), 3);
+      //  _
+      // 100
+
+      assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "2");
+      assert_eq!(cache.len(), 3);
+      //  _
+      // 110
+
+      assert_eq!(*cache.get(&3, do_not_invoke).unwrap(), "3");
+      assert_eq!(cache.len(), 3);
+      //  _
+      // 111
+    }
+
+    {
+      assert_eq!(*cache.get(&5, populate).unwrap(), "5"); // evicts 2
+      assert_eq!(cache.len(), 3);
+      //   _
+      // 010
+
+      assert_eq!(*cache.get(&3, do_not_invoke).unwrap(), "3"); // 011
+      assert_eq!(cache.len(), 3);
+      assert_eq!(*cache.get(&4, do_not_invoke).unwrap(), "4"); // 111
+      assert_eq!(cache.len(), 3);
+    }
+
+    {
+      assert_eq!(*cache.get(&6, populate).unwrap(), "6"); // full circle, evicts 3
+      assert_eq!(cache.len(), 3);
+      assert_eq!(*cache.get(&5, do_not_invoke).unwrap(), "5");
+      assert_eq!(cache.len(), 3);
+      assert_eq!(*cache.get(&4, do_not_invoke).unwrap(), "4");
+      assert_eq!(cache.len(), 3);
+    }
+  }
+
+  #[test]
+  fn admission_keeps_hot_keys() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_admission_policy(3, TinyLfu::new(3));
+    for _ in 0..3 {
+      for key in 1..4 {
+        cache.get(&key, populate);
+      }
+    }
+    assert_eq!(cache.len(), 3);
+
+    for key in 100..110 {
+      assert_eq!(*cache.get(&key, populate).unwrap(), key.to_string());
+    }
+
+    assert_eq!(cache.len(), 3);
+    for key in 1..4 {
+      assert_eq!(*cache.get(&key, do_not_invoke).unwrap(), key.to_string());
+    }
+  }
+
+  #[test]
+  fn boxed_strategy_evicts() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_boxed_strategy(Box::new(Fifo {
+      slots: vec![None; 3],
+      order: VecDeque::new(),
+    }));
+    for key in 1..4 {
+      cache.get(&key, populate);
+    }
+    cache.get(&1, do_not_invoke);
+    cache.get(&4, populate);
+    assert_eq!(cache.len(), 3);
+    assert_eq!(cache.get_if_present(&1), None);
+    for key in 2..5 {
+      assert_eq!(*cache.get(&key, do_not_invoke).unwrap(), key.to_string());
+    }
+  }
+
+  /// Evicts keys in the order they were added, regardless of accesses.
+  struct Fifo {
+    slots: Vec<Option<i32>>,
+    order: VecDeque<usize>,
+  }
+
+  impl Evictor<i32> for Fifo {
+    fn add(&mut self, key: i32) -> (usize, Option<i32>) {
+      let (index, evicted) = match self.slots.iter().position(Option::is_none) {
+        Some(index) => (index, None),
+        None => {
+          let index = self.order.pop_front().unwrap();
+          (index, self.slots[index].take())
+        }
+      };
+      self.slots[index] = Some(key);
+      self.order.push_back(index);
+      (index, evicted)
+    }
+
+    fn touch(&self, _index: usize) {}
+
+    fn candidate(&mut self) -> Option<&i32> {
+      if self.slots.iter().any(Option::is_none) {
+        return None;
+      }
+      self.slots[*self.order.front()?].as_ref()
+    }
+
+    fn evict(&mut self) -> Option<i32> {
+      let index = self.order.pop_front()?;
+      self.slots[index].take()
+    }
+
+    fn remove(&mut self, index: usize) -> Option<i32> {
+      self.order.retain(|slot| *slot != index);
+      self.slots[index].take()
+    }
+
+    fn capacity(&self) -> usize {
+      self.slots.len()
+    }
+
+    fn resize(&mut self, _capacity: usize) -> Resized<i32> {
+      unimplemented!("the test evictor has a fixed capacity")
+    }
+  }
+
+  #[test]
+  fn custom_hasher() {
+    let cache = CacheThrough::with_hasher(3, BuildHasherDefault::<DefaultHasher>::default());
+    let our_key = 42;
+
+    {
+      let value = cache.get(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.get(&our_key, do_not_invoke);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
+
+  #[test]
+  fn non_copy_keys() {
+    let cache: CacheThrough<String, usize> = CacheThrough::new(2);
+    let our_key = "fourty-two".to_string();
+
+    {
+      let value = cache.get(our_key.as_str(), |key| Some(key.len()));
+      assert_eq!(*value.unwrap(), 10);
+      assert_eq!(cache.len(), 1);
+    }
+
+    {
+      let value = cache.get("fourty-two", |_| unimplemented!());
+      assert_eq!(*value.unwrap(), 10);
+      cache.remove(our_key);
+      assert_eq!(cache.len(), 0);
+    }
+  }
+
+  #[test]
+  fn resizes() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    for key in 1..4 {
+      cache.get(&key, populate);
+    }
+    cache.get(&3, do_not_invoke);
+
+    assert_eq!(cache.resize(0), Err(ConfigError::ZeroCapacity));
+    assert_eq!(cache.resize(1), Ok(()));
+    assert_eq!(cache.capacity(), 1);
+    assert_eq!(cache.keys(), vec![3]);
+
+    assert_eq!(cache.resize(2), Ok(()));
+    cache.get(&4, populate);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(*cache.get(&3, do_not_invoke).unwrap(), "3");
+    assert_eq!(*cache.get(&4, do_not_invoke).unwrap(), "4");
+    cache.get(&5, populate);
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[test]
+  fn populates_other_keys_concurrently() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let other = (2..)
+      .find(|key| !std::ptr::eq(cache.populating_lock(key), cache.populating_lock(&1)))
+      .unwrap();
+    // would deadlock, were the write lock held while populating
+    let value = cache.get(&1, |key| {
+      assert_eq!(*cache.get(&other, populate).unwrap(), other.to_string());
+      populate(key)
+    });
+    assert_eq!(*value.unwrap(), "1");
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[test]
+  fn reports_where_values_come_from() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(
+      cache.get_reporting(&1, populate),
+      (Some(Arc::new("1".to_string())), Source::Loaded)
+    );
+    assert_eq!(
+      cache.get_reporting(&1, do_not_invoke),
+      (Some(Arc::new("1".to_string())), Source::Hit)
+    );
+    assert_eq!(cache.get_reporting(&2, miss), (None, Source::Loaded));
+    assert_eq!(cache.populate_reporting(1, do_not_invoke, false).1, Source::Coalesced);
+  }
+
+  #[test]
+  fn collects_into_a_cache_holding_every_pair() {
+    let cache: CacheThrough<i32, String> = vec![(1, "one".to_string()), (2, "two".to_string())]
+      .into_iter()
+      .collect();
+    assert_eq!(cache.capacity(), 2);
+    assert_eq!(cache.len(), 2);
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "one");
+    let empty: CacheThrough<i32, String> = std::iter::empty().collect();
+    assert_eq!(empty.capacity(), 1);
+    assert!(empty.is_empty());
+  }
+
+  #[test]
+  fn ttl_remaining_reaches_zero_once_expired() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(50));
+    assert_eq!(cache.ttl_remaining(&1), None);
+    cache.get(&1, populate);
+    let remaining = cache.ttl_remaining(&1).unwrap();
+    assert!(remaining > Duration::from_millis(0) && remaining <= Duration::from_millis(50));
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(cache.ttl_remaining(&1), Some(Duration::from_secs(0)));
+    assert_eq!(test_cache().ttl_remaining(&1), None);
+    let eternal = test_cache();
+    eternal.get(&1, populate);
+    assert_eq!(eternal.ttl_remaining(&1), Some(Duration::MAX));
+  }
+
+  #[test]
+  fn caps_concurrent_loads() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder().capacity(10).max_concurrent_loads(2).build();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most_in_flight = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..6)
+      .map(|key| {
+        let cache = cache.clone();
+        let in_flight = in_flight.clone();
+        let most_in_flight = most_in_flight.clone();
+        thread::spawn(move || {
+          cache.get(&key, |key| {
+            most_in_flight.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            populate(key)
+          })
+        })
+      })
+      .collect();
+    for thread in threads {
+      assert!(thread.join().unwrap().is_some());
+    }
+    assert!(most_in_flight.load(Ordering::SeqCst) <= 2);
+    assert_eq!(cache.len(), 6);
+  }
+
+  #[test]
+  fn caps_loads_on_every_path() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder().capacity(10).max_concurrent_loads(1).build();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most_in_flight = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..6)
+      .map(|key| {
+        let cache = cache.clone();
+        let in_flight = in_flight.clone();
+        let most_in_flight = most_in_flight.clone();
+        thread::spawn(move || {
+          let populating_fn = move |key: &i32| {
+            most_in_flight.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Some(key.to_string())
+          };
+          if key % 2 == 0 {
+            cache.get(&key, populating_fn)
+          } else {
+            cache
+              .get_with_timeout(&key, Duration::from_secs(5), populating_fn)
+              .unwrap()
+          }
+        })
+      })
+      .collect();
+    for thread in threads {
+      assert!(thread.join().unwrap().is_some());
+    }
+    assert_eq!(most_in_flight.load(Ordering::SeqCst), 1);
+    assert_eq!(cache.len(), 6);
+  }
+
+  #[test]
+  fn get_mut_fn_loads_once() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder().capacity(10).max_concurrent_loads(1).build();
+    let loads = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..2)
+      .map(|index| {
+        let cache = cache.clone();
+        let loads = loads.clone();
+        thread::spawn(move || {
+          let populating_fn = |key: &i32| {
+            loads.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            Some(key.to_string())
+          };
+          if index == 0 {
+            cache.get(&1, populating_fn)
+          } else {
+            cache.get_mut_fn(&1, populating_fn)
+          }
+        })
+      })
+      .collect();
+    for thread in threads {
+      assert_eq!(*thread.join().unwrap().unwrap(), "1");
+    }
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn rejects_loads_over_the_limit() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(10)
+      .max_concurrent_loads(1)
+      .reject_excess_loads()
+      .build();
+    let value = cache.get(&1, |key| {
+      assert_eq!(cache.get_reporting(&2, do_not_invoke), (None, Source::Throttled));
+      populate(key)
+    });
+    assert_eq!(*value.unwrap(), "1");
+    assert_eq!(cache.get_if_present(&2), None);
+    assert_eq!(*cache.get(&2, populate).unwrap(), "2");
+  }
+
+  #[test]
+  fn restores_snapshots() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    cache.get(&2, populate);
+    cache.get_caching_misses(&3, miss);
+    let mut snapshot = cache.snapshot();
+    snapshot.sort();
+    assert_eq!(snapshot, vec![(1, "1".to_string()), (2, "2".to_string())]);
+    let restored = test_cache();
+    restored.get(&1, |_| Some("newer".to_string()));
+    restored.restore(snapshot);
+    assert_eq!(restored.len(), 2);
+    assert_eq!(*restored.get(&1, do_not_invoke).unwrap(), "newer");
+    assert_eq!(*restored.get(&2, do_not_invoke).unwrap(), "2");
+  }
+
+  #[test]
+  fn restoring_writes_not_through() {
+    let (cache, store) = written_through(3);
+    cache.restore(vec![(1, "1".to_string()), (2, "2".to_string())]);
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "2");
+    assert!(store.0.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn populates_with_mutable_state() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let mut buffer = String::new();
+    let mut populating_fn = |key: &i32| {
+      buffer.push_str(&key.to_string());
+      Some(buffer.clone())
+    };
+    assert_eq!(*cache.get_mut_fn(&1, &mut populating_fn).unwrap(), "1");
+    assert_eq!(*cache.get_mut_fn(&2, &mut populating_fn).unwrap(), "12");
+    assert_eq!(*cache.get_mut_fn(&1, &mut populating_fn).unwrap(), "1");
+    assert_eq!(buffer, "12");
+  }
+
+  #[test]
+  fn maintenance_removes_expired_entries() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .time_to_live(Duration::from_millis(50))
+      .negative_time_to_live(Duration::from_millis(50))
+      .build();
+    cache.get(&1, populate);
+    cache.get(&2, miss);
+    thread::sleep(Duration::from_millis(60));
+    cache.get(&3, populate);
+    assert_eq!(cache.run_maintenance(), 2);
+    assert_eq!(cache.keys(), vec![3]);
+    // the evictor got the slots back
+    cache.get(&4, populate);
+    cache.get(&5, populate);
+    assert_eq!(cache.len(), 3);
+  }
+
+  #[test]
+  fn background_maintenance_stops_with_the_cache() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(10));
+    let maintenance = cache.spawn_maintenance(Duration::from_millis(5));
+    cache.get(&1, populate);
+    while !cache.is_empty() {
+      thread::yield_now();
+    }
+    drop(cache);
+    maintenance.join().unwrap();
+  }
+
+  #[test]
+  fn invalidates_namespaces() {
+    let cache: CacheThrough<(u8, i32), String> = CacheThrough::new(4);
+    cache.get(&(1, 1), |(_, id)| populate(id));
+    cache.get(&(1, 2), |(_, id)| populate(id));
+    cache.get_caching_misses(&(1, 3), |(_, id)| miss(id));
+    cache.get(&(2, 1), |(_, id)| populate(id));
+    assert_eq!(cache.invalidate_namespace(&1), 3);
+    assert_eq!(cache.keys(), vec![(2, 1)]);
+    assert_eq!(cache.invalidate_namespace(&3), 0);
+    // the evictor got the slots back
+    for id in 2..5 {
+      cache.get(&(2, id), |(_, id)| populate(id));
+    }
+    assert_eq!(cache.len(), 4);
+  }
+
+  #[test]
+  fn counts_evictions() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    for key in 1..=4 {
+      cache.get(&key, populate);
+    }
+    assert_eq!(cache.eviction_count(), 1);
+    cache.remove(4);
+    assert_eq!(cache.eviction_count(), 1);
+    cache.evict_to(1);
+    assert_eq!(cache.eviction_count(), 2);
+  }
+
+  #[test]
+  #[cfg(feature = "async")]
+  fn blocks_on_async_populating_fns() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let value = cache.get_blocking_async(&1, |key| {
+      let (sender, receiver) = futures::channel::oneshot::channel();
+      let key = *key;
+      thread::spawn(move || sender.send(populate(&key)).unwrap());
+      async move { receiver.await.unwrap() }
+    });
+    assert_eq!(*value.unwrap(), "1");
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+  }
+
+  #[test]
+  fn counts_coalesced_loads() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    assert_eq!(cache.coalesced_loads(), 0);
+    // as a miss that waited for another thread to populate its key would find it
+    assert_eq!(*cache.populate(1, do_not_invoke, false).unwrap(), "1");
+    assert_eq!(cache.coalesced_loads(), 1);
+  }
+
+  #[test]
+  fn timed_out_population_still_populates() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let value = cache.get_with_timeout(&1, Duration::from_millis(10), move |key| {
+      released.recv().unwrap();
+      populate(key)
+    });
+    assert_eq!(value, Err(Timeout));
+    assert_eq!(cache.get_if_present(&1), None);
+    release.send(()).unwrap();
+    while cache.get_if_present(&1).is_none() {
+      std::thread::yield_now();
+    }
+    assert_eq!(*cache.get_if_present(&1).unwrap(), "1");
+  }
+
+  #[test]
+  fn timed_out_loads_are_shared() {
+    let loads = Arc::new(AtomicUsize::new(0));
+    let timed = loads.clone();
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .on_load(move |_| {
+        timed.fetch_add(1, Ordering::SeqCst);
+      })
+      .build();
+    let invocations = Arc::new(AtomicUsize::new(0));
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let released = Arc::new(Mutex::new(released));
+    for _ in 0..3 {
+      let (invocations, released) = (invocations.clone(), released.clone());
+      let value = cache.get_with_timeout(&1, Duration::from_millis(10), move |key| {
+        invocations.fetch_add(1, Ordering::SeqCst);
+        released.lock().unwrap().recv().unwrap();
+        populate(key)
+      });
+      assert_eq!(value, Err(Timeout));
+    }
+    release.send(()).unwrap();
+    let value = cache.get_with_timeout(&1, Duration::from_secs(5), |_| unreachable!());
+    assert_eq!(*value.unwrap().unwrap(), "1");
+    assert_eq!(invocations.load(Ordering::SeqCst), 1);
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn clones_share_entries() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let clone = cache.clone();
+    cache.get(&1, populate);
+    assert_eq!(*clone.get(&1, do_not_invoke).unwrap(), "1");
+    clone.update(2, upsert);
+    assert_eq!(*cache.get_if_present(&2).unwrap(), "2");
+    assert!(Arc::ptr_eq(&cache.stats, &clone.stats));
+    drop(cache);
+    assert_eq!(clone.len(), 2);
+  }
 
-    {
-      let value = cache.get(our_key, populate);
-      assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+  #[test]
+  fn prefetches_absent_keys_once() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    let (started, starting) = std::sync::mpsc::channel();
+    let (release, released) = std::sync::mpsc::channel();
+    let prefetching = cache.prefetch(vec![1, 2], move |key| {
+      assert_eq!(*key, 2);
+      started.send(()).unwrap();
+      released.recv().unwrap();
+      populate(key)
+    });
+    starting.recv().unwrap();
+    release.send(()).unwrap();
+    // waits for the prefetching thread to populate the key
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "2");
+    prefetching.join().unwrap();
+    assert_eq!(cache.len(), 2);
+  }
+
+  #[test]
+  fn get_or_default_inserts_not() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(*cache.get_or_default(&1, "default".to_string()), "default");
+    assert_eq!(cache.len(), 0);
+    assert_eq!(*cache.get(&1, populate).unwrap(), "1");
+    assert_eq!(*cache.get_or_default(&1, "default".to_string()), "1");
+  }
+
+  #[test]
+  fn refreshes_stale_entries_at_once() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(400));
+    cache.get(&1, populate);
+    cache.get(&2, populate);
+    thread::sleep(Duration::from_millis(200));
+    cache.get(&3, populate);
+    cache.refresh_stale(Duration::from_millis(100), |_| unreachable!());
+
+    cache.refresh_stale(Duration::from_millis(300), |keys| {
+      let mut keys = keys.to_vec();
+      keys.sort_unstable();
+      assert_eq!(keys, vec![1, 2]);
+      vec![(1, "1 refreshed".to_string())].into_iter().collect()
+    });
+    assert_eq!(*cache.get_if_present(&1).unwrap(), "1 refreshed");
+    assert_eq!(cache.get_if_present(&2), None);
+    assert_eq!(*cache.get_if_present(&3).unwrap(), "3");
+    assert_eq!(cache.len(), 2);
+
+    cache.refresh_stale(Duration::MAX, |keys| {
+      assert_eq!(keys.len(), 2);
+      Default::default()
+    });
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn entries_insert_or_modify() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(*cache.entry(1).or_insert_with(|| "1".to_string()), "1");
+    assert_eq!(*cache.entry(1).or_insert_with(|| unreachable!()), "1");
+    let entry = cache.entry(1).and_modify(|value| value.push('!'));
+    assert_eq!(entry.key(), &1);
+    assert_eq!(*entry.or_insert_with(|| unreachable!()), "1!");
+    assert_eq!(
+      *cache.entry(2).and_modify(|_| unreachable!()).or_insert("2".to_string()),
+      "2"
+    );
+
+    assert_eq!(cache.get_caching_misses(&3, miss), None);
+    assert_eq!(*cache.entry(3).or_insert_with(|| "3".to_string()), "3");
+    assert_eq!(cache.len(), 3);
+  }
+
+  #[test]
+  fn publishes_events() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let events = cache.subscribe();
+    for key in 1..=4 {
+      cache.get(&key, populate);
     }
+    cache.update(2, update);
+    cache.remove(3);
+    let updated = Arc::new("2 updated!".to_string());
+    assert_eq!(
+      events.try_iter().collect::<Vec<_>>(),
+      vec![
+        CacheEvent::Insert(1, Arc::new("1".to_string())),
+        CacheEvent::Insert(2, Arc::new("2".to_string())),
+        CacheEvent::Insert(3, Arc::new("3".to_string())),
+        CacheEvent::Insert(4, Arc::new("4".to_string())),
+        CacheEvent::Evict(1, Arc::new("1".to_string())),
+        CacheEvent::Update(2, updated),
+        CacheEvent::Remove(3),
+      ]
+    );
+  }
 
-    {
-      let value = cache.update(our_key, updel);
-      assert_eq!(value, None);
-      assert_eq!(cache.len(), 0);
+  #[test]
+  fn drops_events_subscribers_lag_behind_on() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let lagging = cache.subscribe();
+    drop(cache.subscribe());
+    for _ in 0..1030 {
+      cache.update(1, |key, _| populate(key));
     }
+    assert_eq!(lagging.try_iter().count(), 1024);
+    assert_eq!(cache.dropped_events(), 6);
+  }
+
+  #[test]
+  fn survives_poisoning() {
+    let cache: Arc<CacheThrough<i32, String>> = Arc::new(test_cache());
+    let other_cache = cache.clone();
+    cache.get(&1, populate);
+
+    let result = thread::spawn(move || other_cache.get(&2, |_| panic!("populating failed"))).join();
+    assert!(result.is_err());
+    assert!(cache.is_poisoned());
+
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(*cache.get(&2, populate).unwrap(), "2");
+    cache.remove(1);
+    assert_eq!(cache.len(), 1);
+  }
+
+  #[test]
+  fn debugs_keys_when_alternate() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    assert_eq!(format!("{:?}", cache), "CacheThrough { capacity: 3, len: 1 }");
+    assert!(format!("{:#?}", cache).contains("keys: [\n        1,\n    ],"));
+  }
+
+  #[test]
+  fn defaults() {
+    let cache: CacheThrough<i32, String> = CacheThrough::default();
+    assert_eq!(cache.capacity(), 1024);
+    assert!(cache.is_empty());
+  }
+
+  #[test]
+  fn try_get_if_present_never_blocks() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    assert_eq!(*cache.try_get_if_present(&1).unwrap().unwrap(), "1");
+    assert_eq!(cache.try_get_if_present(&2), Ok(None));
+
+    let _guard = cache.write();
+    assert_eq!(cache.try_get_if_present(&1), Err(WouldBlock));
+  }
+
+  #[test]
+  fn update_if_unlocked_never_blocks() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(*cache.update_if_unlocked(1, upsert).unwrap().unwrap(), "1");
+    assert_eq!(*cache.update_if_unlocked(1, update).unwrap().unwrap(), "1 updated!");
 
     {
-      let value = cache.get(our_key, miss);
-      assert_eq!(value, None);
-      assert_eq!(cache.len(), 0);
+      let _guard = cache.read();
+      assert_eq!(
+        cache.update_if_unlocked(1, |_, _| unreachable!()),
+        Err(TryUpdateError::WouldBlock)
+      );
     }
+    assert_eq!(*cache.get_if_present(&1).unwrap(), "1 updated!");
   }
 
   #[test]
-  fn remove_removes() {
+  fn rejects_zero_capacity() {
+    assert_eq!(
+      CacheThrough::<i32, String>::try_build(0).err(),
+      Some(ConfigError::ZeroCapacity)
+    );
+    assert!(CacheThrough::<i32, String>::try_build(1).is_ok());
+  }
+
+  #[test]
+  #[should_panic(expected = "capacity must be greater than zero")]
+  fn new_panics_on_zero_capacity() {
+    CacheThrough::<i32, String>::new(0);
+  }
+
+  #[test]
+  fn get_many_populates_misses_at_once() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&2, populate);
+
+    let values = cache.get_many(&[1, 2, 3], |missing| {
+      assert_eq!(missing, &[1, 3]);
+      vec![Some("1".to_string()), None]
+    });
+    assert_eq!(
+      values,
+      vec![Some(Arc::new("1".to_string())), Some(Arc::new("2".to_string())), None]
+    );
+    assert_eq!(cache.len(), 2);
+
+    let values = cache.get_many(&[2, 1], |_| unimplemented!());
+    assert_eq!(
+      values,
+      vec![Some(Arc::new("2".to_string())), Some(Arc::new("1".to_string()))]
+    );
+  }
+
+  #[test]
+  fn retain_frees_slots() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    cache.get(&2, populate);
+    cache.get(&3, populate);
+
+    cache.retain(|key, value| key % 2 == 1 && **value != "3");
+    assert_eq!(cache.len(), 1);
+
+    cache.get(&4, populate);
+    cache.get(&5, populate);
+    assert_eq!(cache.len(), 3);
+    for key in [1, 4, 5].iter() {
+      assert_eq!(*cache.get(key, do_not_invoke).unwrap(), key.to_string());
+    }
+
+    cache.get(&6, populate);
+    assert_eq!(cache.len(), 3);
+  }
+
+  #[test]
+  fn drain_empties_and_frees_slots() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    for key in 1..4 {
+      cache.get(&key, populate);
+    }
+
+    let mut entries = cache.drain();
+    entries.sort_by_key(|(key, _)| *key);
+    let keys: Vec<i32> = entries.iter().map(|(key, _)| *key).collect();
+    assert_eq!(keys, vec![1, 2, 3]);
+    assert!(cache.is_empty());
+
+    for key in 4..7 {
+      cache.get(&key, populate);
+    }
+    assert_eq!(cache.len(), 3);
+    for key in 4..7 {
+      assert_eq!(*cache.get(&key, do_not_invoke).unwrap(), key.to_string());
+    }
+  }
+
+  #[test]
+  fn times_loads_on_misses_only() {
+    let loads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = loads.clone();
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .on_load(move |took| recorded.lock().unwrap().push(took))
+      .build();
+    cache.get(&1, |key| {
+      thread::sleep(Duration::from_millis(10));
+      populate(key)
+    });
+    cache.get(&1, do_not_invoke);
+    cache.get(&2, miss);
+    let loads = loads.lock().unwrap();
+    assert_eq!(loads.len(), 2);
+    assert!(loads[0] >= Duration::from_millis(10));
+  }
+
+  /// Records what gets written through, failing to write empty values, as well as to delete `13`.
+  #[derive(Default)]
+  struct Store(Mutex<Vec<(i32, Option<String>)>>);
+
+  impl Writer<i32, String> for Arc<Store> {
+    fn write(&self, key: &i32, value: &String) -> Result<(), WriteError> {
+      if value.is_empty() {
+        return Err(WriteError);
+      }
+      self.0.lock().unwrap().push((*key, Some(value.clone())));
+      Ok(())
+    }
+
+    fn delete(&self, key: &i32) -> Result<(), WriteError> {
+      if *key == 13 {
+        return Err(WriteError);
+      }
+      self.0.lock().unwrap().push((*key, None));
+      Ok(())
+    }
+  }
+
+  fn written_through(capacity: usize) -> (CacheThrough<i32, String>, Arc<Store>) {
+    let store = Arc::new(Store::default());
+    let cache = CacheThrough::builder().capacity(capacity).writer(store.clone()).build();
+    (cache, store)
+  }
+
+  #[test]
+  fn update_if_unlocked_reports_write_errors() {
+    let (cache, store) = written_through(3);
+    assert_eq!(*cache.update_if_unlocked(1, upsert).unwrap().unwrap(), "1");
+    assert_eq!(
+      cache.update_if_unlocked(1, |_, _| Some(String::new())),
+      Err(TryUpdateError::WriteError)
+    );
+    assert_eq!(*cache.get_if_present(&1).unwrap(), "1");
+    assert_eq!(*store.0.lock().unwrap(), vec![(1, Some("1".to_string()))]);
+  }
+
+  #[test]
+  fn writes_through_before_applying() {
+    let (cache, store) = written_through(3);
+    cache.get(&1, populate);
+    cache.update(2, upsert);
+    assert_eq!(cache.try_update(2, |_, _| Some(String::new())), Err(WriteError));
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "2");
+    assert_eq!(*cache.remove(2).unwrap(), "2");
+    cache.update(13, upsert);
+    assert_eq!(cache.try_remove(13), Err(WriteError));
+    assert_eq!(*cache.get(&13, do_not_invoke).unwrap(), "13");
+    assert_eq!(
+      *store.0.lock().unwrap(),
+      vec![(2, Some("2".to_string())), (2, None), (13, Some("13".to_string()))]
+    );
+  }
+
+  #[test]
+  fn compute_if_present_writes_through() {
+    let (cache, store) = written_through(3);
+    cache.get(&1, populate);
+    cache.get(&13, populate);
+    assert_eq!(cache.compute_if_present(&1, |_, _| Some(String::new())), None);
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(
+      *cache.compute_if_present(&1, |_, _| Some("one".to_string())).unwrap(),
+      "one"
+    );
+    assert_eq!(cache.compute_if_present(&13, |_, _| None), None);
+    assert_eq!(*cache.get(&13, do_not_invoke).unwrap(), "13");
+    assert_eq!(cache.compute_if_present(&1, |_, _| None), None);
+    assert_eq!(cache.get_if_present(&1), None);
+    assert_eq!(*store.0.lock().unwrap(), vec![(1, Some("one".to_string())), (1, None)]);
+  }
+
+  #[test]
+  fn get_or_insert_writes_through() {
+    let (cache, store) = written_through(3);
+    assert_eq!(*cache.get_or_insert(1, String::new()), "");
+    assert_eq!(cache.get_if_present(&1), None);
+    assert_eq!(*cache.get_or_insert(1, "1".to_string()), "1");
+    assert_eq!(*cache.get_or_insert(1, "one".to_string()), "1");
+    assert_eq!(*store.0.lock().unwrap(), vec![(1, Some("1".to_string()))]);
+  }
+
+  #[test]
+  fn replace_if_writes_through() {
+    let (cache, store) = written_through(3);
+    cache.get(&1, populate);
+    assert!(!cache.replace_if(&1, &"1".to_string(), String::new()));
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert!(cache.replace_if(&1, &"1".to_string(), "one".to_string()));
+    assert_eq!(*store.0.lock().unwrap(), vec![(1, Some("one".to_string()))]);
+  }
+
+  #[test]
+  fn modify_writes_through() {
+    let (cache, store) = written_through(3);
+    cache.get(&1, populate);
+    assert_eq!(cache.modify(&1, |value| value.clear()), None);
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(*cache.modify(&1, |value| value.push('!')).unwrap(), "1!");
+    assert_eq!(*store.0.lock().unwrap(), vec![(1, Some("1!".to_string()))]);
+  }
+
+  #[test]
+  fn retain_writes_not_through() {
+    let (cache, store) = written_through(3);
+    for key in [1, 2, 13].iter() {
+      cache.get(key, populate);
+    }
+    cache.retain(|key, _| *key == 2);
+    assert_eq!(cache.keys(), vec![2]);
+    assert!(store.0.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn drain_writes_not_through() {
+    let (cache, store) = written_through(3);
+    cache.get(&1, populate);
+    cache.get(&13, populate);
+    let mut drained: Vec<i32> = cache.drain().into_iter().map(|(key, _)| key).collect();
+    drained.sort_unstable();
+    assert_eq!(drained, vec![1, 13]);
+    assert!(cache.is_empty());
+    assert!(store.0.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn invalidating_namespaces_writes_not_through() {
+    /// Records the keys deleted.
+    #[derive(Default)]
+    struct Deletes(Mutex<Vec<(u8, i32)>>);
+
+    impl Writer<(u8, i32), String> for Arc<Deletes> {
+      fn write(&self, _: &(u8, i32), _: &String) -> Result<(), WriteError> {
+        Ok(())
+      }
+
+      fn delete(&self, key: &(u8, i32)) -> Result<(), WriteError> {
+        self.0.lock().unwrap().push(*key);
+        Ok(())
+      }
+    }
+
+    let deletes = Arc::new(Deletes::default());
+    let cache: CacheThrough<(u8, i32), String> = CacheThrough::builder().capacity(4).writer(deletes.clone()).build();
+    cache.get(&(1, 1), |(_, id)| populate(id));
+    cache.get(&(1, 2), |(_, id)| populate(id));
+    cache.get_caching_misses(&(1, 3), |(_, id)| miss(id));
+    cache.get(&(2, 1), |(_, id)| populate(id));
+    assert_eq!(cache.invalidate_namespace(&1), 3);
+    assert_eq!(cache.keys(), vec![(2, 1)]);
+    assert!(deletes.0.lock().unwrap().is_empty());
+  }
+
+  #[test]
+  fn loader_is_consulted_before_populating() {
+    let loads = Arc::new(Mutex::new(Vec::new()));
+    let loaded = loads.clone();
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .loader(move |key: &i32| {
+        loaded.lock().unwrap().push(*key);
+        if key % 2 == 0 {
+          Some(format!("loaded {}", key))
+        } else {
+          None
+        }
+      })
+      .build();
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "loaded 2");
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "loaded 2");
+    assert_eq!(*cache.get(&1, populate).unwrap(), "1");
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(*loads.lock().unwrap(), vec![2, 1]);
+  }
+
+  #[test]
+  fn shared_values_arent_wrapped_again() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    let shared = Arc::new("42".to_string());
+    let value = cache.get_shared(&42, |_| Some(shared.clone())).unwrap();
+    assert!(Arc::ptr_eq(&value, &shared));
+    assert!(Arc::ptr_eq(&cache.get(&42, do_not_invoke).unwrap(), &shared));
+
+    let updated = Arc::new("43".to_string());
+    cache.insert_shared(42, updated.clone());
+    assert!(Arc::ptr_eq(&cache.get_if_present(&42).unwrap(), &updated));
+  }
+
+  #[test]
+  #[cfg(feature = "metrics")]
+  fn reports_to_the_metrics_facade() {
+    use metrics::{Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct Counters(Mutex<HashMap<String, Arc<AtomicU64>>>);
+
+    impl Counters {
+      fn get(&self, name: &str) -> u64 {
+        self
+          .0
+          .lock()
+          .unwrap()
+          .get(name)
+          .map_or(0, |count| count.load(Ordering::Relaxed))
+      }
+    }
+
+    impl Recorder for Counters {
+      fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+      fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+      fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+      fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        let mut counters = self.0.lock().unwrap();
+        Counter::from_arc(counters.entry(key.name().to_string()).or_default().clone())
+      }
+
+      fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+      }
+
+      fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::noop()
+      }
+    }
+
+    let counters = Counters::default();
+    metrics::with_local_recorder(&counters, || {
+      let cache: CacheThrough<i32, String> = CacheThrough::builder().capacity(1).metrics_prefix("test").build();
+      cache.get(&1, populate);
+      cache.get(&1, do_not_invoke);
+      cache.get(&2, populate);
+      cache.update(3, upsert);
+    });
+    assert_eq!(counters.get("test_hits"), 1);
+    assert_eq!(counters.get("test_misses"), 2);
+    assert_eq!(counters.get("test_updates"), 1);
+    assert_eq!(counters.get("test_evictions"), 2);
+  }
+
+  #[test]
+  fn insert_many_reports_evictions() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+
+    let evicted = cache.insert_many(vec![(1, "one".to_string()), (2, "2".to_string()), (3, "3".to_string())]);
+    assert!(evicted.is_empty());
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "one");
+
+    let evicted = cache.insert_many((4..=5).map(|key| (key, key.to_string())));
+    assert_eq!(evicted, vec![1, 2]);
+    assert_eq!(cache.len(), 3);
+    for key in [3, 4, 5].iter() {
+      assert_eq!(*cache.get(key, do_not_invoke).unwrap(), key.to_string());
+    }
+  }
+
+  #[test]
+  fn snapshots_keys_and_entries() {
     let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    cache.get(&2, populate);
+    cache.get(&3, miss);
+
+    let mut keys = cache.keys();
+    keys.sort();
+    assert_eq!(keys, vec![1, 2]);
+
+    let mut entries = cache.iter();
+    entries.sort();
+    assert_eq!(
+      entries,
+      vec![(1, Arc::new("1".to_string())), (2, Arc::new("2".to_string()))]
+    );
+  }
+
+  #[test]
+  fn entries_expire() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(200));
     let our_key = 42;
 
     {
-      let value = cache.get(our_key, populate);
+      let value = cache.get(&our_key, populate);
       assert_eq!(*value.unwrap(), "42");
-      assert_eq!(cache.len(), 1);
+      assert_eq!(*cache.get_if_present(&our_key).unwrap(), "42");
     }
 
+    thread::sleep(Duration::from_millis(300));
+
     {
-      cache.remove(our_key);
-      assert_eq!(cache.len(), 0);
+      assert_eq!(cache.get_if_present(&our_key), None);
+      let value = cache.get(&our_key, |key| Some(key.to_string() + " again"));
+      assert_eq!(*value.unwrap(), "42 again");
+      assert_eq!(cache.len(), 1);
     }
   }
 
   #[test]
-  fn evicts() {
-    let cache: CacheThrough<i32, String> = test_cache();
+  fn caches_misses() {
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .negative_time_to_live(Duration::from_millis(200))
+      .build();
+    let our_key = 42;
 
     {
-      assert_eq!(*cache.get(1, populate).unwrap(), "1"); // eviction candidate
+      assert_eq!(cache.get(&our_key, miss), None);
+      assert_eq!(cache.get(&our_key, do_not_invoke), None);
+      assert_eq!(cache.get_many(&[our_key], |_| unimplemented!()), vec![None]);
+      assert_eq!(cache.get_if_present(&our_key), None);
       assert_eq!(cache.len(), 1);
-      assert_eq!(*cache.get(2, populate).unwrap(), "2");
-      assert_eq!(cache.len(), 2);
-      assert_eq!(*cache.get(3, populate).unwrap(), "3");
-      assert_eq!(cache.len(), 3);
-
-      // Clock state & hand:
-      // _
-      // 111
+      assert!(cache.keys().is_empty());
     }
 
+    thread::sleep(Duration::from_millis(300));
+
     {
-      assert_eq!(*cache.get(4, populate).unwrap(), "4"); // evicts 1
-      assert_eq!(cache.len(), 3);
-      //  _
-      // 100
+      assert_eq!(*cache.get(&our_key, populate).unwrap(), "42");
+      assert_eq!(*cache.get(&our_key, do_not_invoke).unwrap(), "42");
+      assert_eq!(cache.len(), 1);
+    }
+  }
 
-      assert_eq!(*cache.get(2, do_not_invoke).unwrap(), "2");
-      assert_eq!(cache.len(), 3);
-      //  _
-      // 110
+  #[test]
+  fn caches_misses_on_demand() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    assert_eq!(cache.get_caching_misses(&1, miss), None);
+    assert_eq!(cache.get_caching_misses(&1, do_not_invoke), None);
+    assert_eq!(cache.get(&1, do_not_invoke), None);
+    assert_eq!(cache.get(&2, miss), None);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(*cache.update(1, upsert).unwrap(), "1");
+    assert_eq!(*cache.get_caching_misses(&1, do_not_invoke).unwrap(), "1");
 
-      assert_eq!(*cache.get(3, do_not_invoke).unwrap(), "3");
-      assert_eq!(cache.len(), 3);
-      //  _
-      // 111
-    }
+    let cache: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(3)
+      .negative_time_to_live(Duration::from_millis(200))
+      .build();
+    assert_eq!(cache.get_caching_misses(&1, miss), None);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(*cache.get_caching_misses(&1, populate).unwrap(), "1");
+  }
 
-    {
-      assert_eq!(*cache.get(5, populate).unwrap(), "5"); // evicts 3
-      assert_eq!(cache.len(), 3);
-      //   _
-      // 010
+  #[test]
+  fn entries_override_ttl() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(10));
+    cache.update_with_ttl(1, |key, _| Some((key.to_string(), Some(Duration::from_secs(60)))));
+    cache.update_with_ttl(2, |key, _| Some((key.to_string(), None)));
+    cache.update_with_ttl(3, |key, _| Some((key.to_string(), Some(Duration::from_millis(1)))));
 
-      assert_eq!(*cache.get(2, do_not_invoke).unwrap(), "2"); // 011
-      assert_eq!(cache.len(), 3);
-      assert_eq!(*cache.get(4, do_not_invoke).unwrap(), "4"); // 111
-      assert_eq!(cache.len(), 3);
+    thread::sleep(Duration::from_millis(20));
+
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1");
+    assert_eq!(cache.get_if_present(&2), None);
+    assert_eq!(cache.get_if_present(&3), None);
+
+    let plain: CacheThrough<i32, String> = test_cache();
+    plain.update_with_ttl(1, |key, _| Some((key.to_string(), Some(Duration::from_millis(1)))));
+    plain.update_with_ttl(2, |key, _| Some((key.to_string(), None)));
+
+    thread::sleep(Duration::from_millis(5));
+
+    assert_eq!(plain.get_if_present(&1), None);
+    assert_eq!(*plain.get(&2, do_not_invoke).unwrap(), "2");
+  }
+
+  #[test]
+  fn entries_keep_their_ttl() {
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_millis(10));
+    cache.update_with_ttl(1, |key, _| Some((key.to_string(), Some(Duration::from_secs(60)))));
+    cache.update_with_ttl(2, |key, _| Some((key.to_string(), Some(Duration::from_secs(60)))));
+    cache.compute_if_present(&1, |_, value| Some(value.to_string() + "!"));
+    assert!(cache.replace_if(&2, &"2".to_string(), "2!".to_string()));
+
+    thread::sleep(Duration::from_millis(20));
+
+    assert_eq!(*cache.get(&1, do_not_invoke).unwrap(), "1!");
+    assert_eq!(*cache.get(&2, do_not_invoke).unwrap(), "2!");
+
+    let cache: CacheThrough<i32, String> = CacheThrough::with_time_to_live(3, Duration::from_secs(60));
+    cache.update_with_ttl(1, |key, _| Some((key.to_string(), Some(Duration::from_millis(1)))));
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(*cache.get(&1, |key| Some(key.to_string() + "!")).unwrap(), "1!");
+    assert!(cache.ttl_remaining(&1).unwrap() <= Duration::from_millis(1));
+  }
+
+  #[test]
+  fn refreshes_ahead_once() {
+    let refreshes = Arc::new(Mutex::new(Vec::<Box<dyn FnOnce() + Send>>::new()));
+    let queue = refreshes.clone();
+    let cache: CacheThrough<i32, String> = CacheThrough::with_refresh_ahead(
+      3,
+      Duration::from_secs(60),
+      Duration::from_secs(60) - Duration::from_millis(10),
+      move |refresh| queue.lock().unwrap().push(refresh),
+    );
+    let our_key = 42;
+
+    {
+      let value = cache.get_refreshing(&our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(refreshes.lock().unwrap().len(), 0);
     }
 
+    thread::sleep(Duration::from_millis(20));
+
     {
-      assert_eq!(*cache.get(6, populate).unwrap(), "6"); // evicts 4
-      assert_eq!(cache.len(), 3);
-      assert_eq!(*cache.get(5, do_not_invoke).unwrap(), "5");
-      assert_eq!(cache.len(), 3);
-      assert_eq!(*cache.get(2, do_not_invoke).unwrap(), "2");
-      assert_eq!(cache.len(), 3);
+      for _ in 0..3 {
+        let value = cache.get_refreshing(&our_key, |key| Some(key.to_string() + " refreshed!"));
+        assert_eq!(*value.unwrap(), "42");
+      }
+      assert_eq!(refreshes.lock().unwrap().len(), 1);
+      let refresh = refreshes.lock().unwrap().pop().unwrap();
+      refresh();
+      assert_eq!(*cache.get(&our_key, do_not_invoke).unwrap(), "42 refreshed!");
     }
   }
 
+  #[test]
+  fn refreshes_ahead_of_unbounded_ttls() {
+    let refreshes = Arc::new(AtomicUsize::new(0));
+    let counter = refreshes.clone();
+    let cache: CacheThrough<i32, String> =
+      CacheThrough::with_refresh_ahead(3, Duration::MAX, Duration::MAX - Duration::from_millis(1), move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+      });
+    cache.get_refreshing(&1, populate);
+    thread::sleep(Duration::from_millis(5));
+    assert_eq!(*cache.get_refreshing(&1, populate).unwrap(), "1");
+    assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "refresh window must be shorter than the time-to-live")]
+  fn refresh_window_shorter_than_ttl() {
+    CacheThrough::<i32, String>::with_refresh_ahead(3, Duration::from_secs(1), Duration::from_secs(1), |_| {});
+  }
+
+  #[test]
+  #[cfg(feature = "prometheus-text")]
+  fn renders_prometheus_text() {
+    let cache: CacheThrough<i32, String> = test_cache();
+    cache.get(&1, populate);
+    cache.get(&1, do_not_invoke);
+    cache.get(&2, populate);
+    cache.get(&3, populate);
+    cache.get(&4, populate);
+
+    assert_eq!(
+      cache.render_prometheus("test"),
+      "# TYPE test_hits counter\n\
+       test_hits_total 1\n\
+       # TYPE test_misses counter\n\
+       test_misses_total 4\n\
+       # TYPE test_coalesced_loads counter\n\
+       test_coalesced_loads_total 0\n\
+       # TYPE test_evictions counter\n\
+       test_evictions_total 1\n\
+       # TYPE test_size gauge\n\
+       test_size 3\n\
+       # TYPE test_capacity gauge\n\
+       test_capacity 3\n\
+       # EOF\n"
+    );
+  }
+
   fn miss(_key: &i32) -> Option<String> {
     None
   }
@@ -388,20 +3702,22 @@ mod bench {
     let t = thread::spawn(move || {
       for warmup in 0..our_key {
         other_cache
-          .get(warmup, |key| Some(key.to_string()))
+          .get(&warmup, |key| Some(key.to_string()))
           .expect("We had a miss?!");
       }
-      let _value = other_cache.get(our_key, |key| Some(key.to_string())); // miss, so populating
+      let _value = other_cache.get(&our_key, |key| Some(key.to_string())); // miss, so populating
       for iteration in 0..10000 {
         {
-          other_cache.get(our_key, |_| unimplemented!()).expect("We had a miss?!");
+          other_cache
+            .get(&our_key, |_| unimplemented!())
+            .expect("We had a miss?!");
           if iteration % 4 == 0 {
             other_cache
               .update(iteration, |key, _| Some(key.to_string()))
               .expect("We had a miss?!");
           } else {
             other_cache
-              .get(iteration as i32, |key| Some(key.to_string()))
+              .get(&iteration, |key| Some(key.to_string()))
               .expect("We had a miss?!");
           }
           if iteration == cache_size / 100 {
@@ -413,7 +3729,7 @@ mod bench {
     other_barrier.wait(); // wait for the other thread to populate
     b.iter(|| {
       for _ in 0..100 {
-        cache.get(our_key, |_| unimplemented!()).expect("We had a miss?!"); // entry should be there!
+        cache.get(&our_key, |_| unimplemented!()).expect("We had a miss?!"); // entry should be there!
       }
     });
     t.join().unwrap();
@@ -0,0 +1,36 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+
+/// A second tier a cache consults on misses, before invoking the `populating_fn`, e.g. a cache
+/// kept on disk or in a remote store, both slower than memory but cheaper than computing the value
+/// from the origin. Values it loads are promoted into the cache, as populated ones are.
+///
+/// Any closure `Fn(&K) -> Option<V>` is a `Loader`, see `CacheThroughBuilder::loader`.
+pub trait Loader<K, V> {
+  /// The value `key` maps to in this tier, `None` should it not hold one.
+  fn load(&self, key: &K) -> Option<V>;
+}
+
+impl<K, V, F> Loader<K, V> for F
+where
+  F: Fn(&K) -> Option<V>,
+{
+  fn load(&self, key: &K) -> Option<V> {
+    self(key)
+  }
+}
+
+pub type BoxedLoader<K, V> = Box<dyn Loader<K, V> + Send + Sync>;
@@ -0,0 +1,33 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Keys that belong to a namespace, e.g. the tenant of a `(tenant, id)` key, so that all the
+/// entries of a namespace can be removed at once, see `CacheThrough::invalidate_namespace`.
+///
+/// Pairs are namespaced by their first element.
+pub trait Namespaced {
+  /// What keys sharing a namespace have in common.
+  type Namespace: PartialEq + ?Sized;
+
+  /// The namespace the key belongs to.
+  fn namespace(&self) -> &Self::Namespace;
+}
+
+impl<N: PartialEq, I> Namespaced for (N, I) {
+  type Namespace = N;
+
+  fn namespace(&self) -> &N {
+    &self.0
+  }
+}
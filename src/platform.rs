@@ -0,0 +1,275 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What the caches need from `std`, or their replacements when built with the `no_std` feature:
+//! `hashbrown` for the hash maps, `spin` for the locks, and a clock that never ticks.
+
+use alloc::sync::Arc;
+
+#[cfg(feature = "std")]
+pub use std::collections::hash_map::{DefaultHasher, Entry, HashMap, RandomState};
+#[cfg(feature = "std")]
+pub use std::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "std")]
+pub use std::time::Instant;
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::hash_map::{DefaultHashBuilder as RandomState, Entry, HashMap};
+#[cfg(not(feature = "std"))]
+pub use spin::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+/// A reader-writer lock that shrugs off poisoning: all operations leave the data they protect in a
+/// consistent state before invoking user-provided functions, so that a panic in one of them
+/// doesn't need to render the cache unusable.
+pub struct RwLock<T> {
+  #[cfg(feature = "std")]
+  inner: std::sync::RwLock<T>,
+  #[cfg(not(feature = "std"))]
+  inner: spin::RwLock<T>,
+}
+
+/// A mutual exclusion lock that shrugs off poisoning, as `RwLock` does.
+pub struct Mutex<T> {
+  #[cfg(feature = "std")]
+  inner: std::sync::Mutex<T>,
+  #[cfg(not(feature = "std"))]
+  inner: spin::Mutex<T>,
+}
+
+#[cfg(feature = "std")]
+impl<T> RwLock<T> {
+  pub fn new(value: T) -> RwLock<T> {
+    RwLock {
+      inner: std::sync::RwLock::new(value),
+    }
+  }
+
+  pub fn read(&self) -> RwLockReadGuard<'_, T> {
+    self.inner.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+
+  /// Acquires the read lock, unless that requires waiting for a writer.
+  pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+    match self.inner.try_read() {
+      Ok(guard) => Some(guard),
+      Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+      Err(std::sync::TryLockError::WouldBlock) => None,
+    }
+  }
+
+  pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+    self.inner.write().unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+
+  /// Acquires the write lock, unless that requires waiting for readers or another writer.
+  pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+    match self.inner.try_write() {
+      Ok(guard) => Some(guard),
+      Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+      Err(std::sync::TryLockError::WouldBlock) => None,
+    }
+  }
+
+  /// Whether a thread panicked while holding the write lock.
+  pub fn is_poisoned(&self) -> bool {
+    self.inner.is_poisoned()
+  }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> RwLock<T> {
+  pub fn new(value: T) -> RwLock<T> {
+    RwLock {
+      inner: spin::RwLock::new(value),
+    }
+  }
+
+  pub fn read(&self) -> RwLockReadGuard<'_, T> {
+    self.inner.read()
+  }
+
+  /// Acquires the read lock, unless that requires waiting for a writer.
+  pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+    self.inner.try_read()
+  }
+
+  pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+    self.inner.write()
+  }
+
+  /// Acquires the write lock, unless that requires waiting for readers or another writer.
+  pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+    self.inner.try_write()
+  }
+
+  /// Spin locks don't get poisoned, as there is no unwinding without `std`.
+  pub fn is_poisoned(&self) -> bool {
+    false
+  }
+}
+
+#[cfg(feature = "std")]
+impl<T> Mutex<T> {
+  pub fn new(value: T) -> Mutex<T> {
+    Mutex {
+      inner: std::sync::Mutex::new(value),
+    }
+  }
+
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+
+  /// Whether a thread panicked while holding the lock.
+  pub fn is_poisoned(&self) -> bool {
+    self.inner.is_poisoned()
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.inner.get_mut().unwrap_or_else(std::sync::PoisonError::into_inner)
+  }
+}
+
+#[cfg(not(feature = "std"))]
+impl<T> Mutex<T> {
+  pub fn new(value: T) -> Mutex<T> {
+    Mutex {
+      inner: spin::Mutex::new(value),
+    }
+  }
+
+  pub fn lock(&self) -> MutexGuard<'_, T> {
+    self.inner.lock()
+  }
+
+  /// Spin locks don't get poisoned, as there is no unwinding without `std`.
+  pub fn is_poisoned(&self) -> bool {
+    false
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    self.inner.get_mut()
+  }
+}
+
+/// Caps the number of its permits held at once, e.g. of loads in flight. Permits are released as
+/// they get dropped, and own a reference to their semaphore, so that they can be moved to the
+/// thread doing the work they were acquired for.
+pub struct Semaphore {
+  permits: Mutex<usize>,
+  #[cfg(feature = "std")]
+  released: std::sync::Condvar,
+}
+
+pub struct SemaphorePermit {
+  semaphore: Arc<Semaphore>,
+}
+
+impl Semaphore {
+  pub fn new(permits: usize) -> Semaphore {
+    Semaphore {
+      permits: Mutex::new(permits),
+      #[cfg(feature = "std")]
+      released: std::sync::Condvar::new(),
+    }
+  }
+
+  /// Acquires a permit, unless that requires waiting for one to be released.
+  pub fn try_acquire(self: &Arc<Self>) -> Option<SemaphorePermit> {
+    let mut permits = self.permits.lock();
+    if *permits == 0 {
+      return None;
+    }
+    *permits -= 1;
+    Some(SemaphorePermit {
+      semaphore: self.clone(),
+    })
+  }
+
+  /// Acquires a permit, waiting for one to be released if need be.
+  #[cfg(feature = "std")]
+  pub fn acquire(self: &Arc<Self>) -> SemaphorePermit {
+    let mut permits = self.permits.lock();
+    while *permits == 0 {
+      permits = self
+        .released
+        .wait(permits)
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    }
+    *permits -= 1;
+    SemaphorePermit {
+      semaphore: self.clone(),
+    }
+  }
+
+  /// Acquires a permit, spinning until one is released if need be.
+  #[cfg(not(feature = "std"))]
+  pub fn acquire(self: &Arc<Self>) -> SemaphorePermit {
+    loop {
+      if let Some(permit) = self.try_acquire() {
+        return permit;
+      }
+      core::hint::spin_loop();
+    }
+  }
+}
+
+impl Drop for SemaphorePermit {
+  fn drop(&mut self) {
+    *self.semaphore.permits.lock() += 1;
+    #[cfg(feature = "std")]
+    self.semaphore.released.notify_one();
+  }
+}
+
+/// A point in time. Without `std` there is no clock to read it from, so that time stands still:
+/// no duration ever elapses, which is why the time-to-live options are only offered with `std`.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, Clone, Copy)]
+pub struct Instant;
+
+#[cfg(not(feature = "std"))]
+impl Instant {
+  pub fn now() -> Instant {
+    Instant
+  }
+
+  pub fn elapsed(&self) -> core::time::Duration {
+    core::time::Duration::from_secs(0)
+  }
+}
+
+/// The 64-bit FNV-1a hasher, used to derive the stable hashes admission policies track keys by.
+#[cfg(not(feature = "std"))]
+pub struct DefaultHasher(u64);
+
+#[cfg(not(feature = "std"))]
+impl DefaultHasher {
+  pub fn new() -> DefaultHasher {
+    DefaultHasher(0xcbf2_9ce4_8422_2325)
+  }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::hash::Hasher for DefaultHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for byte in bytes {
+      self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+}
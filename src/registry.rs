@@ -0,0 +1,396 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hash};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::platform::{Mutex, RwLock, RwLockWriteGuard};
+use crate::segment::Segment;
+use crate::CacheThrough;
+
+/// A budget shared by many caches, e.g. all those of a service, capping the total weight of their
+/// entries, each weighed by its cache's `Weigher`, or counting `1` in caches that don't weigh
+/// theirs.
+///
+/// The registry doesn't keep the caches registered with it alive: those dropped are forgotten.
+/// The caches keep to the budget as they grow: one inserting an entry that takes their total
+/// weight over it has the heaviest caches evict entries to make room, as `enforce` does. These
+/// evictions happen once the inserting cache released its lock, and pass on the caches whose lock
+/// is held meanwhile, e.g. by a thread populating them: should none other have room to make, the
+/// budget stays exceeded until the next insert.
+///
+/// ```
+/// use cachers::{CacheRegistry, CacheThrough};
+///
+/// let registry = CacheRegistry::new(100);
+/// let users = CacheThrough::<usize, String>::new(100);
+/// let orders = CacheThrough::<usize, String>::new(100);
+/// registry.register(&users);
+/// registry.register(&orders);
+/// for key in 0..80 {
+///   users.get(&key, |key| Some(key.to_string()));
+/// }
+/// for key in 0..40 {
+///   orders.get(&key, |key| Some(key.to_string()));
+/// }
+/// assert_eq!(registry.weight(), 100);
+/// assert_eq!(users.len(), 60);
+/// assert_eq!(orders.len(), 40);
+/// ```
+pub struct CacheRegistry {
+  budget: Arc<Budget>,
+}
+
+/// The budget of a registry, the total weight of its caches as they last reported it, and the
+/// caches themselves.
+pub(crate) struct Budget {
+  max_weight: usize,
+  weight: AtomicUsize,
+  caches: Mutex<Vec<Weak<dyn Budgeted>>>,
+}
+
+impl Budget {
+  /// By how much the caches registered exceed the budget.
+  fn excess(&self) -> usize {
+    self.weight.load(Ordering::Relaxed).saturating_sub(self.max_weight)
+  }
+
+  /// Evicts entries from the heaviest caches registered until their total weight fits the budget
+  /// again. Unless `wait`, the caches whose lock is held by another thread, or this one, are
+  /// passed on rather than waited for. Returns the number of entries evicted.
+  pub fn enforce(&self, wait: bool) -> usize {
+    let mut caches = self.live_caches();
+    let mut evictions = 0;
+    loop {
+      let excess = self.excess();
+      if excess == 0 {
+        return evictions;
+      }
+      let heaviest = caches
+        .iter()
+        .enumerate()
+        .filter_map(|(i, cache)| match wait {
+          true => Some((i, cache.weight())),
+          false => cache.try_weight().map(|weight| (i, weight)),
+        })
+        .max_by_key(|&(_, weight)| weight);
+      let heaviest = match heaviest {
+        Some((i, _)) => i,
+        None => return evictions,
+      };
+      let shed = match wait {
+        true => Some(caches[heaviest].shed(excess)),
+        false => caches[heaviest].try_shed(excess),
+      };
+      match shed {
+        Some(shed) if shed > 0 => evictions += shed,
+        // no room to make there, the next heaviest cache is asked instead
+        _ => {
+          caches.swap_remove(heaviest);
+        }
+      }
+    }
+  }
+
+  /// The caches still alive, forgetting about those dropped since.
+  fn live_caches(&self) -> Vec<Arc<dyn Budgeted>> {
+    let mut caches = self.caches.lock();
+    caches.retain(|cache| cache.strong_count() > 0);
+    caches.iter().filter_map(Weak::upgrade).collect()
+  }
+}
+
+/// A cache's share of a registry's budget, through which it reports its weight as it changes.
+/// Dropping it, along with the cache, withdraws the weight it last reported.
+pub(crate) struct Share {
+  budget: Arc<Budget>,
+  reported: usize,
+}
+
+impl Share {
+  /// Reports `weight` as the cache's current one.
+  pub fn report(&mut self, weight: usize) {
+    if weight > self.reported {
+      self.budget.weight.fetch_add(weight - self.reported, Ordering::Relaxed);
+    } else {
+      self.budget.weight.fetch_sub(self.reported - weight, Ordering::Relaxed);
+    }
+    self.reported = weight;
+  }
+
+  /// The budget, should the caches registered exceed it.
+  pub fn exceeded(&self) -> Option<&Arc<Budget>> {
+    match self.budget.excess() {
+      0 => None,
+      _ => Some(&self.budget),
+    }
+  }
+}
+
+impl Drop for Share {
+  fn drop(&mut self) {
+    self.budget.weight.fetch_sub(self.reported, Ordering::Relaxed);
+  }
+}
+
+/// The write lock of a cache, which has the registries the cache took over budget enforce it once
+/// released: doing so while holding it would deadlock with a thread holding the lock of another
+/// cache registered, waiting for this one.
+pub(crate) struct WriteGuard<'a, K, V, S> {
+  segment: Option<RwLockWriteGuard<'a, Segment<K, V, S>>>,
+}
+
+impl<'a, K, V, S> WriteGuard<'a, K, V, S> {
+  pub fn new(segment: RwLockWriteGuard<'a, Segment<K, V, S>>) -> WriteGuard<'a, K, V, S> {
+    WriteGuard { segment: Some(segment) }
+  }
+}
+
+impl<'a, K, V, S> Deref for WriteGuard<'a, K, V, S> {
+  type Target = Segment<K, V, S>;
+
+  fn deref(&self) -> &Segment<K, V, S> {
+    self.segment.as_ref().unwrap()
+  }
+}
+
+impl<'a, K, V, S> DerefMut for WriteGuard<'a, K, V, S> {
+  fn deref_mut(&mut self) -> &mut Segment<K, V, S> {
+    self.segment.as_mut().unwrap()
+  }
+}
+
+impl<'a, K, V, S> Drop for WriteGuard<'a, K, V, S> {
+  fn drop(&mut self) {
+    if let Some(segment) = self.segment.take() {
+      let exceeded = segment.exceeded_budgets();
+      drop(segment);
+      for budget in exceeded {
+        budget.enforce(false);
+      }
+    }
+  }
+}
+
+/// A cache, as seen by the registry: its weight, and a way to shed some of it.
+trait Budgeted: Send + Sync {
+  fn weight(&self) -> usize;
+  /// Same as `weight`, unless that requires waiting for the cache's lock.
+  fn try_weight(&self) -> Option<usize>;
+  fn shed(&self, weight: usize) -> usize;
+  /// Same as `shed`, unless that requires waiting for the cache's lock.
+  fn try_shed(&self, weight: usize) -> Option<usize>;
+  fn share(&self, share: Share);
+}
+
+impl<K, V, S> Budgeted for RwLock<Segment<K, V, S>>
+where
+  K: Eq + Hash + Clone + Send + Sync,
+  V: Send + Sync,
+  S: BuildHasher + Send + Sync,
+{
+  fn weight(&self) -> usize {
+    self.read().weight()
+  }
+
+  fn try_weight(&self) -> Option<usize> {
+    self.try_read().map(|segment| segment.weight())
+  }
+
+  fn shed(&self, weight: usize) -> usize {
+    self.write().shed(weight)
+  }
+
+  fn try_shed(&self, weight: usize) -> Option<usize> {
+    self.try_write().map(|mut segment| segment.shed(weight))
+  }
+
+  fn share(&self, share: Share) {
+    self.write().share(share)
+  }
+}
+
+impl CacheRegistry {
+  /// Creates a registry capping the total weight of the caches registered with it to
+  /// `max_weight`.
+  pub fn new(max_weight: usize) -> CacheRegistry {
+    CacheRegistry {
+      budget: Arc::new(Budget {
+        max_weight,
+        weight: AtomicUsize::new(0),
+        caches: Mutex::new(Vec::new()),
+      }),
+    }
+  }
+
+  /// Registers `cache`, and all its clones, with the registry, then enforces its budget.
+  /// Registering a cache already registered, or a clone of it, does nothing.
+  pub fn register<K, V, S>(&self, cache: &CacheThrough<K, V, S>)
+  where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Send + Sync + 'static,
+  {
+    let data: Weak<dyn Budgeted> = Arc::downgrade(&cache.data) as Weak<RwLock<Segment<K, V, S>>>;
+    {
+      let mut caches = self.budget.caches.lock();
+      if caches.iter().any(|registered| Weak::ptr_eq(registered, &data)) {
+        return;
+      }
+      caches.push(data);
+    }
+    cache.data.share(Share {
+      budget: self.budget.clone(),
+      reported: 0,
+    });
+    self.enforce();
+  }
+
+  /// The maximum total weight of the caches registered.
+  pub fn max_weight(&self) -> usize {
+    self.budget.max_weight
+  }
+
+  /// The current total weight of the caches registered.
+  pub fn weight(&self) -> usize {
+    self.budget.live_caches().iter().map(|cache| cache.weight()).sum()
+  }
+
+  /// Evicts entries from the heaviest caches registered until their total weight fits the
+  /// budget again, each cache picking its entries as its eviction strategy does. Returns the
+  /// number of entries evicted.
+  pub fn enforce(&self) -> usize {
+    self.budget.enforce(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CacheRegistry;
+  use crate::CacheThrough;
+  use core::sync::atomic::Ordering;
+
+  fn populate(key: &i32) -> Option<String> {
+    Some(key.to_string())
+  }
+
+  #[test]
+  fn evicts_from_the_heaviest_cache() {
+    let registry = CacheRegistry::new(6);
+    let light: CacheThrough<i32, String> = CacheThrough::new(10);
+    let heavy: CacheThrough<i32, String> = CacheThrough::builder()
+      .capacity(10)
+      .weigher(100, |_: &i32, value: &String| value.len())
+      .build();
+    light.get(&1, populate);
+    light.get(&2, populate);
+    heavy.get(&10, populate);
+    heavy.get(&20, populate);
+    heavy.get(&30, populate);
+    registry.register(&light);
+    assert_eq!(registry.weight(), 2);
+    registry.register(&heavy);
+    assert_eq!(registry.weight(), 6);
+    assert_eq!(light.len(), 2);
+    assert_eq!(heavy.len(), 2);
+    assert_eq!(registry.enforce(), 0);
+  }
+
+  #[test]
+  fn inserts_keep_to_the_budget() {
+    let registry = CacheRegistry::new(5);
+    let first: CacheThrough<i32, String> = CacheThrough::new(10);
+    let second: CacheThrough<i32, String> = CacheThrough::new(10);
+    registry.register(&first);
+    registry.register(&second);
+    for key in 0..3 {
+      first.get(&key, populate);
+      assert!(registry.weight() <= 5);
+    }
+    for key in 0..4 {
+      second.get(&key, populate);
+      assert!(registry.weight() <= 5);
+    }
+    assert_eq!(first.len(), 3);
+    assert_eq!(second.len(), 2);
+    first.remove(0);
+    second.get(&4, populate);
+    assert_eq!(second.len(), 3);
+    assert_eq!(registry.weight(), 5);
+  }
+
+  #[test]
+  fn inserts_evict_from_the_heaviest_cache() {
+    let registry = CacheRegistry::new(5);
+    let heavy: CacheThrough<i32, String> = CacheThrough::new(10);
+    let light: CacheThrough<i32, String> = CacheThrough::new(10);
+    registry.register(&heavy);
+    registry.register(&light);
+    for key in 0..4 {
+      heavy.get(&key, populate);
+    }
+    light.get(&1, populate);
+    light.get(&2, populate);
+    assert_eq!(registry.weight(), 5);
+    assert_eq!(heavy.len(), 3);
+    assert_eq!(light.len(), 2);
+  }
+
+  #[test]
+  fn updates_of_another_cache_pass_on_the_updating_one() {
+    let registry = CacheRegistry::new(3);
+    let outer: CacheThrough<i32, String> = CacheThrough::new(10);
+    let inner: CacheThrough<i32, String> = CacheThrough::new(10);
+    registry.register(&outer);
+    registry.register(&inner);
+    for key in 0..3 {
+      outer.get(&key, populate);
+    }
+    // outer is the heaviest, but locked while updating: inner makes room instead
+    outer.update(3, |key, _| inner.get(key, populate).map(|value| value.to_string()));
+    assert_eq!(inner.len(), 0);
+    assert_eq!(outer.len(), 3);
+    assert_eq!(registry.weight(), 3);
+  }
+
+  #[test]
+  fn registers_caches_once() {
+    let registry = CacheRegistry::new(10);
+    let cache: CacheThrough<i32, String> = CacheThrough::new(10);
+    registry.register(&cache);
+    registry.register(&cache);
+    registry.register(&cache.clone());
+    cache.get(&1, populate);
+    assert_eq!(registry.weight(), 1);
+    assert_eq!(registry.budget.weight.load(Ordering::Relaxed), 1);
+    assert_eq!(registry.budget.caches.lock().len(), 1);
+  }
+
+  #[test]
+  fn forgets_dropped_caches() {
+    let registry = CacheRegistry::new(1);
+    let cache: CacheThrough<i32, String> = CacheThrough::new(10);
+    registry.register(&cache);
+    cache.get(&1, populate);
+    let clone = cache.clone();
+    drop(cache);
+    assert_eq!(registry.weight(), 1);
+    drop(clone);
+    assert_eq!(registry.weight(), 0);
+    assert!(registry.budget.caches.lock().is_empty());
+  }
+}
@@ -12,102 +12,586 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::admission::AdmissionPolicy;
+use crate::error::WriteError;
+use crate::events::{CacheEvent, Subscribers};
+#[cfg(feature = "debug-internals")]
 use crate::eviction::ClockEvictor;
-use crate::eviction::Evictor;
-use std;
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::ops::Fn;
-use std::sync::Arc;
-
-pub struct Segment<K, V> {
-  data: HashMap<K, CacheEntry<V>>,
-  evictor: ClockEvictor<K>,
+#[cfg(feature = "eviction-metrics")]
+use crate::eviction::EvictionMetrics;
+use crate::eviction::{AnyEvictor, Evictor, Resized};
+use crate::platform::{DefaultHasher, Entry, HashMap, Instant};
+use crate::registry::{Budget, Share};
+#[cfg(feature = "metrics")]
+use crate::stats::MetricNames;
+use crate::weigher::BoxedWeigher;
+use crate::writer::BoxedWriter;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::mem;
+use core::ops::Fn;
+use core::sync::atomic::Ordering;
+use core::sync::atomic::{AtomicBool, AtomicU64};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+
+/// Invoked with the key & value of every entry evicted to make room for another one.
+pub type EvictionListener<K, V> = Box<dyn Fn(&K, &Arc<V>) + Send + Sync>;
+
+/// The key & value of an entry evicted to make room for another one.
+pub type Evicted<K, V> = (K, Arc<V>);
+
+/// The value an entry got updated to, alongside the entry evicted to make room for it, if any.
+pub type Updated<K, V> = (Option<Arc<V>>, Option<Evicted<K, V>>);
+
+/// What a cache knows about one of its entries, besides its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMeta {
+  /// The time elapsed since the entry was last populated or updated.
+  pub age: Duration,
+  /// The number of times the entry was hit since it was last populated or updated.
+  pub hit_count: u64,
+}
+
+pub struct Segment<K, V, S> {
+  data: HashMap<K, CacheEntry<V>, S>,
+  evictor: AnyEvictor<K>,
+  admission: Option<Box<dyn AdmissionPolicy + Send + Sync>>,
+  time_to_live: Option<Duration>,
+  time_to_idle: Option<Duration>,
+  negative_time_to_live: Option<Duration>,
+  refresh_window: Option<Duration>,
+  /// Bumped by `invalidate_all`, entries stamped with an older one being expired.
+  epoch: u64,
+  on_evict: Option<EvictionListener<K, V>>,
+  evictions: u64,
+  weigher: Option<BoxedWeigher<K, V>>,
+  max_weight: usize,
+  /// What to shed the total weight down to, once it exceeds `max_weight`.
+  low_water_weight: usize,
+  max_entry_weight: usize,
+  total_weight: usize,
+  writer: Option<BoxedWriter<K, V>>,
+  subscribers: Subscribers<K, V>,
+  /// The budgets of the registries the cache is registered with.
+  shares: Vec<Share>,
+  #[cfg(feature = "metrics")]
+  metric_names: Option<MetricNames>,
 }
 
+/// A cached value, or a cached miss (i.e. a tombstone) should `value` be `None`.
 struct CacheEntry<V> {
-  value: Arc<V>,
+  value: Option<Arc<V>>,
   index: usize,
+  inserted_at: Instant,
+  /// When the entry was last accessed, in nanoseconds since `inserted_at`.
+  last_accessed: AtomicU64,
+  hit_count: AtomicU64,
+  time_to_live: Option<Duration>,
+  refreshing: AtomicBool,
+  /// The segment's epoch when the value was last set.
+  epoch: u64,
+  /// As weighed when the value was last set, zero without a weigher or for cached misses.
+  weight: usize,
 }
 
-impl<K, V> Segment<K, V>
+impl<K, V, S> Segment<K, V, S>
 where
-  K: std::cmp::Eq + std::hash::Hash + Copy,
+  K: core::cmp::Eq + core::hash::Hash + Clone,
+  S: BuildHasher,
 {
-  pub fn new(capacity: usize) -> Segment<K, V> {
+  #[cfg(test)]
+  pub fn new(capacity: usize, hasher: S) -> Segment<K, V, S> {
+    Segment::with_evictor(crate::eviction::Eviction::Clock.evictor(capacity), hasher)
+  }
+
+  pub fn with_evictor(evictor: AnyEvictor<K>, hasher: S) -> Segment<K, V, S> {
     Segment {
-      data: HashMap::new(),
-      evictor: ClockEvictor::new(capacity),
+      data: HashMap::with_hasher(hasher),
+      evictor,
+      admission: None,
+      time_to_live: None,
+      time_to_idle: None,
+      negative_time_to_live: None,
+      refresh_window: None,
+      epoch: 0,
+      on_evict: None,
+      evictions: 0,
+      weigher: None,
+      max_weight: usize::MAX,
+      low_water_weight: usize::MAX,
+      max_entry_weight: usize::MAX,
+      total_weight: 0,
+      writer: None,
+      subscribers: Subscribers::new(),
+      shares: Vec::new(),
+      #[cfg(feature = "metrics")]
+      metric_names: None,
+    }
+  }
+
+  /// Allocates room for `additional` entries in the map upfront, rather than as they get added.
+  pub fn reserve(&mut self, additional: usize) {
+    self.data.reserve(additional);
+  }
+
+  pub fn admit_with(&mut self, policy: Box<dyn AdmissionPolicy + Send + Sync>) {
+    self.admission = Some(policy);
+  }
+
+  pub fn expire_after(&mut self, time_to_live: Duration) {
+    self.time_to_live = Some(time_to_live);
+  }
+
+  /// Expires entries that weren't accessed for `time_to_idle`, regardless of their time-to-live.
+  pub fn expire_when_idle_for(&mut self, time_to_idle: Duration) {
+    self.time_to_idle = Some(time_to_idle);
+  }
+
+  /// Caches misses, i.e. the `populating_fn` returning `None`, for `negative_time_to_live`.
+  pub fn cache_misses_for(&mut self, negative_time_to_live: Duration) {
+    self.negative_time_to_live = Some(negative_time_to_live);
+  }
+
+  pub fn refresh_ahead(&mut self, refresh_window: Duration) {
+    self.refresh_window = Some(refresh_window);
+  }
+
+  /// Evicts entries, as picked by the evictor, for as long as their total weight exceeds
+  /// `max_weight`.
+  pub fn weigh_with(&mut self, weigher: BoxedWeigher<K, V>, max_weight: usize) {
+    self.weigher = Some(weigher);
+    self.max_weight = max_weight;
+  }
+
+  /// Once their total weight exceeds `max_weight`, evicts entries until it is down to
+  /// `low_water_weight`, rather than until it just fits.
+  pub fn shed_weight_to(&mut self, low_water_weight: usize) {
+    self.low_water_weight = low_water_weight;
+  }
+
+  /// Never retains values weighing more than `max_entry_weight`: they are handed back to the
+  /// caller, but not cached.
+  pub fn cap_entry_weight(&mut self, max_entry_weight: usize) {
+    self.max_entry_weight = max_entry_weight;
+  }
+
+  /// Reports evictions to the `metrics` facade.
+  #[cfg(feature = "metrics")]
+  pub fn report_metrics(&mut self, names: MetricNames) {
+    self.metric_names = Some(names);
+  }
+
+  /// Persists every update & removal through `writer`, before applying it.
+  pub fn write_through(&mut self, writer: BoxedWriter<K, V>) {
+    self.writer = Some(writer);
+  }
+
+  #[cfg(feature = "std")]
+  pub fn subscribe(&mut self) -> Receiver<CacheEvent<K, V>> {
+    self.subscribers.subscribe()
+  }
+
+  #[cfg(feature = "std")]
+  pub fn dropped_events(&self) -> u64 {
+    self.subscribers.dropped()
+  }
+
+  pub fn on_evict(&mut self, listener: EvictionListener<K, V>) {
+    self.on_evict = Some(listener);
+  }
+
+  #[cfg(test)]
+  pub fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.lookup(key).and_then(|cache_entry| cache_entry.value.clone())
+  }
+
+  /// Looks `key` up, telling cached misses apart from absent entries by returning `Some(None)`.
+  pub fn get_cached<Q>(&self, key: &Q) -> Option<Option<Arc<V>>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.lookup(key).map(|cache_entry| cache_entry.value.clone())
+  }
+
+  /// Same as `get_cached`, but borrows the value rather than sharing it.
+  pub fn get_cached_ref<Q>(&self, key: &Q) -> Option<Option<&V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.lookup(key).map(|cache_entry| cache_entry.value.as_deref())
+  }
+
+  /// Same as `get`, but also returns the entry's `EntryMeta`, this lookup counting as a hit.
+  pub fn get_with_metadata<Q>(&self, key: &Q) -> Option<(Arc<V>, EntryMeta)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let cache_entry = self.lookup(key)?;
+    let meta = EntryMeta {
+      age: cache_entry.inserted_at.elapsed(),
+      hit_count: cache_entry.hit_count.load(Ordering::Relaxed),
+    };
+    Some((cache_entry.value.clone()?, meta))
+  }
+
+  /// Same as `get`, but also tells whether the caller is the one expected to refresh the entry,
+  /// as it is about to expire. Only one caller is ever told to, until the entry is `refreshed`.
+  #[cfg(feature = "std")]
+  pub fn get_or_claim_refresh<Q>(&self, key: &Q) -> Option<(Arc<V>, bool)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.lookup(key).and_then(|cache_entry| {
+      let value = cache_entry.value.clone()?;
+      let due = match (cache_entry.time_to_live.or(self.time_to_live), self.refresh_window) {
+        (Some(time_to_live), Some(refresh_window)) => {
+          cache_entry.inserted_at.elapsed().saturating_add(refresh_window) >= time_to_live
+        }
+        _ => false,
+      };
+      let claimed = due && !cache_entry.refreshing.swap(true, Ordering::AcqRel);
+      Some((value, claimed))
+    })
+  }
+
+  /// Installs the refreshed `value` for `key`, if the entry is still present. Should there be no
+  /// value, the current one is kept until it expires, but the entry can be claimed for refresh again.
+  #[cfg(feature = "std")]
+  pub fn refreshed(&mut self, key: &K, value: Option<V>) {
+    if let Some(cache_entry) = self.data.get_mut(key) {
+      match value {
+        Some(value) => {
+          let (time_to_live, epoch) = (cache_entry.time_to_live, cache_entry.epoch);
+          let value = Arc::new(value);
+          self
+            .subscribers
+            .publish(|| CacheEvent::Update(key.clone(), value.clone()));
+          let value = Some(value);
+          cache_entry.weighed(
+            weigh(&self.weigher, key, &value),
+            &mut self.total_weight,
+            &mut self.evictor,
+          );
+          cache_entry.renew(value, time_to_live, epoch);
+          self.shed_weight();
+        }
+        None => cache_entry.refreshing.store(false, Ordering::Release),
+      }
     }
   }
 
-  pub fn get(&self, key: &K) -> Option<Arc<V>> {
-    if let Some(cache_entry) = self.data.get(key) {
-      self.evictor.touch(cache_entry.index);
-      return Some(cache_entry.value.clone());
+  /// The time left until the entry for `key` expires, zero should it already have, or `None` if
+  /// there is no such entry, or it is a cached miss. Unlike a lookup, this doesn't count as an
+  /// access to the entry.
+  #[cfg(feature = "std")]
+  pub fn ttl_remaining<Q>(&self, key: &Q) -> Option<Duration>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let cache_entry = self.data.get(key)?;
+    cache_entry.value.as_ref()?;
+    if cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch) {
+      return Some(Duration::from_secs(0));
     }
-    None
+    let age = cache_entry.inserted_at.elapsed();
+    let outliving = cache_entry
+      .time_to_live
+      .or(self.time_to_live)
+      .map(|time_to_live| time_to_live.saturating_sub(age));
+    let idling = self.time_to_idle.map(|time_to_idle| {
+      let idle = age.saturating_sub(Duration::from_nanos(cache_entry.last_accessed.load(Ordering::Relaxed)));
+      time_to_idle.saturating_sub(idle)
+    });
+    Some(match (outliving, idling) {
+      (Some(outliving), Some(idling)) => outliving.min(idling),
+      (remaining, None) | (None, remaining) => remaining.unwrap_or(Duration::MAX),
+    })
+  }
+
+  /// The keys of the live entries that expire within `within`.
+  #[cfg(feature = "std")]
+  pub fn expiring_keys(&self, within: Duration) -> Vec<K> {
+    self
+      .data
+      .iter()
+      .filter(|(_, cache_entry)| {
+        cache_entry.value.is_some()
+          && !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch)
+          && match cache_entry.time_to_live.or(self.time_to_live) {
+            Some(time_to_live) => cache_entry.inserted_at.elapsed().saturating_add(within) >= time_to_live,
+            None => false,
+          }
+      })
+      .map(|(key, _)| key.clone())
+      .collect()
+  }
+
+  /// Installs the refreshed `values` for `keys`, as `refreshed` does, and removes the entries of
+  /// the keys that have none. Removed entries aren't deleted through the writer.
+  #[cfg(feature = "std")]
+  pub fn refreshed_all(&mut self, keys: Vec<K>, mut values: HashMap<K, V>) {
+    for key in keys {
+      match values.remove(&key) {
+        Some(value) => self.refreshed(&key, Some(value)),
+        None => {
+          if let Some(cache_entry) = self.data.remove(&key) {
+            self.removed(&cache_entry);
+            self.subscribers.publish(|| CacheEvent::Remove(key.clone()));
+          }
+        }
+      }
+    }
+  }
+
+  fn lookup<Q>(&self, key: &Q) -> Option<&CacheEntry<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(key));
+    }
+    match self.data.get(key) {
+      Some(cache_entry) if !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch) => {
+        self.evictor.touch(cache_entry.index);
+        cache_entry.accessed();
+        cache_entry.hit_count.fetch_add(1, Ordering::Relaxed);
+        Some(cache_entry)
+      }
+      _ => None,
+    }
+  }
+
+  /// Populates `key` with what `populating_fn` returns, should it be absent. That can either be a
+  /// `V`, or an `Arc<V>` already shared outside the segment, which then gets stored as is.
+  ///
+  /// The `populating_fn` only borrows the `key`, which is moved into the map: the one clone made,
+  /// on a miss only, is the evictor's.
+  pub fn get_or_populate<T, F>(&mut self, key: K, populating_fn: F) -> Option<Arc<V>>
+  where
+    T: Into<Arc<V>>,
+    F: FnOnce(&K) -> Option<T>,
+  {
+    let (option, evicted) = self.get_or_populate_reporting_eviction(key, populating_fn, false);
+    self.notify_eviction(evicted);
+    self.shed_weight();
+    option
+  }
+
+  /// Same as `get_or_populate`, but caches misses even without a `negative_time_to_live`, in which
+  /// case they expire as other entries do.
+  pub fn get_or_populate_caching_misses<T, F>(&mut self, key: K, populating_fn: F) -> Option<Arc<V>>
+  where
+    T: Into<Arc<V>>,
+    F: FnOnce(&K) -> Option<T>,
+  {
+    let (option, evicted) = self.get_or_populate_reporting_eviction(key, populating_fn, true);
+    self.notify_eviction(evicted);
+    self.shed_weight();
+    option
   }
 
-  pub fn get_or_populate<F>(&mut self, key: K, populating_fn: F) -> Option<Arc<V>>
+  /// Same as `get_or_populate`, but also returns the entry evicted to make room for the
+  /// populated one, if any. The eviction listener isn't invoked for it, and entries aren't evicted
+  /// by weight, it's up to the caller. Misses are cached regardless of the `negative_time_to_live`
+  /// should `cache_misses` be set.
+  pub fn get_or_populate_reporting_eviction<T, F>(
+    &mut self,
+    key: K,
+    populating_fn: F,
+    cache_misses: bool,
+  ) -> (Option<Arc<V>>, Option<Evicted<K, V>>)
   where
-    F: Fn(&K) -> Option<V>,
+    T: Into<Arc<V>>,
+    F: FnOnce(&K) -> Option<T>,
   {
     let (option, key_evicted) = match self.data.entry(key) {
-      Entry::Occupied(entry) => {
+      Entry::Occupied(mut entry) => {
+        if entry.get().expired(self.time_to_live, self.time_to_idle, self.epoch) {
+          let (value, time_to_live) = match (populating_fn(entry.key()), self.negative_time_to_live) {
+            (None, None) if !cache_misses => {
+              let cache_entry = entry.remove();
+              self.removed(&cache_entry);
+              return (None, None);
+            }
+            // a value replacing an expired one keeps its time-to-live, unlike one replacing a miss
+            (Some(value), _) => {
+              let previous = entry.get();
+              (Some(value.into()), previous.value.as_ref().and(previous.time_to_live))
+            }
+            (None, negative_time_to_live) => (None, negative_time_to_live),
+          };
+          let weight = weigh(&self.weigher, entry.key(), &value);
+          if weight > self.max_entry_weight {
+            let cache_entry = entry.remove();
+            self.removed(&cache_entry);
+            return (value, None);
+          }
+          if let Some(value) = &value {
+            self
+              .subscribers
+              .publish(|| CacheEvent::Insert(entry.key().clone(), value.clone()));
+          }
+          let cache_entry = entry.get_mut();
+          cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+          cache_entry.renew(value, time_to_live, self.epoch);
+        }
         let cache_entry = entry.get();
         self.evictor.touch(cache_entry.index);
-        (Some(cache_entry.value.clone()), None)
+        cache_entry.accessed();
+        (cache_entry.value.clone(), None)
       }
       Entry::Vacant(entry) => {
-        let (option, to_remove) = match populating_fn(entry.key()) {
-          Some(value) => {
+        let value = populating_fn(entry.key()).map(Into::into);
+        let weight = weigh(&self.weigher, entry.key(), &value);
+        let (option, to_remove) = match (value, self.negative_time_to_live) {
+          (None, None) if !cache_misses => (None, None),
+          (value, _) if weight > self.max_entry_weight => (value, None),
+          (value, _) if !admits(&mut self.evictor, &self.admission, entry.key()) => (value, None),
+          (value, negative_time_to_live) => {
+            let time_to_live = if value.is_some() { None } else { negative_time_to_live };
+            if let Some(value) = &value {
+              self
+                .subscribers
+                .publish(|| CacheEvent::Insert(entry.key().clone(), value.clone()));
+            }
             let (index, to_remove) = self.evictor.add(entry.key().clone());
-            let cache_entry = entry.insert(CacheEntry {
-              value: Arc::new(value),
-              index,
-            });
-            (Some(cache_entry.value.clone()), to_remove)
+            let mut cache_entry = CacheEntry::new(value, index, time_to_live, self.epoch);
+            cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+            let cache_entry = entry.insert(cache_entry);
+            (cache_entry.value.clone(), to_remove)
           }
-          None => (None, None),
         };
         (option, to_remove)
       }
     };
 
-    if key_evicted.is_some() {
-      self.data.remove(&key_evicted.unwrap());
-    }
-
-    option
+    (option, key_evicted.and_then(|key| self.evicted(key)))
   }
 
+  #[cfg(test)]
   pub fn update<F>(&mut self, key: K, updating_fn: F) -> Option<Arc<V>>
   where
-    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+    F: FnOnce(&K, Option<Arc<V>>) -> Option<V>,
+  {
+    self.try_update(key, updating_fn).unwrap_or(None)
+  }
+
+  /// Same as `update`, but tells a failure of the writer apart, see `try_update_with_ttl`.
+  pub fn try_update<F>(&mut self, key: K, updating_fn: F) -> Result<Option<Arc<V>>, WriteError>
+  where
+    F: FnOnce(&K, Option<Arc<V>>) -> Option<V>,
   {
+    self.try_update_with_ttl(key, |key, previous| {
+      updating_fn(key, previous).map(|value| (value, None))
+    })
+  }
+
+  /// Same as `update`, but the `updating_fn` can also override the segment's time-to-live for
+  /// the entry it returns, which can be an `Arc<V>` as for `get_or_populate`. Should the writer
+  /// fail to persist the update, `None` is returned, see `try_update_with_ttl`.
+  pub fn update_with_ttl<T, F>(&mut self, key: K, updating_fn: F) -> Option<Arc<V>>
+  where
+    T: Into<Arc<V>>,
+    F: FnOnce(&K, Option<Arc<V>>) -> Option<(T, Option<Duration>)>,
+  {
+    self.try_update_with_ttl(key, updating_fn).unwrap_or(None)
+  }
+
+  /// Same as `update_with_ttl`, but tells a failure of the writer apart, in which case the
+  /// mapping is left untouched.
+  pub fn try_update_with_ttl<T, F>(&mut self, key: K, updating_fn: F) -> Result<Option<Arc<V>>, WriteError>
+  where
+    T: Into<Arc<V>>,
+    F: FnOnce(&K, Option<Arc<V>>) -> Option<(T, Option<Duration>)>,
+  {
+    let (option, evicted) = self.update_reporting_eviction(key, updating_fn)?;
+    self.notify_eviction(evicted);
+    self.shed_weight();
+    Ok(option)
+  }
+
+  /// Same as `try_update_with_ttl`, but also returns the entry evicted to make room for the
+  /// updated one, if any. The eviction listener isn't invoked for it, and entries aren't evicted
+  /// by weight, it's up to the caller.
+  pub fn update_reporting_eviction<T, F>(&mut self, key: K, updating_fn: F) -> Result<Updated<K, V>, WriteError>
+  where
+    T: Into<Arc<V>>,
+    F: FnOnce(&K, Option<Arc<V>>) -> Option<(T, Option<Duration>)>,
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(&key));
+    }
     let (option, key_evicted) = match self.data.entry(key) {
-      Entry::Occupied(mut entry) => match updating_fn(entry.key(), Some(entry.get().value.clone())) {
-        Some(value) => {
+      Entry::Occupied(mut entry) => match updating_fn(
+        entry.key(),
+        entry.get().live_value(self.time_to_live, self.time_to_idle, self.epoch),
+      ) {
+        Some((value, time_to_live)) => {
+          let value = Some(value.into());
+          persist(&self.writer, entry.key(), value.as_ref())?;
+          let weight = weigh(&self.weigher, entry.key(), &value);
+          if weight > self.max_entry_weight {
+            self.subscribers.publish(|| CacheEvent::Remove(entry.key().clone()));
+            let cache_entry = entry.remove();
+            self.removed(&cache_entry);
+            return Ok((value, None));
+          }
+          if let Some(value) = &value {
+            self
+              .subscribers
+              .publish(|| CacheEvent::Update(entry.key().clone(), value.clone()));
+          }
           let cache_entry = entry.get_mut();
-          cache_entry.value = Arc::new(value);
+          cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+          cache_entry.renew(value, time_to_live, self.epoch);
           self.evictor.touch(cache_entry.index);
-          (Some(cache_entry.value.clone()), None)
+          cache_entry.accessed();
+          (cache_entry.value.clone(), None)
         }
         None => {
-          entry.remove();
+          persist(&self.writer, entry.key(), None)?;
+          self.subscribers.publish(|| CacheEvent::Remove(entry.key().clone()));
+          let cache_entry = entry.remove();
+          self.removed(&cache_entry);
           (None, None)
         }
       },
       Entry::Vacant(entry) => {
-        let (option, key_evicted) = match updating_fn(entry.key(), None) {
-          Some(value) => {
-            let (index, to_remove) = self.evictor.add(*entry.key());
-            let cache_entry = entry.insert(CacheEntry {
-              value: Arc::new(value),
-              index,
-            });
-            (Some(cache_entry.value.clone()), to_remove)
+        let updated = updating_fn(entry.key(), None).map(|(value, time_to_live)| (value.into(), time_to_live));
+        persist(&self.writer, entry.key(), updated.as_ref().map(|(value, _)| value))?;
+        let weight = weigh(
+          &self.weigher,
+          entry.key(),
+          &updated.as_ref().map(|(value, _)| value.clone()),
+        );
+        let (option, key_evicted) = match updated {
+          Some((value, _)) if weight > self.max_entry_weight => (Some(value), None),
+          Some((value, _)) if !admits(&mut self.evictor, &self.admission, entry.key()) => (Some(value), None),
+          Some((value, time_to_live)) => {
+            self
+              .subscribers
+              .publish(|| CacheEvent::Insert(entry.key().clone(), value.clone()));
+            let (index, to_remove) = self.evictor.add(entry.key().clone());
+            let mut cache_entry = CacheEntry::new(Some(value), index, time_to_live, self.epoch);
+            cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+            let cache_entry = entry.insert(cache_entry);
+            (cache_entry.value.clone(), to_remove)
           }
           None => (None, None),
         };
@@ -115,34 +599,734 @@ where
       }
     };
 
-    if key_evicted.is_some() {
-      self.data.remove(&key_evicted.unwrap());
+    Ok((option, key_evicted.and_then(|key| self.evicted(key))))
+  }
+
+  pub fn compute_if_present<Q, F>(&mut self, key: &Q, computing_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    F: FnOnce(&K, Arc<V>) -> Option<V>,
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(key));
+    }
+    let (computed, weight) = match self.data.get_key_value(key) {
+      Some((key, cache_entry)) if !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch) => {
+        match &cache_entry.value {
+          Some(value) => {
+            let computed = computing_fn(key, value.clone()).map(Arc::new);
+            persist(&self.writer, key, computed.as_ref()).ok()?;
+            let weight = weigh(&self.weigher, key, &computed);
+            self.subscribers.publish(|| match &computed {
+              Some(value) => CacheEvent::Update(key.clone(), value.clone()),
+              None => CacheEvent::Remove(key.clone()),
+            });
+            (computed, weight)
+          }
+          None => return None,
+        }
+      }
+      _ => return None,
+    };
+    match computed {
+      Some(value) => {
+        let cache_entry = self.data.get_mut(key)?;
+        cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+        cache_entry.renew(Some(value), cache_entry.time_to_live, self.epoch);
+        self.evictor.touch(cache_entry.index);
+        cache_entry.accessed();
+        let value = cache_entry.value.clone();
+        self.shed_weight();
+        value
+      }
+      None => {
+        if let Some(cache_entry) = self.data.remove(key) {
+          self.removed(&cache_entry);
+        }
+        None
+      }
+    }
+  }
+
+  /// Returns the live value for `key`, only inserting the one `default` returns should there be
+  /// none. A cached miss gets overridden by that value.
+  pub fn get_or_insert_with<F>(&mut self, key: K, default: F) -> Arc<V>
+  where
+    F: FnOnce() -> V,
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(&key));
     }
+    let (inserted, key_evicted) = match self.data.entry(key) {
+      Entry::Occupied(mut entry) => {
+        let cache_entry = entry.get();
+        self.evictor.touch(cache_entry.index);
+        cache_entry.accessed();
+        if let Some(existing) = cache_entry.live_value(self.time_to_live, self.time_to_idle, self.epoch) {
+          return existing;
+        }
+        let value = Arc::new(default());
+        if persist(&self.writer, entry.key(), Some(&value)).is_err() {
+          return value;
+        }
+        self
+          .subscribers
+          .publish(|| CacheEvent::Insert(entry.key().clone(), value.clone()));
+        let value = Some(value);
+        let weight = weigh(&self.weigher, entry.key(), &value);
+        let cache_entry = entry.get_mut();
+        cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+        cache_entry.renew(value, None, self.epoch);
+        (cache_entry.value.clone(), None)
+      }
+      Entry::Vacant(entry) => {
+        if !admits(&mut self.evictor, &self.admission, entry.key()) {
+          return Arc::new(default());
+        }
+        let value = Arc::new(default());
+        if persist(&self.writer, entry.key(), Some(&value)).is_err() {
+          return value;
+        }
+        self
+          .subscribers
+          .publish(|| CacheEvent::Insert(entry.key().clone(), value.clone()));
+        let (index, to_remove) = self.evictor.add(entry.key().clone());
+        let mut cache_entry = CacheEntry::new(Some(value), index, None, self.epoch);
+        cache_entry.weighed(
+          weigh(&self.weigher, entry.key(), &cache_entry.value),
+          &mut self.total_weight,
+          &mut self.evictor,
+        );
+        let cache_entry = entry.insert(cache_entry);
+        (cache_entry.value.clone(), to_remove)
+      }
+    };
+    let evicted = key_evicted.and_then(|key| self.evicted(key));
+    self.notify_eviction(evicted);
+    self.shed_weight();
+    inserted.expect("entry was just populated")
+  }
 
-    match option {
-      Some(cache_entry) => Some(cache_entry.clone()),
-      None => None,
+  /// Replaces the live value for `key` with `new`, only should it equal `expected`. Returns whether
+  /// it did.
+  pub fn replace_if<Q>(&mut self, key: &Q, expected: &V, new: V) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    V: PartialEq,
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(key));
     }
+    let new = Some(Arc::new(new));
+    let weight = match self.data.get_key_value(key) {
+      Some((key, cache_entry)) if !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch) => {
+        match &cache_entry.value {
+          Some(value) if **value == *expected => {
+            if persist(&self.writer, key, new.as_ref()).is_err() {
+              return false;
+            }
+            if let Some(new) = &new {
+              self
+                .subscribers
+                .publish(|| CacheEvent::Update(key.clone(), new.clone()));
+            }
+            weigh(&self.weigher, key, &new)
+          }
+          _ => return false,
+        }
+      }
+      _ => return false,
+    };
+    let cache_entry = match self.data.get_mut(key) {
+      Some(cache_entry) => cache_entry,
+      None => return false,
+    };
+    cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+    cache_entry.renew(new, cache_entry.time_to_live, self.epoch);
+    self.evictor.touch(cache_entry.index);
+    cache_entry.accessed();
+    self.shed_weight();
+    true
+  }
+
+  /// Inserts all `entries`, overriding existing ones, and returns the keys evicted along the way.
+  pub fn insert_many<I>(&mut self, entries: I) -> Vec<K>
+  where
+    I: IntoIterator<Item = (K, V)>,
+  {
+    let mut evicted_keys = Vec::new();
+    for (key, value) in entries {
+      // entries the writer fails to persist are skipped
+      let evicted = self
+        .update_reporting_eviction(key, |_, _| Some((value, None)))
+        .ok()
+        .and_then(|(_, evicted)| evicted);
+      for evicted in evicted.into_iter().chain(self.shed_weight_reporting_evictions()) {
+        evicted_keys.push(evicted.0.clone());
+        self.notify_eviction(Some(evicted));
+      }
+    }
+    evicted_keys
+  }
+
+  /// Mutates the value for `key` in place, only cloning it first should it be shared, e.g. with a
+  /// caller still holding on to it.
+  pub fn modify<Q, F>(&mut self, key: &Q, modifying_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    V: Clone,
+    F: FnOnce(&mut V),
+  {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(key));
+    }
+    let cache_entry = self.data.get_mut(key)?;
+    if cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch) {
+      return None;
+    }
+    if self.writer.is_some() {
+      // the value is only altered once persisted, so that a failing writer leaves it untouched
+      let mut modified = V::clone(cache_entry.value.as_ref()?);
+      modifying_fn(&mut modified);
+      let modified = Arc::new(modified);
+      let (stored_key, _) = self.data.get_key_value(key)?;
+      persist(&self.writer, stored_key, Some(&modified)).ok()?;
+      let cache_entry = self.data.get_mut(key)?;
+      cache_entry.value = Some(modified);
+    } else {
+      modifying_fn(Arc::make_mut(cache_entry.value.as_mut()?));
+    }
+    let cache_entry = self.data.get_mut(key)?;
+    cache_entry.inserted_at = Instant::now();
+    *cache_entry.refreshing.get_mut() = false;
+    self.evictor.touch(cache_entry.index);
+    cache_entry.accessed();
+    let value = cache_entry.value.clone();
+    if let (Some((key, _)), Some(value)) = (self.data.get_key_value(key), &value) {
+      self
+        .subscribers
+        .publish(|| CacheEvent::Update(key.clone(), value.clone()));
+    }
+    if self.weigher.is_some() {
+      let weight = self
+        .data
+        .get_key_value(key)
+        .map(|(key, cache_entry)| weigh(&self.weigher, key, &cache_entry.value));
+      if let (Some(weight), Some(cache_entry)) = (weight, self.data.get_mut(key)) {
+        cache_entry.weighed(weight, &mut self.total_weight, &mut self.evictor);
+      }
+      self.shed_weight();
+    }
+    value
+  }
+
+  /// Evicts entries until their total weight fits the segment's `max_weight` again, down to its
+  /// `low_water_weight`, notifying the eviction listener of each of them.
+  fn shed_weight(&mut self) {
+    for evicted in self.shed_weight_reporting_evictions() {
+      self.notify_eviction(Some(evicted));
+    }
+  }
+
+  fn shed_weight_reporting_evictions(&mut self) -> Vec<Evicted<K, V>> {
+    let mut evicted = Vec::new();
+    if self.total_weight > self.max_weight {
+      let target = self.max_weight.min(self.low_water_weight);
+      while self.total_weight > target {
+        match self.evictor.evict() {
+          Some(key) => evicted.extend(self.evicted(key)),
+          None => break,
+        }
+      }
+    }
+    // the registries this takes over budget have their heaviest caches make room, once the lock
+    // is released, see `WriteGuard`
+    self.report_weight();
+    evicted
+  }
+
+  /// Counts this cache towards the budget of a registry.
+  pub fn share(&mut self, share: Share) {
+    self.shares.push(share);
+    self.report_weight();
+  }
+
+  /// Reports the current weight to the registries the cache is registered with.
+  fn report_weight(&mut self) {
+    let weight = self.weight();
+    for share in &mut self.shares {
+      share.report(weight);
+    }
+  }
+
+  /// Evicts entries, as picked by the evictor, until no more than `target_len` are left. Returns
+  /// the number of entries evicted.
+  pub fn evict_to(&mut self, target_len: usize) -> usize {
+    let mut evictions = 0;
+    while self.data.len() > target_len {
+      match self.evictor.evict() {
+        Some(key) => {
+          let evicted = self.evicted(key);
+          self.notify_eviction(evicted);
+          evictions += 1;
+        }
+        None => break,
+      }
+    }
+    evictions
+  }
+
+  /// The total weight of the entries, or their number when they aren't weighed.
+  pub fn weight(&self) -> usize {
+    match self.weigher {
+      Some(_) => self.total_weight,
+      None => self.data.len(),
+    }
+  }
+
+  /// Evicts entries, as picked by the evictor, until at least `weight` got freed. Returns the
+  /// number of entries evicted.
+  pub fn shed(&mut self, weight: usize) -> usize {
+    let target = self.weight().saturating_sub(weight);
+    let mut evictions = 0;
+    while self.weight() > target {
+      match self.evictor.evict() {
+        Some(key) => {
+          let evicted = self.evicted(key);
+          self.notify_eviction(evicted);
+          evictions += 1;
+        }
+        None => break,
+      }
+    }
+    evictions
+  }
+
+  /// Accounts for `cache_entry` having been removed from `data`, freeing its slot.
+  fn removed(&mut self, cache_entry: &CacheEntry<V>) {
+    self.evictor.remove(cache_entry.index);
+    self.total_weight -= cache_entry.weight;
+    self.report_weight();
+  }
+
+  fn evicted(&mut self, key: K) -> Option<Evicted<K, V>> {
+    let cache_entry = self.data.remove(&key)?;
+    self.total_weight -= cache_entry.weight;
+    self.report_weight();
+    self.evictions += 1;
+    if let Some(value) = &cache_entry.value {
+      self
+        .subscribers
+        .publish(|| CacheEvent::Evict(key.clone(), value.clone()));
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.metric_names {
+      metrics::counter!(names.evictions.clone()).increment(1);
+    }
+    cache_entry.value.map(|value| (key, value))
+  }
+
+  fn notify_eviction(&self, evicted: Option<Evicted<K, V>>) {
+    if let (Some((key, value)), Some(listener)) = (evicted, &self.on_evict) {
+      listener(&key, &value);
+    }
+  }
+
+  /// Removes the entry for `key`, returning its value unless it was a cached miss or had expired.
+  /// Should the writer fail to persist the removal, the entry is kept and `None` is returned.
+  pub fn remove(&mut self, key: &K) -> Option<Arc<V>> {
+    self.try_remove(key).unwrap_or(None)
+  }
+
+  /// Removes all `keys`, as `remove` does, returning how many of them had a live value.
+  pub fn remove_many<I>(&mut self, keys: I) -> usize
+  where
+    I: IntoIterator<Item = K>,
+  {
+    keys.into_iter().filter(|key| self.remove(key).is_some()).count()
+  }
+
+  /// Same as `remove`, but tells a failure of the writer apart.
+  pub fn try_remove(&mut self, key: &K) -> Result<Option<Arc<V>>, WriteError> {
+    if let Some(admission) = &self.admission {
+      admission.record(hash(key));
+    }
+    persist(&self.writer, key, None)?;
+    match self.data.remove(key) {
+      Some(cache_entry) => {
+        self.removed(&cache_entry);
+        self.subscribers.publish(|| CacheEvent::Remove(key.clone()));
+        Ok(cache_entry.live_value(self.time_to_live, self.time_to_idle, self.epoch))
+      }
+      None => Ok(None),
+    }
+  }
+
+  /// Removes all entries for which `f` returns `false`, freeing their slots in the evictor. Cached
+  /// misses are kept. Removals only affect the cache, they aren't persisted by the writer.
+  pub fn retain<F>(&mut self, f: F)
+  where
+    F: Fn(&K, &Arc<V>) -> bool,
+  {
+    self.remove_where(|key, cache_entry| match &cache_entry.value {
+      Some(value) => !f(key, value),
+      None => false,
+    });
+  }
+
+  /// Pins the entry for `key`, or unpins it, as the evictor allows, see `Evictor::pin`. Returns
+  /// whether it did, `false` should there be no value for `key`.
+  pub fn pin<Q>(&mut self, key: &Q, pinned: bool) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    match self.data.get(key) {
+      Some(cache_entry) if cache_entry.value.is_some() => self.evictor.pin(cache_entry.index, pinned),
+      _ => false,
+    }
+  }
+
+  /// Removes the entries of all keys for which `f` returns `true`, cached misses included, freeing
+  /// their slots in the evictor. Returns the number of entries removed.
+  pub fn remove_keys<F>(&mut self, f: F) -> usize
+  where
+    F: Fn(&K) -> bool,
+  {
+    self.remove_where(|key, _| f(key))
+  }
+
+  /// Removes all expired entries, cached misses included, freeing their slots in the evictor.
+  /// Returns the number of entries removed.
+  pub fn remove_expired(&mut self) -> usize {
+    let (time_to_live, time_to_idle, epoch) = (self.time_to_live, self.time_to_idle, self.epoch);
+    self.remove_where(|_, cache_entry| cache_entry.expired(time_to_live, time_to_idle, epoch))
+  }
+
+  /// Removes all entries for which `remove` returns `true`, freeing their slots in the evictor and
+  /// publishing the removal of live ones. Removals only affect the cache, they aren't persisted by
+  /// the writer. Returns the number of entries removed.
+  fn remove_where<F>(&mut self, mut remove: F) -> usize
+  where
+    F: FnMut(&K, &CacheEntry<V>) -> bool,
+  {
+    let evictor = &mut self.evictor;
+    let total_weight = &mut self.total_weight;
+    let subscribers = &mut self.subscribers;
+    let len = self.data.len();
+    self.data.retain(|key, cache_entry| {
+      if !remove(key, cache_entry) {
+        return true;
+      }
+      evictor.remove(cache_entry.index);
+      *total_weight -= cache_entry.weight;
+      if cache_entry.value.is_some() {
+        subscribers.publish(|| CacheEvent::Remove(key.clone()));
+      }
+      false
+    });
+    self.report_weight();
+    len - self.data.len()
   }
 
-  #[cfg(test)]
   pub fn len(&self) -> usize {
     self.data.len()
   }
+
+  /// Expires all entries at once, by bumping the segment's epoch. They are only removed lazily,
+  /// as they get repopulated, updated or evicted.
+  pub fn invalidate_all(&mut self) {
+    self.epoch += 1;
+  }
+
+  /// Approximates the bytes the segment holds: the buckets of its map, a key & slot for every
+  /// slot of the evictor, and every value's `Arc`, plus what `heap_size_of` reports it holds on
+  /// the heap.
+  pub fn estimated_memory_bytes<F>(&self, heap_size_of: F) -> usize
+  where
+    F: Fn(&V) -> usize,
+  {
+    // hashbrown adds a control byte to every bucket
+    let buckets = self.data.capacity() * (mem::size_of::<(K, CacheEntry<V>)>() + 1);
+    let slots = self.evictor.capacity() * (mem::size_of::<K>() + mem::size_of::<usize>());
+    let values: usize = self
+      .data
+      .values()
+      .filter_map(|cache_entry| cache_entry.value.as_ref())
+      .map(|value| 2 * mem::size_of::<usize>() + mem::size_of::<V>() + heap_size_of(value))
+      .sum();
+    buckets + slots + values
+  }
+
+  pub fn keys(&self) -> Vec<K> {
+    self
+      .data
+      .iter()
+      .filter(|(_, cache_entry)| {
+        cache_entry.value.is_some() && !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch)
+      })
+      .map(|(key, _)| key.clone())
+      .collect()
+  }
+
+  pub fn entries(&self) -> Vec<(K, Arc<V>)> {
+    self
+      .data
+      .iter()
+      .filter(|(_, cache_entry)| !cache_entry.expired(self.time_to_live, self.time_to_idle, self.epoch))
+      .filter_map(|(key, cache_entry)| Some((key.clone(), cache_entry.value.clone()?)))
+      .collect()
+  }
+
+  /// Empties the segment, freeing every slot of the evictor, and returns the live entries it held.
+  /// The writer isn't involved: the entries are handed back for the caller to persist.
+  pub fn drain(&mut self) -> Vec<(K, Arc<V>)> {
+    let (time_to_live, time_to_idle, epoch) = (self.time_to_live, self.time_to_idle, self.epoch);
+    let mut drained = Vec::new();
+    self.remove_where(|key, cache_entry| {
+      if let (false, Some(value)) = (
+        cache_entry.expired(time_to_live, time_to_idle, epoch),
+        &cache_entry.value,
+      ) {
+        drained.push((key.clone(), value.clone()));
+      }
+      true
+    });
+    drained
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.evictor.capacity()
+  }
+
+  /// Changes the segment's capacity, evicting entries down to it when shrinking.
+  pub fn resize(&mut self, capacity: usize) {
+    let Resized { evicted, relocated } = self.evictor.resize(capacity);
+    for key in evicted {
+      let evicted = self.evicted(key);
+      self.notify_eviction(evicted);
+    }
+    if !relocated.is_empty() {
+      let relocated: HashMap<usize, usize> = relocated.into_iter().collect();
+      for cache_entry in self.data.values_mut() {
+        if let Some(index) = relocated.get(&cache_entry.index) {
+          cache_entry.index = *index;
+        }
+      }
+    }
+  }
+
+  pub fn evictions(&self) -> u64 {
+    self.evictions
+  }
+
+  #[cfg(feature = "eviction-metrics")]
+  pub fn eviction_metrics(&self) -> Option<EvictionMetrics> {
+    self.evictor.metrics()
+  }
+
+  #[cfg(feature = "debug-internals")]
+  pub fn clock(&self) -> Option<&ClockEvictor<K>> {
+    self.evictor.clock()
+  }
+}
+
+impl<K, V, S> Segment<K, V, S> {
+  /// The budgets of the registries the cache is registered with that are exceeded.
+  pub fn exceeded_budgets(&self) -> Vec<Arc<Budget>> {
+    self.shares.iter().filter_map(Share::exceeded).cloned().collect()
+  }
+}
+
+impl<K, V, S> Segment<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone + fmt::Debug,
+  S: BuildHasher,
+{
+  /// Adds the capacity & length of the segment to `debug`, as well as its keys if `keys` is set.
+  pub fn debug_fields(&self, debug: &mut fmt::DebugStruct<'_, '_>, keys: bool) {
+    debug.field("capacity", &self.capacity()).field("len", &self.len());
+    if keys {
+      debug.field("keys", &self.keys());
+    }
+  }
+}
+
+impl<K, V, S> fmt::Debug for Segment<K, V, S>
+where
+  K: core::cmp::Eq + core::hash::Hash + Clone + fmt::Debug,
+  S: BuildHasher,
+{
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let keys = f.alternate();
+    let mut debug = f.debug_struct("Segment");
+    self.debug_fields(&mut debug, keys);
+    debug.finish()
+  }
+}
+
+impl<V> CacheEntry<V> {
+  fn new(value: Option<Arc<V>>, index: usize, time_to_live: Option<Duration>, epoch: u64) -> CacheEntry<V> {
+    CacheEntry {
+      value,
+      index,
+      inserted_at: Instant::now(),
+      last_accessed: AtomicU64::new(0),
+      hit_count: AtomicU64::new(0),
+      time_to_live,
+      refreshing: AtomicBool::new(false),
+      epoch,
+      weight: 0,
+    }
+  }
+
+  /// Sets the entry's `weight`, accounting for the difference in `total_weight`, and hands it to
+  /// the `evictor`.
+  fn weighed<K: Hash>(&mut self, weight: usize, total_weight: &mut usize, evictor: &mut AnyEvictor<K>) {
+    *total_weight = *total_weight - self.weight + weight;
+    self.weight = weight;
+    evictor.set_weight(self.index, weight);
+  }
+
+  fn renew(&mut self, value: Option<Arc<V>>, time_to_live: Option<Duration>, epoch: u64) {
+    self.value = value;
+    self.inserted_at = Instant::now();
+    *self.last_accessed.get_mut() = 0;
+    *self.hit_count.get_mut() = 0;
+    self.time_to_live = time_to_live;
+    *self.refreshing.get_mut() = false;
+    self.epoch = epoch;
+  }
+
+  /// Records an access to the entry, pushing back its idle expiry.
+  fn accessed(&self) {
+    let since_inserted = self.inserted_at.elapsed().as_nanos() as u64;
+    self.last_accessed.store(since_inserted, Ordering::Relaxed);
+  }
+
+  /// Whether the entry outlived its own time-to-live, or the segment's `time_to_live` if it has
+  /// none of its own, or went unaccessed for `time_to_idle`.
+  fn expired(&self, time_to_live: Option<Duration>, time_to_idle: Option<Duration>, epoch: u64) -> bool {
+    let age = self.inserted_at.elapsed();
+    let outlived = match self.time_to_live.or(time_to_live) {
+      Some(time_to_live) => age >= time_to_live,
+      None => false,
+    };
+    let idle = match time_to_idle {
+      Some(time_to_idle) => {
+        age.saturating_sub(Duration::from_nanos(self.last_accessed.load(Ordering::Relaxed))) >= time_to_idle
+      }
+      None => false,
+    };
+    outlived || idle || self.epoch < epoch
+  }
+
+  fn live_value(&self, time_to_live: Option<Duration>, time_to_idle: Option<Duration>, epoch: u64) -> Option<Arc<V>> {
+    if self.expired(time_to_live, time_to_idle, epoch) {
+      None
+    } else {
+      self.value.clone()
+    }
+  }
+}
+
+/// Weighs the entry mapping `key` to `value`, zero for cached misses or without a `weigher`.
+fn weigh<K, V>(weigher: &Option<BoxedWeigher<K, V>>, key: &K, value: &Option<Arc<V>>) -> usize {
+  match (weigher, value) {
+    (Some(weigher), Some(value)) => weigher.weigh(key, value),
+    _ => 0,
+  }
+}
+
+/// Persists the change of `key` to `value`, its removal for `None`, should there be a `writer`.
+fn persist<K, V>(writer: &Option<BoxedWriter<K, V>>, key: &K, value: Option<&Arc<V>>) -> Result<(), WriteError> {
+  match (writer, value) {
+    (Some(writer), Some(value)) => writer.write(key, value),
+    (Some(writer), None) => writer.delete(key),
+    (None, _) => Ok(()),
+  }
+}
+
+fn hash<Q: Hash + ?Sized>(key: &Q) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  key.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// Whether the `AdmissionPolicy`, if any, lets `key` in, at the expense of the next eviction victim
+fn admits<K: Hash>(
+  evictor: &mut AnyEvictor<K>,
+  admission: &Option<Box<dyn AdmissionPolicy + Send + Sync>>,
+  key: &K,
+) -> bool {
+  match admission {
+    Some(admission) => match evictor.candidate() {
+      Some(victim) => admission.admit(hash(key), hash(victim)),
+      None => true,
+    },
+    None => true,
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::Segment;
+  use std::collections::hash_map::RandomState;
+  use std::hash::{Hash, Hasher};
+  use std::sync::atomic::{AtomicUsize, Ordering};
   use std::sync::Arc;
+  use std::thread;
+  use std::time::Duration;
+
+  fn test_segment() -> Segment<i32, String, RandomState> {
+    Segment::new(3, RandomState::new())
+  }
+
+  /// A key counting how many times it got cloned.
+  #[derive(Debug)]
+  struct CountedKey(i32, Arc<AtomicUsize>);
+
+  impl Clone for CountedKey {
+    fn clone(&self) -> CountedKey {
+      self.1.fetch_add(1, Ordering::SeqCst);
+      CountedKey(self.0, self.1.clone())
+    }
+  }
 
-  fn test_segment() -> Segment<i32, String> {
-    Segment::new(3)
+  impl PartialEq for CountedKey {
+    fn eq(&self, other: &CountedKey) -> bool {
+      self.0 == other.0
+    }
+  }
+
+  impl Eq for CountedKey {}
+
+  impl Hash for CountedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+      self.0.hash(state);
+    }
+  }
+
+  #[test]
+  fn populating_clones_the_key_once() {
+    let clones = Arc::new(AtomicUsize::new(0));
+    let mut segment: Segment<CountedKey, String, RandomState> = Segment::new(3, RandomState::new());
+
+    let value = segment.get_or_populate(CountedKey(42, clones.clone()), |key| Some(key.0.to_string()));
+    assert_eq!(*value.unwrap(), "42");
+    assert_eq!(clones.load(Ordering::SeqCst), 1);
+
+    let value = segment.get_or_populate(CountedKey(42, clones.clone()), |_| None::<String>);
+    assert_eq!(*value.unwrap(), "42");
+    assert_eq!(clones.load(Ordering::SeqCst), 1);
   }
 
   #[test]
   fn hit_populates() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
     {
       let value = segment.get_or_populate(our_key, populate);
@@ -159,7 +1343,7 @@ mod tests {
 
   #[test]
   fn miss_populates_not() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
     {
       let value = segment.get_or_populate(our_key, miss);
@@ -170,7 +1354,7 @@ mod tests {
 
   #[test]
   fn get_or_populate_evicts() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
     {
       let value = segment.get_or_populate(our_key, populate);
@@ -186,7 +1370,7 @@ mod tests {
 
   #[test]
   fn update_populates() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
 
     {
@@ -204,7 +1388,7 @@ mod tests {
 
   #[test]
   fn update_updates() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
 
     {
@@ -228,7 +1412,7 @@ mod tests {
 
   #[test]
   fn update_evicts() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
     {
       let value = segment.update(our_key, upsert);
@@ -244,7 +1428,7 @@ mod tests {
 
   #[test]
   fn update_removes() {
-    let mut segment: Segment<i32, String> = test_segment();
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
     let our_key = 42;
 
     {
@@ -266,6 +1450,116 @@ mod tests {
     }
   }
 
+  #[test]
+  fn reports_evictions() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    for key in 1..4 {
+      assert_eq!(segment.get_or_populate_reporting_eviction(key, populate, false).1, None);
+    }
+
+    let (value, evicted) = segment.get_or_populate_reporting_eviction(4, populate, false);
+    assert_eq!(*value.unwrap(), "4");
+    assert_eq!(evicted, Some((1, Arc::new("1".to_string()))));
+
+    let (value, evicted) = segment
+      .update_reporting_eviction(5, |key, _| Some((key.to_string(), None)))
+      .unwrap();
+    assert_eq!(*value.unwrap(), "5");
+    assert_eq!(evicted, Some((2, Arc::new("2".to_string()))));
+    assert_eq!(segment.len(), 3);
+  }
+
+  #[test]
+  fn evicts_to_target_len() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    for key in 1..4 {
+      segment.get_or_populate(key, populate);
+    }
+    assert_eq!(segment.evict_to(3), 0);
+    assert_eq!(segment.evict_to(1), 2);
+    assert_eq!(segment.keys(), vec![3]);
+    assert_eq!(segment.evict_to(0), 1);
+    assert_eq!(segment.evict_to(0), 0);
+    assert_eq!(segment.evictions, 3);
+    assert_eq!(segment.capacity(), 3);
+  }
+
+  #[test]
+  fn estimates_memory_of_live_values() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    let empty = segment.estimated_memory_bytes(String::capacity);
+    segment.get_or_populate(1, |_| Some("x".repeat(1000)));
+    segment.get_or_populate(2, |_| None::<String>);
+    let populated = segment.estimated_memory_bytes(String::capacity);
+    assert!(populated >= empty + 1000);
+    segment.remove(&1);
+    assert!(segment.estimated_memory_bytes(String::capacity) < populated - 1000);
+  }
+
+  #[test]
+  fn expired_entries_repopulate() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    segment.expire_after(Duration::from_millis(10));
+    let our_key = 42;
+
+    {
+      let value = segment.get_or_populate(our_key, populate);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+    }
+
+    thread::sleep(Duration::from_millis(20));
+
+    {
+      assert_eq!(segment.get(&our_key), None);
+      let value = segment.update(our_key, upsert);
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+      assert_eq!(segment.len(), 1);
+    }
+  }
+
+  #[test]
+  fn invalidated_entries_repopulate() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    segment.get_or_populate(1, populate);
+    segment.get_or_populate(2, populate);
+    segment.invalidate_all();
+    assert_eq!(segment.get(&1), None);
+    assert!(segment.keys().is_empty());
+    assert_eq!(
+      segment.update(2, |_, previous| previous.map(|_| "stale".to_string())),
+      None
+    );
+    let value = segment.get_or_populate(1, |key: &i32| Some(key.to_string() + " again"));
+    assert_eq!(*value.unwrap(), "1 again");
+    assert_eq!(segment.keys(), vec![1]);
+    assert_eq!(segment.len(), 1);
+  }
+
+  #[test]
+  fn refresh_claimed_once() {
+    let mut segment: Segment<i32, String, RandomState> = test_segment();
+    segment.expire_after(Duration::from_secs(60));
+    segment.refresh_ahead(Duration::from_secs(60));
+    let our_key = 42;
+
+    segment.get_or_populate(our_key, populate);
+    assert_eq!(
+      segment.get_or_claim_refresh(&our_key),
+      Some((Arc::new("42".to_string()), true))
+    );
+    assert_eq!(
+      segment.get_or_claim_refresh(&our_key),
+      Some((Arc::new("42".to_string()), false))
+    );
+    segment.refreshed(&our_key, Some("42 refreshed!".to_string()));
+    assert_eq!(
+      segment.get_or_claim_refresh(&our_key),
+      Some((Arc::new("42 refreshed!".to_string()), true))
+    );
+  }
+
   fn miss(_key: &i32) -> Option<String> {
     None
   }
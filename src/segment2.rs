@@ -12,132 +12,254 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::error::ConfigError;
 use crate::eviction::ClockEvictor;
 use crate::eviction::Evictor;
-use std;
+use crate::softlock::{Failure, Lock, Waiter};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::ops::Fn;
-
-use futures::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub struct Segment<K, V> {
   data: HashMap<K, CacheEntry<V>>,
   evictor: ClockEvictor<K>,
+  locks: u64,
+  time_to_live: Option<Duration>,
+}
+
+enum CacheEntry<V> {
+  Available(CacheValue<V>),
+  Locked(Lock<Arc<V>>, Option<CacheValue<V>>),
 }
 
-struct CacheEntry<V> {
-  value: V,
+struct CacheValue<V> {
+  value: Arc<V>,
   index: usize,
+  inserted_at: Instant,
+}
+
+/// The id of the lock taken on an entry by `Segment::lock`, along with its previous value.
+pub type LockedValue<V> = (u64, Option<Arc<V>>);
+
+/// What the caller of `Segment::get_or_lock` is expected to do next
+pub enum Population<V> {
+  /// The value was present
+  Hit(Arc<V>),
+  /// Another caller holds the lock on the entry, its outcome will be handed to the `Waiter`
+  Wait(Waiter<Arc<V>>),
+  /// The entry is now locked by the caller, who needs to hand the populated value back to
+  /// `Segment::populated` along with the lock id, or `Segment::abandon` the lock
+  Populate(u64),
 }
 
 impl<K, V> Segment<K, V>
 where
   K: std::cmp::Eq + std::hash::Hash + Copy,
-  V: Clone,
 {
   pub fn new(capacity: usize) -> Segment<K, V> {
+    if capacity == 0 {
+      panic!("Invalid cache configuration: {}", ConfigError::ZeroCapacity);
+    }
     Segment {
       data: HashMap::new(),
       evictor: ClockEvictor::new(capacity),
+      locks: 0,
+      time_to_live: None,
     }
   }
 
-  pub fn get(&self, key: &K) -> Option<V> {
-    if let Some(cache_entry) = self.data.get(key) {
-      self.evictor.touch(cache_entry.index);
-      return Some(cache_entry.value.clone());
+  /// Expires `Available` entries `time_to_live` after they were populated. Entries that are
+  /// `Locked` never expire: the populate or update in flight will replace them anyways.
+  pub fn expire_after(&mut self, time_to_live: Duration) {
+    self.time_to_live = Some(time_to_live);
+  }
+
+  pub fn get(&self, key: &K) -> Option<Arc<V>> {
+    if let Some(CacheEntry::Available(cache_value)) = self.data.get(key) {
+      if !cache_value.expired(self.time_to_live) {
+        self.evictor.touch(cache_value.index);
+        return Some(cache_value.value.clone());
+      }
     }
     None
   }
 
-  pub async fn get_or_populate<Fut, F>(&mut self, key: K, populating_fn: F) -> Fut::Output
-  where
-    F: Fn(K) -> Fut,
-    Fut: Future<Output = Option<V>>,
-  {
-    let (option, key_evicted) = match self.data.entry(key) {
-      Entry::Occupied(entry) => {
-        let cache_entry = entry.get();
-        self.evictor.touch(cache_entry.index);
-        (Some(cache_entry.value.clone()), None)
-      }
-      Entry::Vacant(entry) => {
-        let (option, to_remove) = match populating_fn(*entry.key()).await {
-          Some(value) => {
-            let (index, to_remove) = self.evictor.add(entry.key().clone());
-            let cache_entry = entry.insert(CacheEntry {
-              value: value.clone(),
-              index,
-            });
-            (Some(cache_entry.value.clone()), to_remove)
-          }
-          None => (None, None),
-        };
-        (option, to_remove)
+  /// As `get`, but leaves the entry's eviction state alone.
+  pub fn peek(&self, key: &K) -> Option<Arc<V>> {
+    match self.data.get(key) {
+      Some(CacheEntry::Available(cache_value)) if !cache_value.expired(self.time_to_live) => {
+        Some(cache_value.value.clone())
       }
-    };
-
-    if key_evicted.is_some() {
-      self.data.remove(&key_evicted.unwrap());
+      _ => None,
     }
+  }
 
-    option
+  pub fn contains_key(&self, key: &K) -> bool {
+    self.peek(key).is_some()
   }
 
-  pub async fn update<Fut, F>(&mut self, key: K, updating_fn: F) -> Fut::Output
-  where
-    F: Fn(K, Option<V>) -> Fut,
-    Fut: Future<Output = Option<V>>,
-  {
-    let (option, key_evicted) = match self.data.entry(key) {
-      Entry::Occupied(mut entry) => match updating_fn(*entry.key(), Some(entry.get().value.clone())).await {
-        Some(value) => {
-          let cache_entry = entry.get_mut();
-          cache_entry.value = value.clone();
-          self.evictor.touch(cache_entry.index);
-          (Some(cache_entry.value.clone()), None)
+  pub fn get_or_lock(&mut self, key: K) -> Population<V> {
+    match self.data.entry(key) {
+      Entry::Occupied(mut entry) => match entry.get_mut() {
+        CacheEntry::Available(cache_value) if cache_value.expired(self.time_to_live) => {
+          self.evictor.remove(cache_value.index);
+          self.locks += 1;
+          entry.insert(CacheEntry::Locked(Lock::new(self.locks), None));
+          Population::Populate(self.locks)
         }
-        None => {
-          entry.remove();
-          (None, None)
+        CacheEntry::Available(cache_value) => {
+          self.evictor.touch(cache_value.index);
+          Population::Hit(cache_value.value.clone())
         }
+        CacheEntry::Locked(lock, _) => Population::Wait(lock.wait()),
       },
       Entry::Vacant(entry) => {
-        let (option, key_evicted) = match updating_fn(*entry.key(), None).await {
-          Some(value) => {
-            let (index, to_remove) = self.evictor.add(*entry.key());
-            let cache_entry = entry.insert(CacheEntry {
-              value: value.clone(),
-              index,
-            });
-            (Some(cache_entry.value.clone()), to_remove)
-          }
-          None => (None, None),
+        self.locks += 1;
+        entry.insert(CacheEntry::Locked(Lock::new(self.locks), None));
+        Population::Populate(self.locks)
+      }
+    }
+  }
+
+  /// Locks the entry for `key`, whether present or not, so that the caller can update it. Returns
+  /// the lock id along with the previous value, or a `Waiter` if someone else holds the lock.
+  pub fn lock(&mut self, key: K) -> Result<LockedValue<V>, Waiter<Arc<V>>> {
+    self.locks += 1;
+    let lock = Lock::new(self.locks);
+    match self.data.entry(key) {
+      Entry::Occupied(mut entry) => {
+        if let CacheEntry::Locked(lock, _) = entry.get_mut() {
+          return Err(lock.wait());
+        }
+        let cache_value = match entry.insert(CacheEntry::Locked(lock, None)) {
+          CacheEntry::Available(cache_value) => cache_value,
+          CacheEntry::Locked(_, _) => unreachable!(),
         };
-        (option, key_evicted)
+        if cache_value.expired(self.time_to_live) {
+          self.evictor.remove(cache_value.index);
+          return Ok((self.locks, None));
+        }
+        let previous = cache_value.value.clone();
+        if let CacheEntry::Locked(_, slot) = entry.get_mut() {
+          *slot = Some(cache_value);
+        }
+        Ok((self.locks, Some(previous)))
+      }
+      Entry::Vacant(entry) => {
+        entry.insert(CacheEntry::Locked(lock, None));
+        Ok((self.locks, None))
       }
+    }
+  }
+
+  /// Installs the `value` populated by the holder of the lock `id`, waking up all waiters. Should
+  /// the lock be gone in the meantime (e.g. the entry got evicted), the mapping is left untouched.
+  pub fn populated(&mut self, key: K, id: u64, value: Option<V>) -> Option<Arc<V>> {
+    let value = value.map(Arc::new);
+    let (lock, key_evicted) = match self.data.entry(key) {
+      Entry::Occupied(mut entry) => match entry.get_mut() {
+        CacheEntry::Locked(lock, _) if lock.id() == id => match value.clone() {
+          Some(value) => {
+            let (index, to_remove) = match entry.get() {
+              CacheEntry::Locked(_, Some(previous)) => {
+                self.evictor.touch(previous.index);
+                (previous.index, None)
+              }
+              _ => self.evictor.add(*entry.key()),
+            };
+            let lock = entry
+              .insert(CacheEntry::Available(CacheValue {
+                value,
+                index,
+                inserted_at: Instant::now(),
+              }))
+              .into_lock();
+            (lock, to_remove)
+          }
+          None => {
+            // the update removes the entry, which frees the slot the previous value held
+            if let CacheEntry::Locked(_, Some(previous)) = entry.get() {
+              self.evictor.remove(previous.index);
+            }
+            (entry.remove().into_lock(), None)
+          }
+        },
+        _ => (None, None),
+      },
+      Entry::Vacant(_) => (None, None),
     };
 
-    if key_evicted.is_some() {
-      self.data.remove(&key_evicted.unwrap());
+    if let Some(key) = key_evicted {
+      self.data.remove(&key);
+    }
+
+    if let Some(lock) = lock {
+      lock.release(value.clone());
     }
 
-    match option {
-      Some(cache_entry) => Some(cache_entry.clone()),
-      None => None,
+    value
+  }
+
+  /// Gives up on the lock `id`, e.g. because populating the entry failed, letting waiters retry.
+  /// The previous value is restored, should the entry have been locked for an update.
+  pub fn abandon(&mut self, key: K, id: u64) {
+    self.unlock(key, id);
+  }
+
+  /// Same as `abandon`, but hands the `failure` to the waiters, rather than having them retry.
+  pub fn fail(&mut self, key: K, id: u64, failure: Failure) {
+    if let Some(lock) = self.unlock(key, id) {
+      lock.fail(failure);
     }
   }
 
+  fn unlock(&mut self, key: K, id: u64) -> Option<Lock<Arc<V>>> {
+    if let Entry::Occupied(mut entry) = self.data.entry(key) {
+      let previous = match entry.get_mut() {
+        CacheEntry::Locked(lock, previous) if lock.id() == id => previous.take(),
+        _ => return None,
+      };
+      // without a previous value, the entry holds no slot: it was either vacant, or expired and
+      // freed its slot when it got locked
+      return match previous {
+        Some(cache_value) => entry.insert(CacheEntry::Available(cache_value)).into_lock(),
+        None => entry.remove().into_lock(),
+      };
+    }
+    None
+  }
+
   #[cfg(test)]
   pub fn len(&self) -> usize {
     self.data.len()
   }
 }
 
+impl<V> CacheValue<V> {
+  fn expired(&self, time_to_live: Option<Duration>) -> bool {
+    match time_to_live {
+      Some(time_to_live) => self.inserted_at.elapsed() >= time_to_live,
+      None => false,
+    }
+  }
+}
+
+impl<V> CacheEntry<V> {
+  fn into_lock(self) -> Option<Lock<Arc<V>>> {
+    match self {
+      CacheEntry::Available(_) => None,
+      CacheEntry::Locked(lock, _) => Some(lock),
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
-  use super::Segment;
+  use super::{Population, Segment};
+  use futures::future::Future;
+  use std::sync::Arc;
+  use std::time::Duration;
 
   fn test_segment() -> Segment<i32, String> {
     Segment::new(3)
@@ -148,14 +270,14 @@ mod tests {
     let mut segment: Segment<i32, String> = test_segment();
     let our_key = 42;
     {
-      let value = segment.get_or_populate(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, populate).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
 
     {
-      let value = segment.get_or_populate(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, do_not_invoke).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
   }
@@ -165,7 +287,7 @@ mod tests {
     let mut segment: Segment<i32, String> = test_segment();
     let our_key = 42;
     {
-      let value = segment.get_or_populate(our_key, miss).await;
+      let value = get_or_populate(&mut segment, our_key, miss).await;
       assert_eq!(value, None);
       assert_eq!(segment.len(), 0);
     }
@@ -176,13 +298,13 @@ mod tests {
     let mut segment: Segment<i32, String> = test_segment();
     let our_key = 42;
     {
-      let value = segment.get_or_populate(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, populate).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
-      segment.get_or_populate(2, populate).await;
-      segment.get_or_populate(3, populate).await;
+      get_or_populate(&mut segment, 2, populate).await;
+      get_or_populate(&mut segment, 3, populate).await;
       assert_eq!(segment.len(), 3);
-      segment.get_or_populate(4, populate).await;
+      get_or_populate(&mut segment, 4, populate).await;
       assert_eq!(segment.len(), 3);
     }
   }
@@ -193,14 +315,14 @@ mod tests {
     let our_key = 42;
 
     {
-      let value = segment.update(our_key, upsert).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = lock_and_update(&mut segment, our_key, upsert).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
 
     {
-      let value = segment.get_or_populate(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, do_not_invoke).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
   }
@@ -211,20 +333,20 @@ mod tests {
     let our_key = 42;
 
     {
-      let value = segment.get_or_populate(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, populate).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
 
     {
-      let value = segment.update(our_key, update).await;
-      assert_eq!(value.unwrap(), "42 updated!");
+      let value = lock_and_update(&mut segment, our_key, update).await;
+      assert_eq!(*value.unwrap(), "42 updated!");
       assert_eq!(segment.len(), 1);
     }
 
     {
-      let value = segment.get_or_populate(our_key, do_not_invoke).await;
-      assert_eq!(value.unwrap(), "42 updated!");
+      let value = get_or_populate(&mut segment, our_key, do_not_invoke).await;
+      assert_eq!(*value.unwrap(), "42 updated!");
       assert_eq!(segment.len(), 1);
     }
   }
@@ -234,13 +356,13 @@ mod tests {
     let mut segment: Segment<i32, String> = test_segment();
     let our_key = 42;
     {
-      let value = segment.update(our_key, upsert).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = lock_and_update(&mut segment, our_key, upsert).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
-      segment.update(2, upsert).await;
-      segment.update(3, upsert).await;
+      lock_and_update(&mut segment, 2, upsert).await;
+      lock_and_update(&mut segment, 3, upsert).await;
       assert_eq!(segment.len(), 3);
-      segment.update(4, upsert).await;
+      lock_and_update(&mut segment, 4, upsert).await;
       assert_eq!(segment.len(), 3);
     }
   }
@@ -251,24 +373,181 @@ mod tests {
     let our_key = 42;
 
     {
-      let value = segment.get_or_populate(our_key, populate).await;
-      assert_eq!(value.unwrap(), "42");
+      let value = get_or_populate(&mut segment, our_key, populate).await;
+      assert_eq!(*value.unwrap(), "42");
       assert_eq!(segment.len(), 1);
     }
 
     {
-      let value = segment.update(our_key, updel).await;
+      let value = lock_and_update(&mut segment, our_key, updel).await;
       assert_eq!(value, None);
       assert_eq!(segment.len(), 0);
     }
 
     {
-      let value = segment.get_or_populate(our_key, miss).await;
+      let value = get_or_populate(&mut segment, our_key, miss).await;
       assert_eq!(value, None);
       assert_eq!(segment.len(), 0);
     }
   }
 
+  #[tokio::test]
+  async fn waiters_get_populated_value() {
+    let mut segment: Segment<i32, String> = test_segment();
+    let our_key = 42;
+
+    let id = match segment.get_or_lock(our_key) {
+      Population::Populate(id) => id,
+      _ => panic!("Entry should have been locked for us to populate!"),
+    };
+    let waiter = match segment.get_or_lock(our_key) {
+      Population::Wait(waiter) => waiter,
+      _ => panic!("Entry should be locked!"),
+    };
+    assert_eq!(*segment.populated(our_key, id, Some("42".to_string())).unwrap(), "42");
+    assert_eq!(*waiter.await.unwrap().unwrap(), "42");
+    assert_eq!(segment.len(), 1);
+  }
+
+  #[tokio::test]
+  async fn update_waits_for_lock() {
+    let mut segment: Segment<i32, String> = test_segment();
+    let our_key = 42;
+
+    let id = match segment.get_or_lock(our_key) {
+      Population::Populate(id) => id,
+      _ => panic!("Entry should have been locked for us to populate!"),
+    };
+    let waiter = match segment.lock(our_key) {
+      Err(waiter) => waiter,
+      Ok(_) => panic!("Entry should be locked!"),
+    };
+    segment.populated(our_key, id, Some("42".to_string()));
+    assert_eq!(*waiter.await.unwrap().unwrap(), "42");
+
+    let value = lock_and_update(&mut segment, our_key, update).await;
+    assert_eq!(*value.unwrap(), "42 updated!");
+  }
+
+  #[tokio::test]
+  async fn abandon_restores_previous() {
+    let mut segment: Segment<i32, String> = test_segment();
+    let our_key = 42;
+
+    {
+      get_or_populate(&mut segment, our_key, populate).await;
+      let (id, previous) = segment.lock(our_key).ok().unwrap();
+      assert_eq!(*previous.unwrap(), "42");
+      assert_eq!(segment.get(&our_key), None);
+      segment.abandon(our_key, id);
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+    }
+
+    {
+      let id = match segment.get_or_lock(7) {
+        Population::Populate(id) => id,
+        _ => panic!("Entry should have been locked for us to populate!"),
+      };
+      segment.abandon(7, id);
+      assert_eq!(segment.len(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn removed_entries_free_their_slot() {
+    let mut segment: Segment<i32, String> = test_segment();
+    let our_key = 42;
+
+    {
+      let id = match segment.get_or_lock(our_key) {
+        Population::Populate(id) => id,
+        _ => panic!("Entry should have been locked for us to populate!"),
+      };
+      segment.abandon(our_key, id);
+      get_or_populate(&mut segment, our_key, populate).await;
+      get_or_populate(&mut segment, 2, populate).await;
+      get_or_populate(&mut segment, 3, populate).await;
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+      assert_eq!(segment.len(), 3);
+    }
+
+    {
+      lock_and_update(&mut segment, 2, updel).await;
+      get_or_populate(&mut segment, 2, populate).await;
+      lock_and_update(&mut segment, 3, updel).await;
+      get_or_populate(&mut segment, 3, populate).await;
+      assert_eq!(*segment.get(&2).unwrap(), "2");
+      assert_eq!(*segment.get(&3).unwrap(), "3");
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+      assert_eq!(segment.len(), 3);
+    }
+  }
+
+  #[tokio::test]
+  async fn expired_entries_repopulate() {
+    let mut segment: Segment<i32, String> = test_segment();
+    segment.expire_after(Duration::from_millis(200));
+    let our_key = 42;
+
+    {
+      get_or_populate(&mut segment, our_key, populate).await;
+      assert_eq!(*segment.get(&our_key).unwrap(), "42");
+      tokio::time::delay_for(Duration::from_millis(300)).await;
+      assert_eq!(segment.get(&our_key), None);
+    }
+
+    {
+      let id = match segment.get_or_lock(our_key) {
+        Population::Populate(id) => id,
+        _ => panic!("Expired entry should have been locked for us to populate!"),
+      };
+      tokio::time::delay_for(Duration::from_millis(300)).await;
+      match segment.get_or_lock(our_key) {
+        Population::Wait(_) => (),
+        _ => panic!("Locked entry shouldn't expire!"),
+      };
+      segment.populated(our_key, id, Some("42 again".to_string()));
+      assert_eq!(*segment.get(&our_key).unwrap(), "42 again");
+      assert_eq!(segment.len(), 1);
+    }
+
+    {
+      tokio::time::delay_for(Duration::from_millis(300)).await;
+      let value = lock_and_update(&mut segment, our_key, upsert).await;
+      assert_eq!(*value.unwrap(), "42");
+      assert_eq!(segment.len(), 1);
+    }
+  }
+
+  async fn get_or_populate<Fut, F>(
+    segment: &mut Segment<i32, String>,
+    key: i32,
+    populating_fn: F,
+  ) -> Option<Arc<String>>
+  where
+    F: Fn(i32) -> Fut,
+    Fut: Future<Output = Option<String>>,
+  {
+    match segment.get_or_lock(key) {
+      Population::Hit(value) => Some(value),
+      Population::Wait(_) => panic!("No one else should be populating!"),
+      Population::Populate(id) => {
+        let value = populating_fn(key).await;
+        segment.populated(key, id, value)
+      }
+    }
+  }
+
+  async fn lock_and_update<Fut, F>(segment: &mut Segment<i32, String>, key: i32, updating_fn: F) -> Option<Arc<String>>
+  where
+    F: Fn(i32, Option<Arc<String>>) -> Fut,
+    Fut: Future<Output = Option<String>>,
+  {
+    let (id, previous) = segment.lock(key).ok().expect("No one else should hold the lock!");
+    let value = updating_fn(key, previous).await;
+    segment.populated(key, id, value)
+  }
+
   async fn miss(_key: i32) -> Option<String> {
     None
   }
@@ -277,17 +556,17 @@ mod tests {
     Some(key.to_string())
   }
 
-  async fn upsert(key: i32, value: Option<String>) -> Option<String> {
+  async fn upsert(key: i32, value: Option<Arc<String>>) -> Option<String> {
     assert_eq!(value, None);
     populate(key).await
   }
 
-  async fn update(_key: i32, value: Option<String>) -> Option<String> {
+  async fn update(_key: i32, value: Option<Arc<String>>) -> Option<String> {
     let previous = &value.unwrap();
-    Some(previous.clone() + " updated!")
+    Some(previous.to_string() + " updated!")
   }
 
-  async fn updel(_key: i32, value: Option<String>) -> Option<String> {
+  async fn updel(_key: i32, value: Option<Arc<String>>) -> Option<String> {
     assert!(value.is_some());
     None
   }
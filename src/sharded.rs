@@ -0,0 +1,152 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+use crate::CacheThrough;
+
+/// A `CacheThrough` split into independently locked shards, each holding a share of the total
+/// capacity. Keys are assigned to a shard based on their hash, so that populating or updating
+/// entries only contends with operations on keys of the same shard.
+///
+/// Each shard evicts on its own, which means that an entry can be evicted while the cache as a
+/// whole still has spare capacity in other shards.
+///
+/// ```
+/// use cachers::ShardedCache;
+///
+/// let cache = ShardedCache::<i32, String>::with_shards(100, 4);
+/// let value = cache.get(&42, |key| Some(key.to_string()));
+/// assert_eq!(*value.unwrap(), "42");
+/// ```
+pub struct ShardedCache<K, V> {
+  shards: Vec<CacheThrough<K, V>>,
+  hasher: RandomState,
+}
+
+impl<K, V> ShardedCache<K, V>
+where
+  K: std::cmp::Eq + std::hash::Hash + Clone,
+{
+  /// Creates a new `ShardedCache` of the given `capacity`, using four shards per CPU
+  pub fn new(capacity: usize) -> ShardedCache<K, V> {
+    ShardedCache::with_shards(capacity, num_cpus::get() * 4)
+  }
+
+  /// Creates a new `ShardedCache` of the given `capacity`, split across `shards`. There are never
+  /// more shards than `capacity`, so that each shard can hold at least one entry.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `capacity` is zero.
+  pub fn with_shards(capacity: usize, shards: usize) -> ShardedCache<K, V> {
+    let shards = shards.min(capacity).max(1);
+    ShardedCache {
+      shards: (0..shards)
+        .map(|shard| CacheThrough::new(capacity / shards + usize::from(shard < capacity % shards)))
+        .collect(),
+      hasher: RandomState::new(),
+    }
+  }
+
+  /// See `CacheThrough::get`
+  pub fn get<Q, F>(&self, key: &Q, populating_fn: F) -> Option<Arc<V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    F: Fn(&K) -> Option<V>,
+  {
+    self.shard(key).get(key, populating_fn)
+  }
+
+  /// See `CacheThrough::update`
+  pub fn update<F>(&self, key: K, updating_fn: F) -> Option<Arc<V>>
+  where
+    F: Fn(&K, Option<Arc<V>>) -> Option<V>,
+  {
+    self.shard(&key).update(key, updating_fn)
+  }
+
+  /// See `CacheThrough::remove`
+  pub fn remove(&self, key: K) -> Option<Arc<V>> {
+    self.shard(&key).remove(key)
+  }
+
+  /// The number of entries currently held across all shards.
+  pub fn len(&self) -> usize {
+    self.shards.iter().map(CacheThrough::len).sum()
+  }
+
+  /// Whether all shards are currently empty.
+  pub fn is_empty(&self) -> bool {
+    self.shards.iter().all(CacheThrough::is_empty)
+  }
+
+  fn shard<Q>(&self, key: &Q) -> &CacheThrough<K, V>
+  where
+    Q: Hash + ?Sized,
+  {
+    &self.shards[(self.hasher.hash_one(key) % self.shards.len() as u64) as usize]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ShardedCache;
+
+  #[test]
+  fn splits_capacity() {
+    let cache: ShardedCache<i32, String> = ShardedCache::with_shards(10, 4);
+    let capacities: Vec<usize> = cache
+      .shards
+      .iter()
+      .map(|shard| {
+        (0..100).for_each(|key| {
+          shard.get(&key, |key| Some(key.to_string()));
+        });
+        shard.len()
+      })
+      .collect();
+    assert_eq!(capacities, vec![3, 3, 2, 2]);
+    assert_eq!(cache.len(), 10);
+  }
+
+  #[test]
+  fn never_more_shards_than_capacity() {
+    let cache: ShardedCache<i32, String> = ShardedCache::with_shards(2, 4);
+    assert_eq!(cache.shards.len(), 2);
+  }
+
+  #[test]
+  fn routes_to_shards() {
+    let cache: ShardedCache<i32, String> = ShardedCache::with_shards(100, 4);
+    for key in 0..50 {
+      assert_eq!(*cache.get(&key, |key| Some(key.to_string())).unwrap(), key.to_string());
+    }
+    assert_eq!(cache.len(), 50);
+
+    for key in 0..50 {
+      assert_eq!(*cache.get(&key, |_| unimplemented!()).unwrap(), key.to_string());
+    }
+
+    let value = cache.update(42, |_, value| Some(value.unwrap().to_string() + " updated!"));
+    assert_eq!(*value.unwrap(), "42 updated!");
+    cache.remove(42);
+    assert_eq!(cache.len(), 49);
+    assert!(!cache.is_empty());
+  }
+}
@@ -0,0 +1,206 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::eviction::{free_slots, relocate, Evictor, Resized};
+use crate::platform::HashMap;
+
+/// Evicts the heaviest key first, as weighed by the cache's `Weigher`, the one added first among
+/// equally heavy ones. Making room for one large entry by evicting another one, rather than many
+/// small ones, keeps the number of evictions down when weights vary a lot. Accesses are ignored
+/// altogether: this suits caches whose weights matter more than their recency, only.
+pub struct SizeAwareEvictor<K> {
+  capacity: usize,
+  mapping: HashMap<usize, K>,
+  free: Vec<usize>,
+  /// The weight of the key in every slot, and when it was added.
+  slots: Vec<Slot>,
+  /// The slots in use, heaviest & oldest last.
+  heaviest: BTreeSet<Rank>,
+  added: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+  weight: usize,
+  added: u64,
+}
+
+type Rank = (usize, Reverse<u64>, usize);
+
+impl<K> SizeAwareEvictor<K> {
+  pub fn new(capacity: usize) -> SizeAwareEvictor<K> {
+    SizeAwareEvictor {
+      capacity,
+      mapping: HashMap::with_capacity(capacity),
+      free: Vec::new(),
+      slots: vec![Slot::default(); capacity],
+      heaviest: BTreeSet::new(),
+      added: 0,
+    }
+  }
+
+  fn rank(&self, index: usize) -> Rank {
+    let slot = self.slots[index];
+    (slot.weight, Reverse(slot.added), index)
+  }
+
+  /// Evicts the heaviest key, returning its slot along with it.
+  fn victim(&mut self) -> Option<(usize, K)> {
+    let (_, _, index) = self.heaviest.pop_last()?;
+    let key = self.mapping.remove(&index)?;
+    Some((index, key))
+  }
+}
+
+impl<K> Evictor<K> for SizeAwareEvictor<K> {
+  fn add(&mut self, key: K) -> (usize, Option<K>) {
+    // slots freed by `remove` come first, all others are in use up to `mapping.len()`
+    let (index, victim) = if self.mapping.len() < self.capacity {
+      (self.free.pop().unwrap_or(self.mapping.len()), None)
+    } else {
+      match self.victim() {
+        Some((index, victim)) => (index, Some(victim)),
+        None => unreachable!("a full evictor tracks keys"),
+      }
+    };
+    self.mapping.insert(index, key);
+    self.slots[index] = Slot {
+      weight: 0,
+      added: self.added,
+    };
+    self.added += 1;
+    self.heaviest.insert(self.rank(index));
+    (index, victim)
+  }
+
+  fn touch(&self, _index: usize) {}
+
+  fn candidate(&mut self) -> Option<&K> {
+    if self.mapping.len() < self.capacity {
+      return None;
+    }
+    let (_, _, index) = self.heaviest.last()?;
+    self.mapping.get(index)
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    let (index, key) = self.victim()?;
+    self.free.push(index);
+    Some(key)
+  }
+
+  fn remove(&mut self, index: usize) -> Option<K> {
+    let key = self.mapping.remove(&index)?;
+    self.heaviest.remove(&self.rank(index));
+    self.free.push(index);
+    Some(key)
+  }
+
+  fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  fn resize(&mut self, capacity: usize) -> Resized<K> {
+    let mut evicted = Vec::new();
+    while self.mapping.len() > capacity {
+      evicted.extend(self.victim().map(|(_, key)| key));
+    }
+    let relocated = relocate(&mut self.mapping, capacity);
+    for &(from, to) in relocated.iter() {
+      self.heaviest.remove(&self.rank(from));
+      self.slots[to] = self.slots[from];
+      self.heaviest.insert(self.rank(to));
+    }
+    self.slots.resize(capacity, Slot::default());
+    self.free = free_slots(&self.mapping, capacity);
+    self.capacity = capacity;
+    Resized { evicted, relocated }
+  }
+
+  fn set_weight(&mut self, index: usize, weight: usize) {
+    if self.heaviest.remove(&self.rank(index)) {
+      self.slots[index].weight = weight;
+      self.heaviest.insert(self.rank(index));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SizeAwareEvictor;
+  use crate::eviction::{Evictor, Resized};
+
+  #[test]
+  fn evicts_heaviest_first() {
+    let mut evictor = SizeAwareEvictor::new(3);
+    for (key, weight) in [(0, 5), (1, 50), (2, 5)] {
+      let (index, evicted) = evictor.add(key);
+      assert_eq!(evicted, None);
+      evictor.set_weight(index, weight);
+    }
+    assert_eq!(evictor.candidate(), Some(&1));
+    assert_eq!(evictor.add(3), (1, Some(1)));
+    evictor.set_weight(1, 5);
+    assert_eq!(evictor.add(4), (0, Some(0)));
+  }
+
+  #[test]
+  fn reweighs_keys() {
+    let mut evictor = SizeAwareEvictor::new(2);
+    evictor.add(0);
+    evictor.add(1);
+    evictor.set_weight(0, 1);
+    evictor.set_weight(1, 2);
+    assert_eq!(evictor.candidate(), Some(&1));
+    evictor.set_weight(0, 3);
+    assert_eq!(evictor.candidate(), Some(&0));
+  }
+
+  #[test]
+  fn removed_slots_are_reused() {
+    let mut evictor = SizeAwareEvictor::new(2);
+    evictor.add(0);
+    evictor.add(1);
+    evictor.set_weight(0, 10);
+    assert_eq!(evictor.remove(0), Some(0));
+    assert_eq!(evictor.candidate(), None);
+    assert_eq!(evictor.add(2), (0, None));
+    assert_eq!(evictor.evict(), Some(1));
+    assert_eq!(evictor.evict(), Some(2));
+    assert_eq!(evictor.evict(), None);
+  }
+
+  #[test]
+  fn resize_evicts_heaviest() {
+    let mut evictor = SizeAwareEvictor::new(4);
+    for key in 0..4 {
+      let (index, _) = evictor.add(key);
+      evictor.set_weight(index, [1, 9, 8, 2][key]);
+    }
+    assert_eq!(
+      evictor.resize(2),
+      Resized {
+        evicted: vec![1, 2],
+        relocated: vec![(3, 1)],
+      }
+    );
+    assert_eq!(evictor.candidate(), Some(&3));
+    assert_eq!(evictor.add(4), (1, Some(3)));
+  }
+}
@@ -0,0 +1,319 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::eviction::{free_slots, relocate, Evictor, Resized};
+use crate::platform::{HashMap, Mutex};
+
+const NIL: usize = usize::MAX;
+
+/// Segmented LRU: new keys enter a probationary segment and only get promoted to the protected
+/// one once touched. Evictions are taken from the probationary segment, so that a scan of
+/// one-shot keys only ever competes with other keys that haven't been hit yet.
+pub struct SlruEvictor<K> {
+  capacity: usize,
+  protected_percent: u8,
+  mapping: HashMap<usize, K>,
+  free: Vec<usize>,
+  lists: Mutex<Lists>,
+}
+
+/// Both segments, as doubly linked lists threaded through the slots, most recently used first.
+struct Lists {
+  links: Vec<Link>,
+  probation: Ends,
+  protected: Ends,
+  protected_len: usize,
+  protected_capacity: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Link {
+  prev: usize,
+  next: usize,
+  protected: bool,
+}
+
+impl Default for Link {
+  fn default() -> Link {
+    Link {
+      prev: NIL,
+      next: NIL,
+      protected: false,
+    }
+  }
+}
+
+fn protected_capacity(capacity: usize, protected_percent: u8) -> usize {
+  (capacity * protected_percent as usize / 100).min(capacity.saturating_sub(1))
+}
+
+#[derive(Clone, Copy)]
+struct Ends {
+  head: usize,
+  tail: usize,
+}
+
+impl<K> SlruEvictor<K> {
+  /// Creates an evictor for `capacity` keys, of which `protected_percent` percent at most can be
+  /// held in the protected segment. At least one slot is always left for probation.
+  pub fn new(capacity: usize, protected_percent: u8) -> SlruEvictor<K> {
+    SlruEvictor {
+      capacity,
+      protected_percent,
+      mapping: HashMap::with_capacity(capacity),
+      free: Vec::new(),
+      lists: Mutex::new(Lists {
+        links: vec![Link::default(); capacity],
+        probation: Ends { head: NIL, tail: NIL },
+        protected: Ends { head: NIL, tail: NIL },
+        protected_len: 0,
+        protected_capacity: protected_capacity(capacity, protected_percent),
+      }),
+    }
+  }
+
+  fn lists(&mut self) -> &mut Lists {
+    self.lists.get_mut()
+  }
+}
+
+impl Lists {
+  fn ends(&mut self, protected: bool) -> &mut Ends {
+    if protected {
+      &mut self.protected
+    } else {
+      &mut self.probation
+    }
+  }
+
+  fn unlink(&mut self, index: usize) {
+    let Link { prev, next, protected } = self.links[index];
+    match prev {
+      NIL => self.ends(protected).head = next,
+      prev => self.links[prev].next = next,
+    }
+    match next {
+      NIL => self.ends(protected).tail = prev,
+      next => self.links[next].prev = prev,
+    }
+    if protected {
+      self.protected_len -= 1;
+    }
+  }
+
+  fn push_front(&mut self, index: usize, protected: bool) {
+    let head = self.ends(protected).head;
+    self.links[index] = Link {
+      prev: NIL,
+      next: head,
+      protected,
+    };
+    match head {
+      NIL => self.ends(protected).tail = index,
+      head => self.links[head].prev = index,
+    }
+    self.ends(protected).head = index;
+    if protected {
+      self.protected_len += 1;
+    }
+  }
+
+  /// Moves the link at `from` to the slot `to`, repointing its neighbours at it.
+  fn relink(&mut self, from: usize, to: usize) {
+    let link = self.links[from];
+    self.links[to] = link;
+    match link.prev {
+      NIL => self.ends(link.protected).head = to,
+      prev => self.links[prev].next = to,
+    }
+    match link.next {
+      NIL => self.ends(link.protected).tail = to,
+      next => self.links[next].prev = to,
+    }
+  }
+
+  /// Demotes the least recently used protected slots to probation, until the protected segment
+  /// fits its capacity.
+  fn demote_overflow(&mut self) {
+    while self.protected_len > self.protected_capacity {
+      let demoted = self.protected.tail;
+      self.unlink(demoted);
+      self.push_front(demoted, false);
+    }
+  }
+
+  /// The least recently used slot of probation, or of the protected segment should probation be
+  /// empty.
+  fn lru(&self) -> usize {
+    match self.probation.tail {
+      NIL => self.protected.tail,
+      tail => tail,
+    }
+  }
+}
+
+impl<K> Evictor<K> for SlruEvictor<K> {
+  fn add(&mut self, key: K) -> (usize, Option<K>) {
+    let (index, victim) = if self.mapping.len() < self.capacity {
+      (self.free.pop().unwrap_or(self.mapping.len()), None)
+    } else {
+      let index = self.lists().lru();
+      self.lists().unlink(index);
+      (index, self.mapping.remove(&index))
+    };
+    self.mapping.insert(index, key);
+    self.lists().push_front(index, false);
+    (index, victim)
+  }
+
+  fn touch(&self, index: usize) {
+    let mut lists = self.lists.lock();
+    lists.unlink(index);
+    lists.push_front(index, true);
+    lists.demote_overflow();
+  }
+
+  fn candidate(&mut self) -> Option<&K> {
+    if self.mapping.len() < self.capacity {
+      return None;
+    }
+    let index = self.lists().lru();
+    self.mapping.get(&index)
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    if self.mapping.is_empty() {
+      return None;
+    }
+    let index = self.lists().lru();
+    self.lists().unlink(index);
+    self.free.push(index);
+    self.mapping.remove(&index)
+  }
+
+  fn remove(&mut self, index: usize) -> Option<K> {
+    let key = self.mapping.remove(&index)?;
+    self.lists().unlink(index);
+    self.free.push(index);
+    Some(key)
+  }
+
+  fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  fn resize(&mut self, capacity: usize) -> Resized<K> {
+    let mut evicted = Vec::new();
+    while self.mapping.len() > capacity {
+      let index = self.lists().lru();
+      self.lists().unlink(index);
+      evicted.extend(self.mapping.remove(&index));
+    }
+    let relocated = relocate(&mut self.mapping, capacity);
+    let protected_percent = self.protected_percent;
+    let lists = self.lists();
+    for &(from, to) in relocated.iter() {
+      lists.relink(from, to);
+    }
+    lists.links.resize(capacity, Link::default());
+    lists.protected_capacity = protected_capacity(capacity, protected_percent);
+    lists.demote_overflow();
+    self.free = free_slots(&self.mapping, capacity);
+    self.capacity = capacity;
+    Resized { evicted, relocated }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SlruEvictor;
+  use crate::eviction::{Evictor, Resized};
+
+  #[test]
+  fn evicts_probation_lru() {
+    let mut evictor = SlruEvictor::new(3, 50);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(evictor.candidate(), Some(&"1"));
+    evictor.touch(0);
+    assert_eq!(evictor.add("4"), (1, Some("2")));
+    assert_eq!(evictor.add("5"), (2, Some("3")));
+    assert_eq!(evictor.add("6"), (1, Some("4")));
+  }
+
+  #[test]
+  fn demotes_protected_lru() {
+    let mut evictor = SlruEvictor::new(3, 50);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    evictor.touch(0);
+    evictor.touch(1); // protects "2", demoting "1" back to the head of probation
+    assert_eq!(evictor.add("4"), (2, Some("3")));
+    assert_eq!(evictor.add("5"), (0, Some("1")));
+  }
+
+  #[test]
+  fn hit_key_survives_scan() {
+    let mut evictor = SlruEvictor::new(4, 80);
+    assert_eq!(evictor.add(0), (0, None));
+    for key in 1..1_000 {
+      evictor.touch(0);
+      let (index, _) = evictor.add(key);
+      assert_ne!(index, 0);
+    }
+    assert_eq!(evictor.remove(0), Some(0));
+  }
+
+  #[test]
+  fn resize_keeps_order() {
+    let mut evictor = SlruEvictor::new(4, 50);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.add("3"), (2, None));
+    assert_eq!(evictor.add("4"), (3, None));
+    evictor.touch(2);
+    evictor.touch(3);
+    assert_eq!(
+      evictor.resize(2),
+      Resized {
+        evicted: vec!["1", "2"],
+        relocated: vec![(2, 0), (3, 1)],
+      }
+    );
+    // only one protected slot is left, for 4, while 3 got demoted to probation
+    assert_eq!(evictor.add("5"), (0, Some("3")));
+    assert_eq!(evictor.add("6"), (0, Some("5")));
+
+    evictor.resize(3);
+    assert_eq!(evictor.add("7"), (2, None));
+    assert_eq!(evictor.add("8"), (0, Some("6")));
+  }
+
+  #[test]
+  fn removed_slots_get_reused() {
+    let mut evictor = SlruEvictor::new(2, 50);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    evictor.touch(0);
+    assert_eq!(evictor.remove(0), Some("1"));
+    assert_eq!(evictor.candidate(), None);
+    assert_eq!(evictor.add("3"), (0, None));
+    assert_eq!(evictor.add("4"), (1, Some("2")));
+  }
+}
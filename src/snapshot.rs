@@ -0,0 +1,84 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+/// The entries of a cache, as taken by `CacheThrough::snapshot`, in a form that can be serialized,
+/// e.g. to persist them across restarts and `restore` them then. A snapshot serializes as the
+/// sequence of its `(key, value)` pairs.
+///
+/// ```
+/// use cachers::{CacheSnapshot, CacheThrough};
+///
+/// let cache = CacheThrough::<usize, String>::new(100);
+/// cache.get(&42, |key| Some(key.to_string()));
+/// let persisted = bincode::serialize(&CacheSnapshot::from(cache.snapshot())).unwrap();
+///
+/// let snapshot: CacheSnapshot<usize, String> = bincode::deserialize(&persisted).unwrap();
+/// let restarted = CacheThrough::<usize, String>::new(100);
+/// restarted.restore(snapshot.into_entries());
+/// assert_eq!(*restarted.get(&42, |_| unreachable!()).unwrap(), "42");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CacheSnapshot<K, V> {
+  entries: Vec<(K, V)>,
+}
+
+impl<K, V> CacheSnapshot<K, V> {
+  /// The `(key, value)` pairs of the snapshot, in no particular order.
+  pub fn entries(&self) -> &[(K, V)] {
+    &self.entries
+  }
+
+  /// The `(key, value)` pairs of the snapshot, ready to `restore` a cache with.
+  pub fn into_entries(self) -> Vec<(K, V)> {
+    self.entries
+  }
+}
+
+impl<K, V> From<Vec<(K, V)>> for CacheSnapshot<K, V> {
+  fn from(entries: Vec<(K, V)>) -> CacheSnapshot<K, V> {
+    CacheSnapshot { entries }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CacheSnapshot;
+  use crate::CacheThrough;
+
+  #[test]
+  fn round_trips_through_bincode() {
+    let cache: CacheThrough<i32, String> = CacheThrough::new(3);
+    for key in 1..=3 {
+      cache.get(&key, |key| Some(key.to_string()));
+    }
+    let mut entries = cache.snapshot();
+    entries.sort();
+    let snapshot = CacheSnapshot::from(entries);
+
+    let bytes = bincode::serialize(&snapshot).unwrap();
+    let deserialized: CacheSnapshot<i32, String> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(deserialized, snapshot);
+
+    let restored: CacheThrough<i32, String> = CacheThrough::new(3);
+    restored.restore(deserialized.into_entries());
+    for key in 1..=3 {
+      assert_eq!(*restored.get(&key, |_| None).unwrap(), key.to_string());
+    }
+  }
+}
@@ -0,0 +1,180 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::channel::oneshot;
+
+/// A soft lock on a key, held by the one caller populating its entry. Other callers interested in
+/// the same key register as waiters and get handed the value once the lock is released.
+pub struct Lock<V> {
+  id: u64,
+  waiters: Vec<oneshot::Sender<Result<Option<V>, WaitError>>>,
+}
+
+/// Why a `Waiter` didn't get handed a value.
+#[derive(Debug)]
+pub enum WaitError {
+  /// The lock was dropped without being released, i.e. the populating caller gave up.
+  Abandoned,
+  /// The populating caller failed, and shared its error with the waiters.
+  Failed(Failure),
+}
+
+/// An error shared by the populating caller with the waiters, type-erased as each waiter may
+/// expect a different error type.
+#[derive(Clone)]
+pub struct Failure(Arc<dyn Any + Send + Sync>);
+
+/// Resolves to the value the lock was released with.
+pub struct Waiter<V> {
+  receiver: oneshot::Receiver<Result<Option<V>, WaitError>>,
+  lock: u64,
+}
+
+impl<V> Lock<V>
+where
+  V: Clone,
+{
+  pub fn new(id: u64) -> Lock<V> {
+    Lock {
+      id,
+      waiters: Vec::new(),
+    }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn wait(&mut self) -> Waiter<V> {
+    let (sender, receiver) = oneshot::channel();
+    self.waiters.push(sender);
+    Waiter {
+      receiver,
+      lock: self.id,
+    }
+  }
+
+  pub fn release(self, value: Option<V>) {
+    for waiter in self.waiters {
+      // the waiter may have stopped waiting, that's fine
+      let _ = waiter.send(Ok(value.clone()));
+    }
+  }
+
+  /// Hands the `failure` to all waiters, instead of a value.
+  pub fn fail(self, failure: Failure) {
+    for waiter in self.waiters {
+      let _ = waiter.send(Err(WaitError::Failed(failure.clone())));
+    }
+  }
+}
+
+impl Failure {
+  pub fn new<E>(error: E) -> Failure
+  where
+    E: Send + Sync + 'static,
+  {
+    Failure(Arc::new(error))
+  }
+
+  /// The error the failure was created with, should it be an `E`.
+  pub fn downcast<E>(&self) -> Option<E>
+  where
+    E: Clone + 'static,
+  {
+    self.0.downcast_ref::<E>().cloned()
+  }
+}
+
+impl fmt::Debug for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Failure").finish()
+  }
+}
+
+impl<V> Waiter<V> {
+  /// The id of the lock this waits on.
+  pub fn lock(&self) -> u64 {
+    self.lock
+  }
+}
+
+impl<V> Future for Waiter<V> {
+  type Output = Result<Option<V>, WaitError>;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    Pin::new(&mut self.receiver)
+      .poll(cx)
+      .map(|result| result.unwrap_or(Err(WaitError::Abandoned)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Failure, Lock, WaitError};
+
+  #[tokio::test]
+  async fn waiters_get_released_value() {
+    let mut lock = Lock::new(1);
+    let first = lock.wait();
+    let second = lock.wait();
+    lock.release(Some("42"));
+    assert_eq!(first.await.unwrap(), Some("42"));
+    assert_eq!(second.await.unwrap(), Some("42"));
+  }
+
+  #[tokio::test]
+  async fn waiters_pend_until_released() {
+    let mut lock = Lock::new(1);
+    let mut waiter = lock.wait();
+    assert!(futures::poll!(&mut waiter).is_pending());
+    lock.release(Some("42"));
+    assert_eq!(waiter.await.unwrap(), Some("42"));
+  }
+
+  #[test]
+  fn waiters_know_their_lock() {
+    let mut lock: Lock<&str> = Lock::new(42);
+    assert_eq!(lock.wait().lock(), 42);
+  }
+
+  #[tokio::test]
+  async fn waiters_notified_of_abandon() {
+    let mut lock: Lock<&str> = Lock::new(1);
+    let waiter = lock.wait();
+    drop(lock);
+    assert!(matches!(waiter.await, Err(WaitError::Abandoned)));
+  }
+
+  #[tokio::test]
+  async fn waiters_get_failure() {
+    let mut lock: Lock<&str> = Lock::new(1);
+    let waiter = lock.wait();
+    lock.fail(Failure::new("boom"));
+    match waiter.await {
+      Err(WaitError::Failed(failure)) => {
+        assert_eq!(failure.downcast::<&str>(), Some("boom"));
+        assert_eq!(failure.downcast::<String>(), None);
+      }
+      _ => panic!("Waiter should have been handed the failure!"),
+    }
+  }
+}
@@ -0,0 +1,27 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Where the value a `CacheThrough::get_reporting` returned came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+  /// The cache already held the entry.
+  Hit,
+  /// The entry was missing, and got populated by this very call.
+  Loaded,
+  /// The entry was missing, but another thread populated it while this call waited for it to.
+  Coalesced,
+  /// The entry was missing, but as many loads as the cache's `max_concurrent_loads` were in
+  /// flight already, so that it wasn't populated, see `CacheThroughBuilder::reject_excess_loads`.
+  Throttled,
+}
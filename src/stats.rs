@@ -0,0 +1,175 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+#[cfg(any(feature = "prometheus-text", feature = "metrics"))]
+use alloc::format;
+#[cfg(feature = "prometheus-text")]
+use alloc::string::String;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+
+#[cfg(feature = "metrics")]
+use metrics::SharedString;
+
+use crate::platform::Instant;
+
+/// Invoked with how long each invocation of a `populating_fn` took.
+pub type LoadListener = Box<dyn Fn(Duration) + Send + Sync>;
+
+#[derive(Default)]
+pub struct Stats {
+  hits: AtomicU64,
+  misses: AtomicU64,
+  coalesced_loads: AtomicU64,
+  on_load: Option<LoadListener>,
+  #[cfg(feature = "metrics")]
+  names: Option<MetricNames>,
+}
+
+/// The names of the metrics a cache reports through the `metrics` facade, all sharing a prefix.
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+pub struct MetricNames {
+  hits: SharedString,
+  misses: SharedString,
+  coalesced_loads: SharedString,
+  updates: SharedString,
+  pub evictions: SharedString,
+  load_seconds: SharedString,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricNames {
+  pub fn new(prefix: &str) -> MetricNames {
+    let name = |suffix: &str| SharedString::from(Arc::<str>::from(format!("{}_{}", prefix, suffix)));
+    MetricNames {
+      hits: name("hits"),
+      misses: name("misses"),
+      coalesced_loads: name("coalesced_loads"),
+      updates: name("updates"),
+      evictions: name("evictions"),
+      load_seconds: name("load_seconds"),
+    }
+  }
+}
+
+impl Stats {
+  /// Also reports to the `metrics` facade, under the given `names`.
+  #[cfg(feature = "metrics")]
+  pub fn report_to(&mut self, names: MetricNames) {
+    self.names = Some(names);
+  }
+
+  pub fn on_load(&mut self, listener: LoadListener) {
+    self.on_load = Some(listener);
+  }
+
+  pub fn hit(&self) {
+    self.hits.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.names {
+      metrics::counter!(names.hits.clone()).increment(1);
+    }
+  }
+
+  pub fn miss(&self) {
+    self.misses.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.names {
+      metrics::counter!(names.misses.clone()).increment(1);
+    }
+  }
+
+  pub fn coalesced(&self) {
+    self.coalesced_loads.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.names {
+      metrics::counter!(names.coalesced_loads.clone()).increment(1);
+    }
+  }
+
+  #[cfg_attr(not(feature = "metrics"), inline(always))]
+  pub fn update(&self) {
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.names {
+      metrics::counter!(names.updates.clone()).increment(1);
+    }
+  }
+
+  /// Invokes `load`, recording how long it took. It's only timed if anyone is interested.
+  pub fn time_load<T, F: FnOnce() -> T>(&self, load: F) -> T {
+    #[cfg(feature = "metrics")]
+    let timed = self.on_load.is_some() || self.names.is_some();
+    #[cfg(not(feature = "metrics"))]
+    let timed = self.on_load.is_some();
+    if !timed {
+      return load();
+    }
+    let start = Instant::now();
+    let loaded = load();
+    let elapsed = start.elapsed();
+    if let Some(listener) = &self.on_load {
+      listener(elapsed);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(names) = &self.names {
+      metrics::histogram!(names.load_seconds.clone()).record(elapsed);
+    }
+    loaded
+  }
+
+  #[cfg(feature = "prometheus-text")]
+  pub fn hits(&self) -> u64 {
+    self.hits.load(Ordering::Relaxed)
+  }
+
+  #[cfg(feature = "prometheus-text")]
+  pub fn misses(&self) -> u64 {
+    self.misses.load(Ordering::Relaxed)
+  }
+
+  pub fn coalesced_loads(&self) -> u64 {
+    self.coalesced_loads.load(Ordering::Relaxed)
+  }
+}
+
+#[cfg(feature = "prometheus-text")]
+pub struct Exposition {
+  text: String,
+}
+
+#[cfg(feature = "prometheus-text")]
+impl Exposition {
+  pub fn new() -> Exposition {
+    Exposition { text: String::new() }
+  }
+
+  pub fn counter(&mut self, name: &str, value: u64) -> &mut Exposition {
+    self.text += &format!("# TYPE {} counter\n{}_total {}\n", name, name, value);
+    self
+  }
+
+  pub fn gauge(&mut self, name: &str, value: u64) -> &mut Exposition {
+    self.text += &format!("# TYPE {} gauge\n{} {}\n", name, name, value);
+    self
+  }
+
+  pub fn finish(&mut self) -> String {
+    self.text += "# EOF\n";
+    core::mem::take(&mut self.text)
+  }
+}
@@ -0,0 +1,397 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+use crate::eviction::{free_slots, relocate, Evictor, Resized};
+use crate::platform::{DefaultHasher, HashMap, Mutex};
+
+const NIL: usize = usize::MAX;
+
+/// 2Q: new keys enter `A1in`, a FIFO queue taking a quarter of the capacity, and leave it in the
+/// order they entered it, however often they get hit meanwhile. The keys evicted from it are
+/// remembered in `A1out`, a ghost queue holding their hashes only, so that the ones added again
+/// soon after are deemed hot and go to `Am`, an LRU queue taking the rest of the capacity. A scan
+/// of one-shot keys only ever goes through `A1in`, leaving the hot keys of `Am` alone.
+pub struct TwoQueueEvictor<K> {
+  capacity: usize,
+  mapping: HashMap<usize, K>,
+  free: Vec<usize>,
+  queues: Mutex<Queues>,
+  ghosts: Ghosts,
+}
+
+/// `A1in` & `Am`, as doubly linked lists threaded through the slots, most recent first.
+struct Queues {
+  links: Vec<Link>,
+  a1in: Ends,
+  am: Ends,
+  a1in_len: usize,
+  a1in_capacity: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Link {
+  prev: usize,
+  next: usize,
+  hot: bool,
+}
+
+impl Default for Link {
+  fn default() -> Link {
+    Link {
+      prev: NIL,
+      next: NIL,
+      hot: false,
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+struct Ends {
+  head: usize,
+  tail: usize,
+}
+
+/// `A1out`: the hashes of the keys last evicted from `A1in`, oldest first. A hash leaves it once
+/// its key gets added again, which only forgets about it in `latest`, so that `order` can hold
+/// stale hashes, skipped as they get trimmed.
+struct Ghosts {
+  capacity: usize,
+  order: VecDeque<(u64, u64)>,
+  latest: HashMap<u64, u64>,
+  pushed: u64,
+}
+
+fn a1in_capacity(capacity: usize) -> usize {
+  (capacity / 4).max(1)
+}
+
+fn ghosts_capacity(capacity: usize) -> usize {
+  (capacity / 2).max(1)
+}
+
+fn hash<K: Hash>(key: &K) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  key.hash(&mut hasher);
+  hasher.finish()
+}
+
+impl<K> TwoQueueEvictor<K> {
+  /// Creates an evictor for `capacity` keys, a quarter of which are held in `A1in`, while `A1out`
+  /// remembers as many evicted keys as half the capacity.
+  pub fn new(capacity: usize) -> TwoQueueEvictor<K> {
+    TwoQueueEvictor {
+      capacity,
+      mapping: HashMap::with_capacity(capacity),
+      free: Vec::new(),
+      queues: Mutex::new(Queues {
+        links: vec![Link::default(); capacity],
+        a1in: Ends { head: NIL, tail: NIL },
+        am: Ends { head: NIL, tail: NIL },
+        a1in_len: 0,
+        a1in_capacity: a1in_capacity(capacity),
+      }),
+      ghosts: Ghosts {
+        capacity: ghosts_capacity(capacity),
+        order: VecDeque::new(),
+        latest: HashMap::new(),
+        pushed: 0,
+      },
+    }
+  }
+
+  fn queues(&mut self) -> &mut Queues {
+    self.queues.get_mut()
+  }
+}
+
+impl<K: Hash> TwoQueueEvictor<K> {
+  /// Takes the victim out of its queue, remembering it in `A1out` should it come from `A1in`.
+  fn victim(&mut self) -> (usize, Option<K>) {
+    let queues = self.queues.get_mut();
+    let index = queues.victim();
+    let hot = queues.links[index].hot;
+    queues.unlink(index);
+    let key = self.mapping.remove(&index);
+    if let (false, Some(key)) = (hot, &key) {
+      self.ghosts.push(hash(key));
+    }
+    (index, key)
+  }
+}
+
+impl Queues {
+  fn ends(&mut self, hot: bool) -> &mut Ends {
+    if hot {
+      &mut self.am
+    } else {
+      &mut self.a1in
+    }
+  }
+
+  fn unlink(&mut self, index: usize) {
+    let Link { prev, next, hot } = self.links[index];
+    match prev {
+      NIL => self.ends(hot).head = next,
+      prev => self.links[prev].next = next,
+    }
+    match next {
+      NIL => self.ends(hot).tail = prev,
+      next => self.links[next].prev = prev,
+    }
+    if !hot {
+      self.a1in_len -= 1;
+    }
+  }
+
+  fn push_front(&mut self, index: usize, hot: bool) {
+    let head = self.ends(hot).head;
+    self.links[index] = Link {
+      prev: NIL,
+      next: head,
+      hot,
+    };
+    match head {
+      NIL => self.ends(hot).tail = index,
+      head => self.links[head].prev = index,
+    }
+    self.ends(hot).head = index;
+    if !hot {
+      self.a1in_len += 1;
+    }
+  }
+
+  /// Moves the link at `from` to the slot `to`, repointing its neighbours at it.
+  fn relink(&mut self, from: usize, to: usize) {
+    let link = self.links[from];
+    self.links[to] = link;
+    match link.prev {
+      NIL => self.ends(link.hot).head = to,
+      prev => self.links[prev].next = to,
+    }
+    match link.next {
+      NIL => self.ends(link.hot).tail = to,
+      next => self.links[next].prev = to,
+    }
+  }
+
+  /// The oldest slot of `A1in` should it exceed its capacity, or `Am` be empty, the least recently
+  /// used one of `Am` otherwise.
+  fn victim(&self) -> usize {
+    if self.a1in_len > self.a1in_capacity || self.am.tail == NIL {
+      self.a1in.tail
+    } else {
+      self.am.tail
+    }
+  }
+}
+
+impl Ghosts {
+  fn push(&mut self, hash: u64) {
+    self.pushed += 1;
+    self.latest.insert(hash, self.pushed);
+    self.order.push_back((hash, self.pushed));
+    self.trim();
+  }
+
+  fn trim(&mut self) {
+    while self.order.len() > self.capacity {
+      if let Some((hash, pushed)) = self.order.pop_front() {
+        if self.latest.get(&hash) == Some(&pushed) {
+          self.latest.remove(&hash);
+        }
+      }
+    }
+  }
+
+  /// Whether `hash` is remembered, forgetting about it.
+  fn take(&mut self, hash: u64) -> bool {
+    self.latest.remove(&hash).is_some()
+  }
+}
+
+impl<K: Hash> Evictor<K> for TwoQueueEvictor<K> {
+  fn add(&mut self, key: K) -> (usize, Option<K>) {
+    let hot = self.ghosts.take(hash(&key));
+    let (index, victim) = if self.mapping.len() < self.capacity {
+      (self.free.pop().unwrap_or(self.mapping.len()), None)
+    } else {
+      self.victim()
+    };
+    self.mapping.insert(index, key);
+    self.queues().push_front(index, hot);
+    (index, victim)
+  }
+
+  fn touch(&self, index: usize) {
+    let mut queues = self.queues.lock();
+    // hits in `A1in` don't reorder it, as a key only hit shortly after it was added isn't hot yet
+    if queues.links[index].hot {
+      queues.unlink(index);
+      queues.push_front(index, true);
+    }
+  }
+
+  fn candidate(&mut self) -> Option<&K> {
+    if self.mapping.len() < self.capacity {
+      return None;
+    }
+    let index = self.queues().victim();
+    self.mapping.get(&index)
+  }
+
+  fn evict(&mut self) -> Option<K> {
+    if self.mapping.is_empty() {
+      return None;
+    }
+    let (index, victim) = self.victim();
+    self.free.push(index);
+    victim
+  }
+
+  fn remove(&mut self, index: usize) -> Option<K> {
+    let key = self.mapping.remove(&index)?;
+    self.queues().unlink(index);
+    self.free.push(index);
+    Some(key)
+  }
+
+  fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  fn resize(&mut self, capacity: usize) -> Resized<K> {
+    let mut evicted = Vec::new();
+    while self.mapping.len() > capacity {
+      evicted.extend(self.victim().1);
+    }
+    let relocated = relocate(&mut self.mapping, capacity);
+    let queues = self.queues();
+    for &(from, to) in relocated.iter() {
+      queues.relink(from, to);
+    }
+    queues.links.resize(capacity, Link::default());
+    queues.a1in_capacity = a1in_capacity(capacity);
+    self.ghosts.capacity = ghosts_capacity(capacity);
+    self.ghosts.trim();
+    self.free = free_slots(&self.mapping, capacity);
+    self.capacity = capacity;
+    Resized { evicted, relocated }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TwoQueueEvictor;
+  use crate::eviction::{ClockEvictor, Evictor, Resized};
+
+  #[test]
+  fn evicts_a1in_in_insertion_order() {
+    let mut evictor = TwoQueueEvictor::new(4);
+    for key in 0..4 {
+      assert_eq!(evictor.add(key), (key as usize, None));
+    }
+    evictor.touch(0);
+    assert_eq!(evictor.candidate(), Some(&0));
+    assert_eq!(evictor.add(4), (0, Some(0)));
+    assert_eq!(evictor.add(5), (1, Some(1)));
+  }
+
+  #[test]
+  fn ghost_hits_go_to_am() {
+    let mut evictor = TwoQueueEvictor::new(4);
+    for key in 0..4 {
+      evictor.add(key);
+    }
+    assert_eq!(evictor.add(4), (0, Some(0)));
+    // 0 is remembered in A1out, so that it is deemed hot when added again
+    assert_eq!(evictor.add(0), (1, Some(1)));
+    for key in 5..8 {
+      let (index, _) = evictor.add(key);
+      assert_ne!(index, 1);
+    }
+    assert_eq!(evictor.remove(1), Some(0));
+  }
+
+  #[test]
+  fn hot_key_survives_scan_unlike_with_lru() {
+    let mut two_queue = TwoQueueEvictor::new(4);
+    let mut clock = ClockEvictor::new(4);
+    for key in 0..4 {
+      two_queue.add(key);
+      clock.add(key);
+    }
+    // 0 gets evicted, then requested again
+    assert_eq!(two_queue.add(4), (0, Some(0)));
+    assert_eq!(clock.add(4), (0, Some(0)));
+    let (hot, _) = two_queue.add(0);
+    let (lru, _) = clock.add(0);
+
+    for key in 100..1_000 {
+      two_queue.add(key);
+      clock.add(key);
+    }
+    assert_eq!(two_queue.remove(hot), Some(0));
+    assert_ne!(clock.remove(lru), Some(0));
+  }
+
+  #[test]
+  fn forgets_ghosts_beyond_half_the_capacity() {
+    let mut evictor = TwoQueueEvictor::new(4);
+    for key in 0..7 {
+      evictor.add(key);
+    }
+    // 0, 1 & 2 got evicted, only 1 & 2 are remembered
+    assert_eq!(evictor.ghosts.latest.len(), 2);
+    let (cold, _) = evictor.add(0);
+    let (hot, _) = evictor.add(2);
+    assert!(!evictor.queues().links[cold].hot);
+    assert!(evictor.queues().links[hot].hot);
+  }
+
+  #[test]
+  fn resize_keeps_order() {
+    let mut evictor = TwoQueueEvictor::new(4);
+    for key in 0..4 {
+      evictor.add(key);
+    }
+    assert_eq!(
+      evictor.resize(2),
+      Resized {
+        evicted: vec![0, 1],
+        relocated: vec![(2, 0), (3, 1)],
+      }
+    );
+    assert_eq!(evictor.add(4), (0, Some(2)));
+    assert_eq!(evictor.add(5), (1, Some(3)));
+  }
+
+  #[test]
+  fn removed_slots_get_reused() {
+    let mut evictor = TwoQueueEvictor::new(2);
+    assert_eq!(evictor.add("1"), (0, None));
+    assert_eq!(evictor.add("2"), (1, None));
+    assert_eq!(evictor.remove(0), Some("1"));
+    assert_eq!(evictor.candidate(), None);
+    assert_eq!(evictor.add("3"), (0, None));
+    assert_eq!(evictor.add("4"), (1, Some("2")));
+    assert_eq!(evictor.evict(), Some("3"));
+    assert_eq!(evictor.evict(), Some("4"));
+    assert_eq!(evictor.evict(), None);
+  }
+}
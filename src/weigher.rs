@@ -0,0 +1,47 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+
+/// Weighs cache entries, e.g. by the number of bytes they hold, so that a cache can be capped by
+/// their total weight rather than by their number, see `CacheThroughBuilder::weigher`.
+///
+/// The weight of an entry is taken when it is populated or updated, so that it needs to be the
+/// same for as long as the value isn't. Any closure `Fn(&K, &V) -> usize` is a `Weigher`.
+pub trait Weigher<K, V> {
+  /// The weight of the entry mapping `key` to `value`.
+  fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+  F: Fn(&K, &V) -> usize,
+{
+  fn weigh(&self, key: &K, value: &V) -> usize {
+    self(key, value)
+  }
+}
+
+/// Weighs every entry `1`, which caps a cache by the number of entries it holds, as its capacity
+/// does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unweighted;
+
+impl<K, V> Weigher<K, V> for Unweighted {
+  fn weigh(&self, _key: &K, _value: &V) -> usize {
+    1
+  }
+}
+
+pub type BoxedWeigher<K, V> = Box<dyn Weigher<K, V> + Send + Sync>;
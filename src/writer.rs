@@ -0,0 +1,32 @@
+// Copyright 2018 Alex Snaps
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+
+use crate::error::WriteError;
+
+/// A backing store a cache writes through to, so that every `update` or `remove` also gets
+/// persisted, see `CacheThroughBuilder::writer`. It complements a `Loader`, which reads from such
+/// a store on misses.
+///
+/// The writer is invoked while holding the cache's write lock, before the cache itself gets
+/// altered: should it fail, the mapping is left untouched.
+pub trait Writer<K, V> {
+  /// Persists `value` as the one `key` maps to.
+  fn write(&self, key: &K, value: &V) -> Result<(), WriteError>;
+  /// Persists the removal of `key`.
+  fn delete(&self, key: &K) -> Result<(), WriteError>;
+}
+
+pub type BoxedWriter<K, V> = Box<dyn Writer<K, V> + Send + Sync>;